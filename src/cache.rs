@@ -0,0 +1,258 @@
+//! caches evicting least recently used entries
+
+use std::{
+    collections::{
+        hash_map::DefaultHasher,
+        BTreeMap,
+        HashMap,
+    },
+    hash::{
+        Hash,
+        Hasher,
+    },
+    sync::{
+        atomic::{
+            AtomicU64,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+
+use crate::sorted_stable::Block;
+
+const NUM_SHARDS: usize = 16;
+
+/// a cache of bounded total charge, split into shards locked separately
+pub struct ShardedLruCache<K, V> {
+    shards: Vec<Mutex<LruShard<K, V>>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ShardedLruCache<K, V> {
+    /// create a cache holding entries of total charge up to `capacity`
+    pub fn new(capacity: usize) -> Self {
+        // a small capacity is split to fewer shards so that every shard can hold an entry
+        let num_shards = NUM_SHARDS.min(capacity).max(1);
+        ShardedLruCache {
+            shards: (0..num_shards)
+                .map(|i| {
+                    let extra = (i < capacity % num_shards) as usize;
+                    Mutex::new(LruShard::new(capacity / num_shards + extra))
+                })
+                .collect(),
+        }
+    }
+
+    /// get value of `key` and mark it as most recently used
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.shard(key).lock().unwrap().get(key)
+    }
+
+    /// insert `value` taking `charge` of capacity, evicting least recently used entries until
+    /// the total charge fits in capacity
+    pub fn insert(&self, key: K, value: V, charge: usize) {
+        self.shard(&key).lock().unwrap().insert(key, value, charge)
+    }
+
+    /// remove entry of `key`
+    pub fn erase(&self, key: &K) {
+        self.shard(key).lock().unwrap().erase(key)
+    }
+
+    /// total charge of all entries
+    pub fn total_charge(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().usage)
+            .sum()
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<LruShard<K, V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+}
+
+struct LruShard<K, V> {
+    capacity: usize,
+    usage:    usize,
+    entries:  HashMap<K, LruEntry<V>>,
+    // keys by the tick of their last use, the least recently used first
+    order:    BTreeMap<u64, K>,
+    tick:     u64,
+}
+
+struct LruEntry<V> {
+    value:  V,
+    charge: usize,
+    tick:   u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> LruShard<K, V> {
+    fn new(capacity: usize) -> Self {
+        LruShard {
+            capacity,
+            usage: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.tick);
+        self.tick += 1;
+        entry.tick = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(entry.value.clone())
+    }
+
+    fn insert(&mut self, key: K, value: V, charge: usize) {
+        self.erase(&key);
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            LruEntry {
+                value,
+                charge,
+                tick: self.tick,
+            },
+        );
+        self.usage += charge;
+        while self.usage > self.capacity {
+            let (_, key) = match self.order.pop_first() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            let entry = self.entries.remove(&key).unwrap();
+            self.usage -= entry.charge;
+        }
+    }
+
+    fn erase(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.tick);
+            self.usage -= entry.charge;
+        }
+    }
+}
+
+/// a cache of table blocks bounded in bytes, which may be shared by several dbs through
+/// [`Options::block_cache`](crate::Options::block_cache)
+#[derive(Clone)]
+pub struct BlockCache {
+    inner: Arc<BlockCacheInner>,
+    // tells tables of different dbs apart, each db opened with the cache uses a clone with a new
+    // id
+    id:    u64,
+}
+
+struct BlockCacheInner {
+    cache:   ShardedLruCache<(u64, u64, u64), Block>,
+    next_id: AtomicU64,
+}
+
+impl BlockCache {
+    /// create a cache holding blocks of up to `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        BlockCache {
+            inner: Arc::new(BlockCacheInner {
+                cache:   ShardedLruCache::new(capacity),
+                next_id: AtomicU64::new(1),
+            }),
+            id:    0,
+        }
+    }
+
+    /// bytes of blocks in cache
+    pub fn usage(&self) -> usize {
+        self.inner.cache.total_charge()
+    }
+
+    /// a handle sharing blocks with this one, whose keys are apart from those of other handles
+    pub(crate) fn with_new_id(&self) -> Self {
+        BlockCache {
+            inner: self.inner.clone(),
+            id:    self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// cached block at `offset` of table file `number`
+    pub(crate) fn get(&self, number: u64, offset: u64) -> Option<Block> {
+        self.inner.cache.get(&(self.id, number, offset))
+    }
+
+    pub(crate) fn insert(&self, number: u64, offset: u64, block: Block) {
+        let charge = block.size();
+        self.inner
+            .cache
+            .insert((self.id, number, offset), block, charge)
+    }
+}
+
+impl std::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("id", &self.id)
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_shard() {
+        let mut shard = LruShard::new(10);
+        shard.insert(1, "a", 4);
+        shard.insert(2, "b", 4);
+        assert_eq!(shard.get(&1), Some("a"));
+        // 2 is the least recently used
+        shard.insert(3, "c", 4);
+        assert_eq!(shard.get(&2), None);
+        assert_eq!(shard.get(&1), Some("a"));
+        assert_eq!(shard.get(&3), Some("c"));
+        assert_eq!(shard.usage, 8);
+
+        // replacing an entry updates its charge
+        shard.insert(3, "cc", 6);
+        assert_eq!(shard.usage, 10);
+        assert_eq!(shard.get(&3), Some("cc"));
+        shard.erase(&1);
+        assert_eq!(shard.get(&1), None);
+        assert_eq!(shard.usage, 6);
+
+        // an entry larger than capacity is not kept
+        shard.insert(4, "d", 11);
+        assert_eq!(shard.usage, 0);
+        assert!(shard.entries.is_empty() && shard.order.is_empty());
+    }
+
+    #[test]
+    fn test_sharded_lru_cache() {
+        let cache = ShardedLruCache::new(NUM_SHARDS * 100);
+        for i in 0..10000 {
+            cache.insert(i, i * 2, 1);
+            assert_eq!(cache.get(&i), Some(i * 2));
+        }
+        assert!(cache.total_charge() <= NUM_SHARDS * 100);
+        // recently inserted entries are kept
+        assert_eq!(cache.get(&9999), Some(9999 * 2));
+        assert_eq!(cache.get(&0), None);
+        cache.erase(&9999);
+        assert_eq!(cache.get(&9999), None);
+
+        // capacity is a bound even if it's less than number of shards
+        let cache = ShardedLruCache::new(3);
+        for i in 0..100 {
+            cache.insert(i, i, 1);
+        }
+        assert_eq!(cache.total_charge(), 3);
+    }
+}
@@ -0,0 +1,136 @@
+//! key orderings
+
+use std::cmp::Ordering;
+
+use crate::format::{
+    compare_internal_key,
+    extract_user_key,
+    InternalKey,
+    ValueType,
+    MAX_SEQUENCE_NUMBER,
+};
+
+/// total order of keys stored in sstables
+pub trait Comparator: Send + Sync {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// a short key in `[start, limit)`, used as an index key separating two blocks
+    fn find_shortest_separator(&self, start: &[u8], limit: &[u8]) -> Vec<u8>;
+
+    /// a short key at or after `key`, used as the index key of the last block
+    fn find_short_successor(&self, key: &[u8]) -> Vec<u8>;
+}
+
+/// order keys by bytes lexicographically
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn find_shortest_separator(&self, start: &[u8], limit: &[u8]) -> Vec<u8> {
+        let diff_index = start
+            .iter()
+            .zip(limit.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if diff_index < start.len().min(limit.len()) {
+            let diff_byte = start[diff_index];
+            if diff_byte < 0xff && diff_byte + 1 < limit[diff_index] {
+                let mut separator = start[..=diff_index].to_vec();
+                separator[diff_index] += 1;
+                return separator;
+            }
+        }
+        // one key is a prefix of the other, or there is no room between them
+        start.to_vec()
+    }
+
+    fn find_short_successor(&self, key: &[u8]) -> Vec<u8> {
+        match key.iter().position(|&b| b != 0xff) {
+            Some(i) => {
+                let mut successor = key[..=i].to_vec();
+                successor[i] += 1;
+                successor
+            }
+            None => key.to_vec(),
+        }
+    }
+}
+
+/// order encoded internal keys, see [`InternalKey`] for the order
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InternalKeyComparator;
+
+impl Comparator for InternalKeyComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        compare_internal_key(a, b)
+    }
+
+    fn find_shortest_separator(&self, start: &[u8], limit: &[u8]) -> Vec<u8> {
+        let user_start = extract_user_key(start);
+        let user_limit = extract_user_key(limit);
+        let separator = BytewiseComparator.find_shortest_separator(user_start, user_limit);
+        self.shortened_or(start, user_start, &separator)
+    }
+
+    fn find_short_successor(&self, key: &[u8]) -> Vec<u8> {
+        let user_key = extract_user_key(key);
+        let successor = BytewiseComparator.find_short_successor(user_key);
+        self.shortened_or(key, user_key, &successor)
+    }
+}
+
+impl InternalKeyComparator {
+    /// use `shortened` user key if it's really shorter and larger than `user_key`, paired with
+    /// the largest trailer so it sorts before every entry of that user key
+    fn shortened_or(&self, key: &[u8], user_key: &[u8], shortened: &[u8]) -> Vec<u8> {
+        if shortened.len() < user_key.len() && user_key < shortened {
+            let shortened = InternalKey::new(shortened, MAX_SEQUENCE_NUMBER, ValueType::FOR_SEEK);
+            return shortened.encoded().to_vec();
+        }
+        key.to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bytewise_separator() {
+        let cmp = BytewiseComparator;
+        assert_eq!(cmp.find_shortest_separator(b"abcd", b"abzz"), b"abd");
+        assert_eq!(cmp.find_shortest_separator(b"abc", b"abd"), b"abc");
+        assert_eq!(cmp.find_shortest_separator(b"abc", b"abcd"), b"abc");
+        assert_eq!(cmp.find_shortest_separator(b"a\xff", b"b"), b"a\xff");
+        assert_eq!(cmp.find_short_successor(b"abc"), b"b");
+        assert_eq!(cmp.find_short_successor(b"\xff\xffa"), b"\xff\xffb");
+        assert_eq!(cmp.find_short_successor(b"\xff\xff"), b"\xff\xff");
+    }
+
+    #[test]
+    fn test_internal_key_separator() {
+        let cmp = InternalKeyComparator;
+        let key = |user_key: &[u8], seq| {
+            InternalKey::new(user_key, seq, ValueType::Value)
+                .encoded()
+                .to_vec()
+        };
+        let separator = cmp.find_shortest_separator(&key(b"foo", 100), &key(b"hello", 200));
+        assert_eq!(separator, key(b"g", MAX_SEQUENCE_NUMBER));
+        // same user key can't be shortened
+        let separator = cmp.find_shortest_separator(&key(b"foo", 100), &key(b"foo", 99));
+        assert_eq!(separator, key(b"foo", 100));
+        assert_eq!(
+            cmp.find_short_successor(&key(b"foo", 100)),
+            key(b"g", MAX_SEQUENCE_NUMBER)
+        );
+        assert_eq!(
+            cmp.find_short_successor(&key(b"\xff\xff", 100)),
+            key(b"\xff\xff", 100)
+        );
+    }
+}
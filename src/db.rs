@@ -1,76 +1,2379 @@
 //! db interface
 
-use std::path::Path;
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    future::Future,
+    ops::{
+        Bound,
+        RangeBounds,
+    },
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        LazyLock,
+    },
+};
 
 use bytes::Bytes;
+use futures::{
+    Stream,
+    TryStreamExt,
+};
 use thiserror::Error;
+use tokio::sync::{
+    Mutex,
+    Notify,
+};
 
 use crate::{
-    mem_table::MemTable,
+    comparator::{
+        Comparator,
+        InternalKeyComparator,
+    },
+    db_iter::DbIterator,
+    filename::{
+        parse_file_name,
+        table_file_name,
+        temp_file_name,
+        FileType,
+        LOCK_FILE_NAME,
+    },
+    filter_policy::{
+        FilterPolicy,
+        InternalFilterPolicy,
+    },
+    format::{
+        extract_user_key,
+        InternalKey,
+        SequenceNumber,
+        ValueType,
+        MAX_SEQUENCE_NUMBER,
+    },
+    iterator::InternalIterator,
+    mem_table::{
+        LookupResult,
+        MemTable,
+    },
+    merger::MergingIterator,
+    options::{
+        CompactRangeOptions,
+        Options,
+        ReadOptions,
+        WriteOptions,
+    },
+    snapshot::{
+        Snapshot,
+        SnapshotList,
+    },
+    sorted_stable::{
+        Table,
+        TableBuilder,
+        TableError,
+    },
+    statistics::Statistics,
+    table_cache::TableCache,
+    version_edit::{
+        FileMetaData,
+        VersionEdit,
+    },
+    version_set::{
+        Compaction,
+        Version,
+        VersionError,
+        VersionSet,
+        NUM_LEVELS,
+    },
     vfs::{
+        FileLock,
         Vfs,
         VfsError,
     },
     wal::{
         Wal,
         WalError,
+        WalFileReader,
     },
+    write_batch::WriteBatch,
 };
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum DbError {
     #[error(transparent)]
     VfsError(#[from] VfsError),
     #[error(transparent)]
     WalError(#[from] WalError),
+    #[error(transparent)]
+    TableError(TableError),
+    #[error(transparent)]
+    VersionError(#[from] VersionError),
+    #[error("corruption in {file} at offset {offset}")]
+    Corruption { file: String, offset: u64 },
+    #[error("invalid internal key")]
+    InvalidInternalKeyError,
+    #[error("background error: {0}")]
+    BackgroundError(Arc<DbError>),
+}
+
+impl From<TableError> for DbError {
+    fn from(err: TableError) -> Self {
+        match err {
+            TableError::Corruption { file, offset, .. } => DbError::Corruption { file, offset },
+            err => DbError::TableError(err),
+        }
+    }
 }
 
 type Result<T> = std::result::Result<T, DbError>;
 
+/// LOCK files of dbs dropped in this process whose background work is still running, each is
+/// notified once its lock is released
+static RELEASING_LOCKS: LazyLock<std::sync::Mutex<HashMap<PathBuf, Arc<Notify>>>> =
+    LazyLock::new(Default::default);
+
 /// db interface object
+///
+/// Dropping db stops scheduling background work, its lock is released once running work finishes.
 pub struct Db {
-    vfs: Vfs,
-    mem: MemTable,
-    wal: Wal,
+    inner:     Arc<DbInner>,
+    // keeps others from opening the db, taken on drop
+    lock:      Option<Box<dyn FileLock>>,
+    lock_path: PathBuf,
+}
+
+struct DbInner {
+    vfs:             Vfs,
+    options:         Options,
+    // WAL of active memtable, its lock also serializes writers so that WAL and memtable see
+    // batches in the same order
+    wal:             Mutex<Wal>,
+    // locked before `state` when both are needed
+    versions:        Mutex<VersionSet>,
+    state:           Mutex<DbState>,
+    // notified when a flush of the immutable memtable finishes
+    flushed:         Notify,
+    // set when a background compaction is running
+    compacting:      AtomicBool,
+    // set once db is dropped, no compaction is scheduled after it
+    shutting_down:   AtomicBool,
+    // number of running background tasks, `background_done` is notified when it drops to 0
+    background:      AtomicUsize,
+    background_done: Notify,
+    // held by a compaction from picking files to installing them, so that no two compactions
+    // pick the same files
+    compaction_lock: Mutex<()>,
+    // numbers of table files being written, which are not in any version yet
+    pending_outputs: std::sync::Mutex<HashSet<u64>>,
+    // sequence of the last write visible to readers
+    last_sequence:   AtomicU64,
+    snapshots:       Arc<SnapshotList>,
+    table_cache:     TableCache,
+}
+
+/// memtables and tables reads go through, from newest to oldest
+#[derive(Clone)]
+struct DbState {
+    mem:      Arc<MemTable>,
+    // full memtable being flushed to level-0
+    imm:      Option<Arc<MemTable>>,
+    // table files
+    version:  Arc<Version>,
+    // error of the last failed flush or compaction, writes fail once it is set
+    bg_error: Option<Arc<DbError>>,
 }
 
 impl Db {
-    /// create a new db
-    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+    /// open the db at `path` with default options, creating it if it does not exist
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_options(path, Options::default()).await
+    }
+
+    /// open the db at `path`, creating it if it does not exist
+    ///
+    /// Data of an existing db is recovered by replaying its WALs and writing them to a level-0
+    /// table.
+    pub async fn open_with_options(path: impl AsRef<Path>, mut options: Options) -> Result<Self> {
         let path = path.as_ref();
-        let vfs = Vfs::new(path.to_owned()).await?;
-        let wal = Wal::open(vfs.clone()).await?;
-        Ok(Db {
+        // tables hold internal keys, filter them by user keys
+        options.filter_policy = options
+            .filter_policy
+            .map(|policy| Arc::new(InternalFilterPolicy::new(policy)) as Arc<dyn FilterPolicy>);
+        options.statistics.get_or_insert_with(Default::default);
+        // blocks of this db are apart from those of other dbs sharing the cache
+        options.block_cache = options.block_cache.map(|cache| cache.with_new_id());
+        let vfs = Vfs::new(options.file_system.clone(), path.to_owned()).await?;
+        let lock_path = path.join(LOCK_FILE_NAME);
+        wait_for_lock_release(&lock_path).await;
+        let lock = vfs.lock(LOCK_FILE_NAME).await?;
+        let mut versions = VersionSet::recover(vfs.clone()).await?;
+        // WALs written after the last flush
+        let mut logs = vec![];
+        for name in vfs.list().await? {
+            if let Some((number, FileType::Log)) = parse_file_name(&name) {
+                versions.mark_file_number_used(number);
+                if number >= versions.log_number() {
+                    logs.push(number);
+                }
+            }
+        }
+        logs.sort_unstable();
+        let recovered = Arc::new(MemTable::new(options.memtable_rep));
+        let mut last_sequence = versions.last_sequence();
+        for &number in &logs {
+            let paranoid = options.paranoid_checks;
+            last_sequence =
+                last_sequence.max(Self::recover(&vfs, number, &recovered, paranoid).await?);
+        }
+
+        let wal = Wal::open(vfs.clone(), versions.new_file_number()).await?;
+        let mut edit = VersionEdit::new();
+        edit.log_number = Some(wal.number());
+        edit.last_sequence = Some(last_sequence);
+        let table_cache = TableCache::new(vfs.clone(), options.clone());
+        let mem = Arc::new(MemTable::new(options.memtable_rep));
+        let inner = Arc::new(DbInner {
             vfs,
-            mem: MemTable::new(),
-            wal,
+            options,
+            wal: Mutex::new(wal),
+            state: Mutex::new(DbState {
+                mem,
+                imm: None,
+                version: versions.current(),
+                bg_error: None,
+            }),
+            versions: Mutex::new(versions),
+            flushed: Notify::new(),
+            compacting: AtomicBool::new(false),
+            shutting_down: AtomicBool::new(false),
+            background: AtomicUsize::new(0),
+            background_done: Notify::new(),
+            compaction_lock: Mutex::new(()),
+            pending_outputs: Default::default(),
+            last_sequence: AtomicU64::new(last_sequence),
+            snapshots: Arc::new(SnapshotList::new()),
+            table_cache,
+        });
+        // recovered data only lives in old WALs, persist it before retiring them
+        inner.flush_memtable(&recovered, &mut edit).await?;
+        inner.switch_compaction_style().await?;
+        inner.remove_obsolete_files().await?;
+        inner.maybe_schedule_compaction().await;
+        Ok(Db {
+            inner,
+            lock: Some(lock),
+            lock_path,
         })
     }
 
+    /// close db once its running background work finishes
+    ///
+    /// Unlike dropping db, its lock is released when this returns.
+    pub async fn close(self) {
+        self.inner.shutting_down.store(true, Ordering::Release);
+        self.inner.wait_for_background().await;
+    }
+
     /// get value from db
     pub async fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
+        self.get_with_options(&ReadOptions::default(), key).await
+    }
+
+    /// get value from db, reading from `options.snapshot` if it is set
+    pub async fn get_with_options(
+        &self,
+        options: &ReadOptions,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<Bytes>> {
         let key = key.as_ref();
-        Ok(self.mem.get(key).await)
+        // a version installed between the two may have dropped entries visible at the sequence
+        let (seq, state) = {
+            let state = self.inner.state.lock().await;
+            (self.read_sequence(options), state.clone())
+        };
+        match self.inner.get(&state, options, key, seq).await? {
+            Some(LookupResult::Value(value)) => Ok(Some(value)),
+            Some(LookupResult::Deleted) | None => Ok(None),
+        }
+    }
+
+    /// create an iterator over db, reading from `options.snapshot` if it is set
+    ///
+    /// Iterator is unpositioned, seek it before reading.
+    pub async fn iter(&self, options: ReadOptions) -> Result<DbIterator> {
+        // pin the state iterator reads from even if no snapshot is given
+        let snapshot = match &options.snapshot {
+            Some(snapshot) => snapshot.clone(),
+            None => self.snapshot(),
+        };
+        let state = self.inner.current().await;
+        let mut children: Vec<Box<dyn InternalIterator>> = vec![Box::new(state.mem.iter())];
+        if let Some(imm) = &state.imm {
+            children.push(Box::new(imm.iter()));
+        }
+        for level in 0..NUM_LEVELS {
+            // newer level-0 files first, though merging iterator doesn't rely on it
+            for file in state.version.files(level).iter().rev() {
+                let table = self.inner.table_cache.find_table(file.number).await?;
+                children.push(Box::new(table.iter(&options)));
+            }
+        }
+        let iter = MergingIterator::new(Arc::new(InternalKeyComparator), children);
+        Ok(DbIterator::new(Box::new(iter), snapshot))
+    }
+
+    /// stream key value pairs in `range` in key order, from a snapshot taken when called
+    pub async fn range<K: AsRef<[u8]>>(
+        &self,
+        range: impl RangeBounds<K>,
+    ) -> Result<impl Stream<Item = Result<(Bytes, Bytes)>>> {
+        let start = owned_bound(range.start_bound());
+        let end = owned_bound(range.end_bound());
+        let iter = self.iter(ReadOptions::default()).await?;
+        Ok(futures::stream::try_unfold(
+            (iter, Some(start)),
+            move |(mut iter, start)| {
+                let end = end.clone();
+                async move {
+                    match start {
+                        Some(Bound::Included(start)) => iter.seek(start).await?,
+                        Some(Bound::Excluded(start)) => {
+                            iter.seek(&start).await?;
+                            if iter.valid() && iter.key() == start {
+                                iter.next().await?;
+                            }
+                        }
+                        Some(Bound::Unbounded) => iter.seek_to_first().await?,
+                        None => iter.next().await?,
+                    }
+                    if !iter.valid() {
+                        return Ok(None);
+                    }
+                    let key = iter.key();
+                    let in_range = match &end {
+                        Bound::Included(end) => key <= end,
+                        Bound::Excluded(end) => key < end,
+                        Bound::Unbounded => true,
+                    };
+                    if !in_range {
+                        return Ok(None);
+                    }
+                    let value = iter.value();
+                    Ok(Some(((key, value), (iter, None))))
+                }
+            },
+        ))
+    }
+
+    /// stream live key value pairs whose key starts with `prefix` in key order
+    pub async fn scan_prefix(
+        &self,
+        prefix: impl AsRef<[u8]>,
+    ) -> Result<impl Stream<Item = Result<(Bytes, Bytes)>>> {
+        let prefix = Bytes::copy_from_slice(prefix.as_ref());
+        // keys with the prefix are exactly those in [prefix, successor of prefix)
+        let end = match prefix_successor(&prefix) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        };
+        self.range((Bound::Included(prefix), end)).await
+    }
+
+    /// compact files overlapping user keys `[begin, end]` into the last level, a missing bound
+    /// is unbounded
+    ///
+    /// Memtable is flushed first if it has keys in the range, so that all data in the range is
+    /// compacted.
+    pub async fn compact_range(&self, begin: Option<&[u8]>, end: Option<&[u8]>) -> Result<()> {
+        self.compact_range_with_options(&CompactRangeOptions::default(), begin, end)
+            .await
+    }
+
+    /// compact files overlapping user keys `[begin, end]` into the last level, running alongside
+    /// automatic compactions unless `options.exclusive_manual_compaction` is set
+    pub async fn compact_range_with_options(
+        &self,
+        options: &CompactRangeOptions,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<()> {
+        {
+            let mut wal = self.inner.wal.lock().await;
+            let mem = self.inner.current().await.mem;
+            if overlaps_memtable(&mem, begin, end).await? {
+                self.inner.make_room_for_write(&mut wal, true).await?;
+            }
+            self.inner.wait_for_flush().await?;
+        }
+        self.inner.compact_range(options, begin, end).await
+    }
+
+    /// counters of db events
+    pub fn statistics(&self) -> Arc<Statistics> {
+        self.inner.options.statistics.clone().unwrap()
+    }
+
+    /// take a snapshot of the current state of db
+    ///
+    /// Reads through the snapshot ignore every write made after it was taken.
+    pub fn snapshot(&self) -> Snapshot {
+        let inner = &self.inner;
+        inner
+            .snapshots
+            .acquire(|| inner.last_sequence.load(Ordering::Acquire))
     }
 
     /// set key value pair in db
     pub async fn set(&self, key: Bytes, value: Bytes) -> Result<()> {
-        self.wal.set(&key, &value).await?;
-        self.mem.set(key, value).await;
+        let mut batch = WriteBatch::new();
+        batch.put(key, value);
+        self.write(batch).await
+    }
+
+    /// delete key from db
+    pub async fn delete(&self, key: Bytes) -> Result<()> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key);
+        self.write(batch).await
+    }
+
+    /// apply all updates in `batch` atomically
+    pub async fn write(&self, batch: WriteBatch) -> Result<()> {
+        self.write_with_options(&WriteOptions::default(), batch)
+            .await
+    }
+
+    /// apply all updates in `batch` atomically, syncing WAL if `options.sync` is set
+    pub async fn write_with_options(
+        &self,
+        options: &WriteOptions,
+        batch: WriteBatch,
+    ) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let inner = &self.inner;
+        let mut wal = inner.wal.lock().await;
+        inner.make_room_for_write(&mut wal, false).await?;
+        let seq = inner.last_sequence.load(Ordering::Relaxed) + 1;
+        let result = async {
+            wal.write(&batch, seq).await?;
+            if options.sync {
+                wal.sync().await?;
+            }
+            Ok::<_, WalError>(())
+        }
+        .await;
+        if let Err(err) = result {
+            // WAL may end with a partial record now, and the batch may or may not be recovered,
+            // later writes must not follow it
+            let err = Arc::new(DbError::from(err));
+            inner.state.lock().await.bg_error = Some(err.clone());
+            return Err(DbError::BackgroundError(err));
+        }
+        // memtable is only switched with WAL locked
+        let mem = inner.state.lock().await.mem.clone();
+        mem.apply(&batch, seq);
+        // publish the whole batch to readers at once
+        inner
+            .last_sequence
+            .store(seq + batch.len() as u64 - 1, Ordering::Release);
         Ok(())
     }
+
+    /// sequence a read with `options` should see
+    fn read_sequence(&self, options: &ReadOptions) -> SequenceNumber {
+        match &options.snapshot {
+            Some(snapshot) => snapshot.sequence(),
+            None => self.inner.last_sequence.load(Ordering::Acquire),
+        }
+    }
+
+    /// replay every record in WAL `number` into `mem`, return the last sequence found
+    ///
+    /// Unless `paranoid`, a corrupted record and all after it are dropped.
+    async fn recover(
+        vfs: &Vfs,
+        number: u64,
+        mem: &MemTable,
+        paranoid: bool,
+    ) -> Result<SequenceNumber> {
+        let reader = WalFileReader::open(vfs.clone(), number, paranoid).await?;
+        let records = reader.into_data_stream();
+        futures::pin_mut!(records);
+        let mut last_sequence = 0;
+        while let Some(data) = records.try_next().await? {
+            let (seq, batch) = WriteBatch::decode(data).ok_or(WalError::MalformedRecordError)?;
+            mem.apply(&batch, seq);
+            last_sequence = last_sequence.max(seq + batch.len() as u64 - 1);
+        }
+        Ok(last_sequence)
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        let inner = &self.inner;
+        inner.shutting_down.store(true, Ordering::Release);
+        let lock = self.lock.take();
+        // only running background work spawns more once db is shutting down
+        if inner.background.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            // background work dies along with its runtime
+            Err(_) => return,
+        };
+        let released = Arc::new(Notify::new());
+        RELEASING_LOCKS
+            .lock()
+            .unwrap()
+            .insert(self.lock_path.clone(), released.clone());
+        let inner = inner.clone();
+        let lock_path = self.lock_path.clone();
+        runtime.spawn(async move {
+            inner.wait_for_background().await;
+            drop(lock);
+            RELEASING_LOCKS.lock().unwrap().remove(&lock_path);
+            released.notify_waiters();
+        });
+    }
+}
+
+/// wait until the db whose lock is `lock_path`, if it's dropped in this process, releases the lock
+async fn wait_for_lock_release(lock_path: &Path) {
+    let releasing = RELEASING_LOCKS.lock().unwrap().get(lock_path).cloned();
+    if let Some(releasing) = releasing {
+        let released = releasing.notified();
+        // the lock is released before its entry is removed and the notification is sent
+        if RELEASING_LOCKS.lock().unwrap().contains_key(lock_path) {
+            released.await;
+        }
+    }
+}
+
+impl DbInner {
+    /// clone of current state
+    async fn current(&self) -> DbState {
+        self.state.lock().await.clone()
+    }
+
+    /// newest version of `key` visible at `seq` in `state`, `None` means the key is unknown to db
+    async fn get(
+        &self,
+        state: &DbState,
+        options: &ReadOptions,
+        key: &[u8],
+        seq: SequenceNumber,
+    ) -> Result<Option<LookupResult>> {
+        if let Some(found) = state.mem.get(key, seq) {
+            return Ok(Some(found));
+        }
+        if let Some(imm) = &state.imm {
+            if let Some(found) = imm.get(key, seq) {
+                return Ok(Some(found));
+            }
+        }
+        for file in state.version.files_for_key(key) {
+            let table = self.table_cache.find_table(file.number).await?;
+            let found = get_from_table(&table, options, key, seq).await?;
+            if found.is_some() && table.has_filter() {
+                self.statistics().record_bloom_filter_true_positive();
+            }
+            if let Some(found) = found {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// counters of db, which is always set on open
+    fn statistics(&self) -> &Statistics {
+        self.options.statistics.as_ref().unwrap()
+    }
+
+    /// switch to a new memtable if the active one is full or `force` is set and it's not empty,
+    /// waiting for the previous flush if it's still running
+    ///
+    /// `wal` is the locked WAL of active memtable, which is replaced on switch.
+    async fn make_room_for_write(self: &Arc<Self>, wal: &mut Wal, force: bool) -> Result<()> {
+        loop {
+            {
+                let state = self.state.lock().await;
+                if let Some(err) = &state.bg_error {
+                    return Err(DbError::BackgroundError(err.clone()));
+                }
+                let usage = state.mem.approximate_memory_usage();
+                if usage == 0 || (!force && usage < self.options.write_buffer_size) {
+                    return Ok(());
+                }
+                if state.imm.is_none() {
+                    break;
+                }
+            }
+            self.wait_for_flush().await?;
+        }
+
+        let number = self.versions.lock().await.new_file_number();
+        *wal = Wal::open(self.vfs.clone(), number).await?;
+        let mut state = self.state.lock().await;
+        let mem = Arc::new(MemTable::new(self.options.memtable_rep));
+        let imm = std::mem::replace(&mut state.mem, mem);
+        state.imm = Some(imm.clone());
+        self.spawn_background(self.clone().flush(imm, number));
+        Ok(())
+    }
+
+    /// run `task` in background, which db waits for before releasing its lock
+    fn spawn_background(self: &Arc<Self>, task: impl Future<Output = ()> + Send + 'static) {
+        self.background.fetch_add(1, Ordering::AcqRel);
+        let inner = self.clone();
+        tokio::spawn(async move {
+            task.await;
+            if inner.background.fetch_sub(1, Ordering::AcqRel) == 1 {
+                inner.background_done.notify_waiters();
+            }
+        });
+    }
+
+    /// wait until no background task is running, a task spawned by a running one is waited as well
+    async fn wait_for_background(&self) {
+        loop {
+            // notified even if it's not polled yet
+            let done = self.background_done.notified();
+            if self.background.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            done.await;
+        }
+    }
+
+    /// wait until the immutable memtable is flushed
+    ///
+    /// Caller must hold WAL lock, so that there is at most one waiter.
+    async fn wait_for_flush(&self) -> Result<()> {
+        loop {
+            // a notification sent before waiting is kept as a permit
+            let flushed = self.flushed.notified();
+            {
+                let state = self.state.lock().await;
+                if let Some(err) = &state.bg_error {
+                    return Err(DbError::BackgroundError(err.clone()));
+                }
+                if state.imm.is_none() {
+                    return Ok(());
+                }
+            }
+            flushed.await;
+        }
+    }
+
+    /// write immutable memtable `imm` to a level-0 table in background, then retire WALs older
+    /// than `log_number`, which is the WAL of active memtable
+    async fn flush(self: Arc<Self>, imm: Arc<MemTable>, log_number: u64) {
+        let mut edit = VersionEdit::new();
+        edit.log_number = Some(log_number);
+        edit.last_sequence = Some(self.last_sequence.load(Ordering::Acquire));
+        match self.flush_memtable(&imm, &mut edit).await {
+            Ok(()) => {
+                // a file which can't be removed now is removed by the next flush or open
+                let _ = self.remove_obsolete_files().await;
+                // data of `imm` is in current version now, this allows the next flush to start
+                self.state.lock().await.imm = None;
+                self.maybe_schedule_compaction().await;
+            }
+            Err(err) => self.state.lock().await.bg_error = Some(Arc::new(err)),
+        }
+        self.flushed.notify_one();
+    }
+
+    /// write all entries of `mem` to a new level-0 table, then install it along with `edit`
+    async fn flush_memtable(&self, mem: &Arc<MemTable>, edit: &mut VersionEdit) -> Result<()> {
+        let mut output = self.new_output().await?;
+        let number = output.number;
+        let result = async {
+            let mut iter = mem.iter();
+            iter.seek_to_first().await?;
+            while iter.valid() {
+                output.add(iter.key(), iter.value()).await?;
+                iter.next().await?;
+            }
+            if let Some(file) = self.finish_output(output).await? {
+                edit.add_file(0, file);
+            }
+            self.install(edit).await
+        }
+        .await;
+        self.release_outputs(&[number]);
+        result
+    }
+
+    /// fully compact files organized by another compaction style, then record the style of
+    /// options
+    async fn switch_compaction_style(&self) -> Result<()> {
+        let style = self.options.compaction_style;
+        let compaction = {
+            let versions = self.versions.lock().await;
+            if versions.compaction_style() == style {
+                return Ok(());
+            }
+            versions.pick_full_compaction()
+        };
+        if let Some(compaction) = compaction {
+            self.compact(compaction).await?;
+        }
+        let mut edit = VersionEdit::new();
+        edit.compaction_style = Some(style);
+        self.install(&mut edit).await
+    }
+
+    /// start a background compaction if some level is over its budget and none is running, unless
+    /// db is shutting down
+    async fn maybe_schedule_compaction(self: &Arc<Self>) {
+        if self.shutting_down.load(Ordering::Acquire) ||
+            self.compacting.load(Ordering::Acquire) ||
+            !self.versions.lock().await.needs_compaction(&self.options)
+        {
+            return;
+        }
+        if !self.compacting.swap(true, Ordering::AcqRel) {
+            self.spawn_background(self.clone().background_compaction());
+        }
+    }
+
+    /// compact levels until none is over its budget or db is shutting down
+    async fn background_compaction(self: Arc<Self>) {
+        loop {
+            while !self.shutting_down.load(Ordering::Acquire) {
+                let _lock = self.compaction_lock.lock().await;
+                let compaction = self.versions.lock().await.pick_compaction(&self.options);
+                let compaction = match compaction {
+                    Some(compaction) => compaction,
+                    None => break,
+                };
+                if let Err(err) = self.compact(compaction).await {
+                    self.state.lock().await.bg_error = Some(Arc::new(err));
+                    self.compacting.store(false, Ordering::Release);
+                    return;
+                }
+                let _ = self.remove_obsolete_files().await;
+            }
+            self.compacting.store(false, Ordering::Release);
+            // a flush may have asked for a compaction after the last pick, but before the flag was
+            // cleared
+            if self.shutting_down.load(Ordering::Acquire) ||
+                !self.versions.lock().await.needs_compaction(&self.options) ||
+                self.compacting.swap(true, Ordering::AcqRel)
+            {
+                return;
+            }
+        }
+    }
+
+    /// compact files overlapping user keys `[begin, end]` level by level into the last level
+    async fn compact_range(
+        self: &Arc<Self>,
+        options: &CompactRangeOptions,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<()> {
+        let exclusive = match options.exclusive_manual_compaction {
+            true => Some(self.compaction_lock.lock().await),
+            false => None,
+        };
+        for level in 0..NUM_LEVELS - 1 {
+            let _lock = match exclusive {
+                Some(_) => None,
+                None => Some(self.compaction_lock.lock().await),
+            };
+            let compaction = self
+                .versions
+                .lock()
+                .await
+                .pick_range_compaction(level, begin, end);
+            if let Some(compaction) = compaction {
+                self.compact(compaction).await?;
+            }
+        }
+        drop(exclusive);
+        self.remove_obsolete_files().await?;
+        self.maybe_schedule_compaction().await;
+        Ok(())
+    }
+
+    /// merge input files of `compaction` into the output level and install the result
+    async fn compact(&self, compaction: Compaction) -> Result<()> {
+        let mut edit = VersionEdit::new();
+        compaction.add_input_deletions(&mut edit);
+        if compaction.is_trivial_move(&self.options) {
+            let file = compaction.inputs[0].1.as_ref().clone();
+            edit.add_file(compaction.output_level, file);
+            return self.install(&mut edit).await;
+        }
+        let mut numbers = vec![];
+        let result = match self
+            .write_compaction_outputs(&compaction, &mut edit, &mut numbers)
+            .await
+        {
+            Ok(()) => self.install(&mut edit).await,
+            Err(err) => Err(err),
+        };
+        self.release_outputs(&numbers);
+        result
+    }
+
+    /// merge input files of `compaction` into new tables recorded in `edit`, numbers of the
+    /// tables are pushed to `numbers`
+    ///
+    /// Versions shadowed by a newer version which every snapshot sees are dropped, so are
+    /// tombstones every snapshot sees if no older version of the key may be below.
+    async fn write_compaction_outputs(
+        &self,
+        compaction: &Compaction,
+        edit: &mut VersionEdit,
+        numbers: &mut Vec<u64>,
+    ) -> Result<()> {
+        let smallest_snapshot = self
+            .snapshots
+            .oldest(|| self.last_sequence.load(Ordering::Acquire));
+        let mut children: Vec<Box<dyn InternalIterator>> = vec![];
+        // input blocks are read once, keep them from evicting blocks of reads
+        let read_options = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        for (_, file) in &compaction.inputs {
+            let table = self.table_cache.find_table(file.number).await?;
+            children.push(Box::new(table.iter(&read_options)));
+        }
+        let mut iter = MergingIterator::new(Arc::new(InternalKeyComparator), children);
+        iter.seek_to_first().await?;
+
+        let level = compaction.output_level;
+        let mut output: Option<TableOutput> = None;
+        let mut current_user_key: Option<Bytes> = None;
+        let mut last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+        while iter.valid() {
+            let key = iter.key();
+            let ikey = InternalKey::decode(key.clone()).ok_or(DbError::InvalidInternalKeyError)?;
+            if current_user_key.as_deref() != Some(ikey.user_key()) {
+                current_user_key = Some(Bytes::copy_from_slice(ikey.user_key()));
+                last_sequence_for_key = MAX_SEQUENCE_NUMBER;
+                // switch outputs only between user keys, a user key in more than one file of a
+                // level would be missed by lookups
+                let full = output
+                    .as_ref()
+                    .is_some_and(|output| output.file_size() >= self.options.max_file_size);
+                if full {
+                    let file = self.finish_output(output.take().unwrap()).await?;
+                    edit.new_files.extend(file.map(|file| (level, file)));
+                }
+            }
+
+            let drop = if last_sequence_for_key <= smallest_snapshot {
+                true
+            } else {
+                ikey.value_type() == ValueType::Deletion &&
+                    ikey.sequence() <= smallest_snapshot &&
+                    compaction.is_base_level_for_key(ikey.user_key())
+            };
+            last_sequence_for_key = ikey.sequence();
+            if !drop {
+                if output.is_none() {
+                    let new_output = self.new_output().await?;
+                    numbers.push(new_output.number);
+                    output = Some(new_output);
+                }
+                output.as_mut().unwrap().add(key, iter.value()).await?;
+            }
+            iter.next().await?;
+        }
+        if let Some(output) = output {
+            let file = self.finish_output(output).await?;
+            edit.new_files.extend(file.map(|file| (level, file)));
+        }
+        Ok(())
+    }
+
+    /// start writing a new table file, which is kept from being removed as obsolete until
+    /// released by [`DbInner::release_outputs`]
+    async fn new_output(&self) -> Result<TableOutput> {
+        // the number is pending before versions are unlocked, otherwise a concurrent
+        // `remove_obsolete_files` may take the new file as obsolete
+        let mut versions = self.versions.lock().await;
+        let number = versions.new_file_number();
+        self.pending_outputs.lock().unwrap().insert(number);
+        drop(versions);
+        let file = self.vfs.create(temp_file_name(number)).await?;
+        let cmp: Arc<dyn Comparator> = Arc::new(InternalKeyComparator);
+        Ok(TableOutput {
+            number,
+            builder: TableBuilder::new(&self.options, cmp, file),
+            smallest: None,
+            largest: Bytes::new(),
+        })
+    }
+
+    /// finish writing `output`, `None` if it has no entry
+    async fn finish_output(&self, output: TableOutput) -> Result<Option<FileMetaData>> {
+        let temp = temp_file_name(output.number);
+        if output.builder.num_entries() == 0 {
+            self.vfs.remove(&temp).await?;
+            return Ok(None);
+        }
+        let (smallest, largest) = (output.smallest.unwrap_or_default(), output.largest);
+        let (mut file, file_size) = output.builder.finish().await?;
+        file.sync().await?;
+        // table only shows up under its real name once it's complete
+        self.vfs
+            .rename(&temp, table_file_name(output.number))
+            .await?;
+        // the name survives a crash before MANIFEST refers to it
+        self.vfs.sync_dir().await?;
+        let parse = |key| InternalKey::decode(key).ok_or(DbError::InvalidInternalKeyError);
+        Ok(Some(FileMetaData {
+            number: output.number,
+            file_size,
+            smallest: parse(smallest)?,
+            largest: parse(largest)?,
+        }))
+    }
+
+    /// allow table files `numbers` to be removed as obsolete once they are not in any version
+    fn release_outputs(&self, numbers: &[u64]) {
+        let mut pending_outputs = self.pending_outputs.lock().unwrap();
+        for number in numbers {
+            pending_outputs.remove(number);
+        }
+    }
+
+    /// persist `edit` and make the resulting version current
+    async fn install(&self, edit: &mut VersionEdit) -> Result<()> {
+        let mut versions = self.versions.lock().await;
+        versions.log_and_apply(edit).await?;
+        self.state.lock().await.version = versions.current();
+        Ok(())
+    }
+
+    /// remove files which are no longer part of db
+    async fn remove_obsolete_files(&self) -> Result<()> {
+        let mut versions = self.versions.lock().await;
+        let mut live = versions.live_files();
+        live.extend(self.pending_outputs.lock().unwrap().iter());
+        for name in self.vfs.list().await? {
+            let parsed = parse_file_name(&name);
+            let obsolete = match parsed {
+                Some((number, FileType::Log)) => number < versions.log_number(),
+                Some((number, FileType::Manifest)) => number < versions.manifest_number(),
+                // a temporary file is either a table being written or CURRENT being written with
+                // versions locked
+                Some((number, FileType::Table)) | Some((number, FileType::Temp)) => {
+                    !live.contains(&number)
+                }
+                Some((_, FileType::Current)) | None => false,
+            };
+            if obsolete {
+                if let Some((number, FileType::Table)) = parsed {
+                    self.table_cache.evict(number);
+                }
+                self.vfs.remove(&name).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// check if `mem` has an entry of user key in `[begin, end]`
+async fn overlaps_memtable(
+    mem: &Arc<MemTable>,
+    begin: Option<&[u8]>,
+    end: Option<&[u8]>,
+) -> Result<bool> {
+    let mut iter = mem.iter();
+    match begin {
+        Some(begin) => {
+            let key = InternalKey::new(begin, MAX_SEQUENCE_NUMBER, ValueType::FOR_SEEK);
+            iter.seek(key.encoded()).await?
+        }
+        None => iter.seek_to_first().await?,
+    }
+    Ok(iter.valid() && end.is_none_or(|end| extract_user_key(&iter.key()) <= end))
+}
+
+/// a table file being written, under a temporary name until it's finished
+struct TableOutput {
+    number:   u64,
+    builder:  TableBuilder,
+    smallest: Option<Bytes>,
+    largest:  Bytes,
+}
+
+impl TableOutput {
+    /// add an entry, `key` must be after every key added before
+    async fn add(&mut self, key: Bytes, value: Bytes) -> Result<()> {
+        self.smallest.get_or_insert_with(|| key.clone());
+        self.largest = key.clone();
+        self.builder.add(key, value).await?;
+        Ok(())
+    }
+
+    fn file_size(&self) -> u64 {
+        self.builder.file_size()
+    }
+}
+
+/// look up the newest version of `key` visible at `seq` in `table`
+async fn get_from_table(
+    table: &Table,
+    options: &ReadOptions,
+    key: &[u8],
+    seq: SequenceNumber,
+) -> Result<Option<LookupResult>> {
+    let lookup = InternalKey::new(key, seq, ValueType::FOR_SEEK);
+    let (found, value) = match table.get(options, lookup.encoded()).await? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let found = InternalKey::decode(found).ok_or(DbError::InvalidInternalKeyError)?;
+    if found.user_key() != key {
+        return Ok(None);
+    }
+    match found.value_type() {
+        ValueType::Value => Ok(Some(LookupResult::Value(value))),
+        ValueType::Deletion => Ok(Some(LookupResult::Deleted)),
+    }
+}
+
+fn owned_bound<K: AsRef<[u8]>>(bound: Bound<&K>) -> Bound<Bytes> {
+    match bound {
+        Bound::Included(key) => Bound::Included(Bytes::copy_from_slice(key.as_ref())),
+        Bound::Excluded(key) => Bound::Excluded(Bytes::copy_from_slice(key.as_ref())),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// smallest key greater than every key starting with `prefix`, `None` if there is no such key
+fn prefix_successor(prefix: &[u8]) -> Option<Bytes> {
+    let last = prefix.iter().rposition(|&b| b != 0xff)?;
+    let mut successor = prefix[..=last].to_vec();
+    successor[last] += 1;
+    Some(successor.into())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
     use super::*;
+    use crate::{
+        cache::BlockCache,
+        fault_injection::{
+            FaultInjectionFileSystem,
+            FileOp,
+        },
+        filename::{
+            log_file_name,
+            CURRENT_FILE_NAME,
+            LEGACY_WAL_FILE_NAME,
+        },
+        filter_policy::BloomFilterPolicy,
+        mem_file_system::MemFileSystem,
+        options::{
+            CompactionStyle,
+            MemTableRepType,
+        },
+        vfs::{
+            FileSystem,
+            RandomAccessFile,
+            TokioFileSystem,
+            WritableFile,
+        },
+    };
+
+    /// open db "db" kept in memory by `fs` with default options
+    async fn open_in_memory(fs: &MemFileSystem) -> Db {
+        let options = Options {
+            file_system: Arc::new(fs.clone()),
+            ..Options::default()
+        };
+        Db::open_with_options("db", options).await.unwrap()
+    }
 
     #[tokio::test]
     async fn test_db_basic() {
-        let db = Db::create("test-db").await.unwrap();
+        let db = open_in_memory(&MemFileSystem::new()).await;
         assert!(db.get("non-exists key").await.unwrap().is_none());
         assert!(db.set("key1".into(), "val1".into()).await.is_ok());
         assert_eq!(db.get("key1").await.unwrap(), Some("val1".into()));
         assert!(db.set("key1".into(), "val2".into()).await.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_db_reopen() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        db.set("key1".into(), "val1".into()).await.unwrap();
+        db.set("key2".into(), "val2".into()).await.unwrap();
+        db.set("key1".into(), "val3".into()).await.unwrap();
+        drop(db);
+
+        let db = open_in_memory(&fs).await;
+        assert_eq!(db.get("key1").await.unwrap(), Some("val3".into()));
+        assert_eq!(db.get("key2").await.unwrap(), Some("val2".into()));
+        assert!(db.get("key3").await.unwrap().is_none());
+
+        // keep writing after recovery and recover again
+        db.set("key3".into(), "val4".into()).await.unwrap();
+        drop(db);
+        let db = open_in_memory(&fs).await;
+        assert_eq!(db.get("key1").await.unwrap(), Some("val3".into()));
+        assert_eq!(db.get("key3").await.unwrap(), Some("val4".into()));
+    }
+
+    #[tokio::test]
+    async fn test_db_open_legacy_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(LEGACY_WAL_FILE_NAME), "data").unwrap();
+        assert!(matches!(
+            Db::open(dir.path()).await,
+            Err(DbError::VersionError(VersionError::CorruptedManifestError(
+                _
+            )))
+        ));
+        // the old db is left as it is
+        assert!(!dir.path().join(CURRENT_FILE_NAME).exists());
+    }
+
+    #[tokio::test]
+    async fn test_db_delete() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        db.set("key1".into(), "val1".into()).await.unwrap();
+        db.set("key2".into(), "val2".into()).await.unwrap();
+        db.delete("key1".into()).await.unwrap();
+        db.delete("non-exists key".into()).await.unwrap();
+        assert!(db.get("key1").await.unwrap().is_none());
+        assert_eq!(db.get("key2").await.unwrap(), Some("val2".into()));
+
+        db.set("key3".into(), "val3".into()).await.unwrap();
+        db.delete("key3".into()).await.unwrap();
+        db.set("key3".into(), "val4".into()).await.unwrap();
+        drop(db);
+
+        let db = open_in_memory(&fs).await;
+        assert!(db.get("key1").await.unwrap().is_none());
+        assert_eq!(db.get("key2").await.unwrap(), Some("val2".into()));
+        assert_eq!(db.get("key3").await.unwrap(), Some("val4".into()));
+        assert!(db.get("non-exists key").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_db_write_batch() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        db.set("key1".into(), "val1".into()).await.unwrap();
+
+        let mut batch = WriteBatch::new();
+        batch.put("key2".into(), "val2".into());
+        batch.delete("key1".into());
+        batch.put("key3".into(), "val3".into());
+        batch.put("key3".into(), "val4".into());
+        db.write(batch).await.unwrap();
+        assert!(db.get("key1").await.unwrap().is_none());
+        assert_eq!(db.get("key2").await.unwrap(), Some("val2".into()));
+        assert_eq!(db.get("key3").await.unwrap(), Some("val4".into()));
+        db.write(WriteBatch::new()).await.unwrap();
+        drop(db);
+
+        let db = open_in_memory(&fs).await;
+        assert!(db.get("key1").await.unwrap().is_none());
+        assert_eq!(db.get("key2").await.unwrap(), Some("val2".into()));
+        assert_eq!(db.get("key3").await.unwrap(), Some("val4".into()));
+    }
+
+    #[tokio::test]
+    async fn test_db_recover_partial_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(dir.path()).await.unwrap();
+        db.set("key1".into(), "val1".into()).await.unwrap();
+        let wal_len = std::fs::metadata(dir.path().join(log_file_name(1)))
+            .unwrap()
+            .len();
+
+        // a batch spanning several WAL blocks
+        let mut batch = WriteBatch::new();
+        for i in 0..10u8 {
+            batch.put(Bytes::from(vec![i]), Bytes::from(vec![i; 10000]));
+        }
+        db.write(batch).await.unwrap();
+        drop(db);
+
+        // simulate a crash in the middle of writing the batch
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(dir.path().join(log_file_name(1)))
+            .unwrap();
+        file.set_len(wal_len + 50000).unwrap();
+        drop(file);
+
+        let db = Db::open(dir.path()).await.unwrap();
+        assert_eq!(db.get("key1").await.unwrap(), Some("val1".into()));
+        for i in 0..10u8 {
+            assert!(db.get([i]).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_sequence_survives_reopen() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        db.set("key1".into(), "val1".into()).await.unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put("key2".into(), "val2".into());
+        batch.delete("key1".into());
+        db.write(batch).await.unwrap();
+        assert_eq!(db.inner.last_sequence.load(Ordering::Acquire), 3);
+        drop(db);
+
+        let db = open_in_memory(&fs).await;
+        assert_eq!(db.inner.last_sequence.load(Ordering::Acquire), 3);
+        // newer writes must shadow versions recovered from WAL
+        db.set("key2".into(), "val3".into()).await.unwrap();
+        assert_eq!(db.inner.last_sequence.load(Ordering::Acquire), 4);
+        assert_eq!(db.get("key2").await.unwrap(), Some("val3".into()));
+        drop(db);
+
+        let db = open_in_memory(&fs).await;
+        assert_eq!(db.inner.last_sequence.load(Ordering::Acquire), 4);
+        assert!(db.get("key1").await.unwrap().is_none());
+        assert_eq!(db.get("key2").await.unwrap(), Some("val3".into()));
+    }
+
+    #[tokio::test]
+    async fn test_db_snapshot() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        db.set("key1".into(), "val1".into()).await.unwrap();
+        db.set("key2".into(), "val2".into()).await.unwrap();
+        let snapshot = db.snapshot();
+
+        db.set("key1".into(), "val3".into()).await.unwrap();
+        db.delete("key2".into()).await.unwrap();
+        db.set("key3".into(), "val4".into()).await.unwrap();
+
+        let options = ReadOptions {
+            snapshot: Some(snapshot.clone()),
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            db.get_with_options(&options, "key1").await.unwrap(),
+            Some("val1".into())
+        );
+        assert_eq!(
+            db.get_with_options(&options, "key2").await.unwrap(),
+            Some("val2".into())
+        );
+        assert!(db
+            .get_with_options(&options, "key3")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(db.get("key1").await.unwrap(), Some("val3".into()));
+        assert!(db.get("key2").await.unwrap().is_none());
+        assert_eq!(db.get("key3").await.unwrap(), Some("val4".into()));
+
+        let latest = || MAX_SEQUENCE_NUMBER;
+        assert_eq!(db.inner.snapshots.oldest(latest), snapshot.sequence());
+        drop(options);
+        drop(snapshot);
+        assert_eq!(db.inner.snapshots.oldest(latest), MAX_SEQUENCE_NUMBER);
+    }
+
+    async fn collect_forward(iter: &mut DbIterator) -> Vec<(Bytes, Bytes)> {
+        let mut result = vec![];
+        iter.seek_to_first().await.unwrap();
+        while iter.valid() {
+            result.push((iter.key(), iter.value()));
+            iter.next().await.unwrap();
+        }
+        result
+    }
+
+    async fn collect_backward(iter: &mut DbIterator) -> Vec<(Bytes, Bytes)> {
+        let mut result = vec![];
+        iter.seek_to_last().await.unwrap();
+        while iter.valid() {
+            result.push((iter.key(), iter.value()));
+            iter.prev().await.unwrap();
+        }
+        result.reverse();
+        result
+    }
+
+    fn pairs(pairs: &[(&'static str, &'static str)]) -> Vec<(Bytes, Bytes)> {
+        pairs
+            .iter()
+            .map(|&(key, value)| (key.into(), value.into()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_db_iter() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        let mut iter = db.iter(ReadOptions::default()).await.unwrap();
+        assert!(collect_forward(&mut iter).await.is_empty());
+        assert!(collect_backward(&mut iter).await.is_empty());
+
+        db.set("b".into(), "b1".into()).await.unwrap();
+        db.set("a".into(), "a1".into()).await.unwrap();
+        db.set("d".into(), "d1".into()).await.unwrap();
+        db.set("c".into(), "c1".into()).await.unwrap();
+        db.set("b".into(), "b2".into()).await.unwrap();
+        db.delete("c".into()).await.unwrap();
+        db.delete("e".into()).await.unwrap();
+
+        let mut iter = db.iter(ReadOptions::default()).await.unwrap();
+        // concurrent writes are invisible to the iterator
+        db.set("a".into(), "a2".into()).await.unwrap();
+        db.set("c".into(), "c2".into()).await.unwrap();
+        db.delete("d".into()).await.unwrap();
+
+        let expected = pairs(&[("a", "a1"), ("b", "b2"), ("d", "d1")]);
+        assert_eq!(collect_forward(&mut iter).await, expected);
+        assert_eq!(collect_backward(&mut iter).await, expected);
+
+        iter.seek("b").await.unwrap();
+        assert_eq!(iter.key(), Bytes::from("b"));
+        iter.seek("bb").await.unwrap();
+        assert_eq!(iter.key(), Bytes::from("d"));
+        iter.prev().await.unwrap();
+        assert_eq!(iter.key(), Bytes::from("b"));
+        iter.prev().await.unwrap();
+        assert_eq!(iter.key(), Bytes::from("a"));
+        iter.next().await.unwrap();
+        assert_eq!(iter.key(), Bytes::from("b"));
+        assert_eq!(iter.value(), Bytes::from("b2"));
+        iter.next().await.unwrap();
+        iter.next().await.unwrap();
+        assert!(!iter.valid());
+        iter.seek("e").await.unwrap();
+        assert!(!iter.valid());
+
+        let mut iter = db.iter(ReadOptions::default()).await.unwrap();
+        let expected = pairs(&[("a", "a2"), ("b", "b2"), ("c", "c2")]);
+        assert_eq!(collect_forward(&mut iter).await, expected);
+        assert_eq!(collect_backward(&mut iter).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_db_iter_snapshot() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        db.set("a".into(), "a1".into()).await.unwrap();
+        db.set("b".into(), "b1".into()).await.unwrap();
+        let snapshot = db.snapshot();
+        db.delete("a".into()).await.unwrap();
+        db.set("c".into(), "c1".into()).await.unwrap();
+
+        let mut iter = db
+            .iter(ReadOptions {
+                snapshot: Some(snapshot),
+                ..ReadOptions::default()
+            })
+            .await
+            .unwrap();
+        let expected = pairs(&[("a", "a1"), ("b", "b1")]);
+        assert_eq!(collect_forward(&mut iter).await, expected);
+        assert_eq!(collect_backward(&mut iter).await, expected);
+    }
+
+    #[tokio::test]
+    async fn test_db_range() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        for key in &["a", "b", "c", "d", "e"] {
+            db.set(Bytes::from(*key), Bytes::from(*key)).await.unwrap();
+        }
+        db.delete("c".into()).await.unwrap();
+
+        async fn keys(stream: impl Stream<Item = Result<(Bytes, Bytes)>>) -> Vec<Bytes> {
+            stream.map_ok(|(key, _)| key).try_collect().await.unwrap()
+        }
+        let range = db.range("b".."e").await.unwrap();
+        // writes after the range is created are invisible
+        db.set("bb".into(), "bb".into()).await.unwrap();
+        assert_eq!(keys(range).await, vec!["b", "d"]);
+        assert_eq!(
+            keys(db.range("b"..="e").await.unwrap()).await,
+            vec!["b", "bb", "d", "e"]
+        );
+        assert_eq!(
+            keys(db.range::<&str>(..).await.unwrap()).await,
+            vec!["a", "b", "bb", "d", "e"]
+        );
+        assert_eq!(keys(db.range(.."b").await.unwrap()).await, vec!["a"]);
+        assert_eq!(keys(db.range("c"..).await.unwrap()).await, vec!["d", "e"]);
+        assert_eq!(
+            keys(
+                db.range::<&str>((Bound::Excluded("bb"), Bound::Unbounded))
+                    .await
+                    .unwrap()
+            )
+            .await,
+            vec!["d", "e"]
+        );
+        assert!(keys(db.range("x"..).await.unwrap()).await.is_empty());
+    }
+
+    #[test]
+    fn test_prefix_successor() {
+        assert_eq!(prefix_successor(b"abc"), Some("abd".into()));
+        assert_eq!(prefix_successor(b"a\xff\xff"), Some("b".into()));
+        assert_eq!(prefix_successor(b"\xff\xff"), None);
+        assert_eq!(prefix_successor(b""), None);
+    }
+
+    #[tokio::test]
+    async fn test_db_scan_prefix() {
+        let fs = MemFileSystem::new();
+        let db = open_in_memory(&fs).await;
+        for key in &[
+            "tenant", "tenant/a", "tenant/b", "tenant/c", "tenant0", "tenanu/a", "other/a",
+        ] {
+            db.set(Bytes::from(*key), Bytes::from(*key)).await.unwrap();
+        }
+        db.delete("tenant/b".into()).await.unwrap();
+        db.set(
+            Bytes::from(&b"\xff\xff/a"[..]),
+            Bytes::from(&b"\xff\xff/a"[..]),
+        )
+        .await
+        .unwrap();
+
+        async fn keys(stream: impl Stream<Item = Result<(Bytes, Bytes)>>) -> Vec<Bytes> {
+            stream.map_ok(|(key, _)| key).try_collect().await.unwrap()
+        }
+        assert_eq!(
+            keys(db.scan_prefix("tenant/").await.unwrap()).await,
+            vec!["tenant/a", "tenant/c"]
+        );
+        assert_eq!(
+            keys(db.scan_prefix("tenant").await.unwrap()).await,
+            vec!["tenant", "tenant/a", "tenant/c", "tenant0"]
+        );
+        assert!(keys(db.scan_prefix("none/").await.unwrap())
+            .await
+            .is_empty());
+        assert_eq!(
+            keys(db.scan_prefix(b"\xff").await.unwrap()).await,
+            vec![Bytes::from(&b"\xff\xff/a"[..])]
+        );
+        assert_eq!(keys(db.scan_prefix("").await.unwrap()).await.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_db_reopen_large_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(dir.path()).await.unwrap();
+        for i in 0..20u8 {
+            let value = Bytes::from(vec![i; 10000]);
+            db.set(Bytes::from(vec![i]), value).await.unwrap();
+        }
+        drop(db);
+
+        let db = Db::open(dir.path()).await.unwrap();
+        for i in 0..20u8 {
+            let value = db.get([i]).await.unwrap().unwrap();
+            assert_eq!(value, Bytes::from(vec![i; 10000]));
+        }
+    }
+
+    /// wait until background flush finishes
+    async fn wait_for_flush(db: &Db) {
+        let _wal = db.inner.wal.lock().await;
+        db.inner.wait_for_flush().await.unwrap();
+    }
+
+    fn count_files(dir: &Path, ty: FileType) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|entry| {
+                let name = entry.as_ref().unwrap().file_name();
+                matches!(parse_file_name(name.to_str().unwrap()), Some((_, found)) if found == ty)
+            })
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_db_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            // keep all tables in level-0
+            level0_file_num_compaction_trigger: 1000,
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options.clone())
+            .await
+            .unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        for i in 0..1000 {
+            db.set(key(i), Bytes::from(format!("val{}", i)))
+                .await
+                .unwrap();
+        }
+        let snapshot = db.snapshot();
+        for i in (0..1000).step_by(3) {
+            db.delete(key(i)).await.unwrap();
+        }
+        wait_for_flush(&db).await;
+        let tables = db.inner.current().await.version.files(0).len();
+        assert!(tables > 1);
+        assert_eq!(count_files(dir.path(), FileType::Table), tables);
+        // WALs of flushed memtables are retired
+        assert_eq!(count_files(dir.path(), FileType::Log), 1);
+
+        let check = |db: Db| async move {
+            for i in 0..1000 {
+                let expected = if i % 3 == 0 {
+                    None
+                } else {
+                    Some(Bytes::from(format!("val{}", i)))
+                };
+                assert_eq!(db.get(key(i)).await.unwrap(), expected);
+            }
+            let mut iter = db.iter(ReadOptions::default()).await.unwrap();
+            iter.seek_to_first().await.unwrap();
+            for i in (0..1000).filter(|i| i % 3 != 0) {
+                assert_eq!(iter.key(), key(i));
+                iter.next().await.unwrap();
+            }
+            assert!(!iter.valid());
+            db
+        };
+        let db = check(db).await;
+
+        // versions in level-0 tables are still visible to old snapshots
+        let read_options = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        for i in (0..1000).step_by(3) {
+            assert_eq!(
+                db.get_with_options(&read_options, key(i)).await.unwrap(),
+                Some(Bytes::from(format!("val{}", i)))
+            );
+        }
+        drop(read_options);
+        drop(db);
+
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        assert_eq!(count_files(dir.path(), FileType::Log), 1);
+        // recovered memtable is written to another table, and the reopened db records it in a new
+        // MANIFEST
+        assert_eq!(db.inner.current().await.version.files(0).len(), tables + 1);
+        assert_eq!(count_files(dir.path(), FileType::Table), tables + 1);
+        assert_eq!(count_files(dir.path(), FileType::Manifest), 1);
+        let db = check(db).await;
+        assert_eq!(db.inner.last_sequence.load(Ordering::Acquire), 1334);
+        db.set(key(0), "new".into()).await.unwrap();
+        assert_eq!(db.get(key(0)).await.unwrap(), Some("new".into()));
+    }
+
+    async fn wait_for_compaction(db: &Db) {
+        while db.inner.compacting.load(Ordering::Acquire) {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            level0_file_num_compaction_trigger: 2,
+            max_bytes_for_level_base: 16 * 1024,
+            max_file_size: 4096,
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options.clone())
+            .await
+            .unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        let value = |i: usize, round: usize| Bytes::from(format!("val{}-{}", i, round));
+        for i in 0..500 {
+            db.set(key(i), value(i, 0)).await.unwrap();
+        }
+        let snapshot = db.snapshot();
+        for round in 1..4 {
+            for i in 0..500 {
+                db.set(key(i), value(i, round)).await.unwrap();
+            }
+        }
+        for i in (0..500).step_by(2) {
+            db.delete(key(i)).await.unwrap();
+        }
+        wait_for_flush(&db).await;
+        wait_for_compaction(&db).await;
+
+        let version = db.inner.current().await.version;
+        assert!(version.files(0).len() < 2);
+        assert!((2..NUM_LEVELS).any(|level| !version.files(level).is_empty()));
+        let tables: usize = (0..NUM_LEVELS)
+            .map(|level| version.files(level).len())
+            .sum();
+        assert_eq!(count_files(dir.path(), FileType::Table), tables);
+        drop(version);
+
+        let check = |db: Db| async move {
+            for i in 0..500 {
+                let expected = if i % 2 == 0 { None } else { Some(value(i, 3)) };
+                assert_eq!(db.get(key(i)).await.unwrap(), expected);
+            }
+            let mut iter = db.iter(ReadOptions::default()).await.unwrap();
+            iter.seek_to_first().await.unwrap();
+            for i in (0..500).filter(|i| i % 2 != 0) {
+                assert_eq!(iter.key(), key(i));
+                assert_eq!(iter.value(), value(i, 3));
+                iter.next().await.unwrap();
+            }
+            assert!(!iter.valid());
+            db
+        };
+        let db = check(db).await;
+
+        // shadowed versions are kept for old snapshots
+        let read_options = ReadOptions {
+            snapshot: Some(snapshot),
+            ..ReadOptions::default()
+        };
+        for i in 0..500 {
+            assert_eq!(
+                db.get_with_options(&read_options, key(i)).await.unwrap(),
+                Some(value(i, 0))
+            );
+        }
+        drop(read_options);
+        drop(db);
+
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        wait_for_compaction(&db).await;
+        check(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_db_universal_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            compaction_style: CompactionStyle::Universal,
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options.clone())
+            .await
+            .unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        let value = |i: usize, round: usize| Bytes::from(format!("val{}-{}", i, round));
+        for round in 0..4 {
+            for i in 0..500 {
+                db.set(key(i), value(i, round)).await.unwrap();
+            }
+        }
+        for i in (0..500).step_by(2) {
+            db.delete(key(i)).await.unwrap();
+        }
+        wait_for_flush(&db).await;
+        wait_for_compaction(&db).await;
+
+        let runs = |db: &Db| {
+            let db = db.inner.clone();
+            async move {
+                let version = db.current().await.version;
+                let levels = (1..NUM_LEVELS).filter(|&level| !version.files(level).is_empty());
+                version.files(0).len() + levels.count()
+            }
+        };
+        assert!(runs(&db).await <= options.universal_compaction.max_sorted_runs);
+        let check = |db: Db| async move {
+            for i in 0..500 {
+                let expected = if i % 2 == 0 { None } else { Some(value(i, 3)) };
+                assert_eq!(db.get(key(i)).await.unwrap(), expected);
+            }
+            let mut iter = db.iter(ReadOptions::default()).await.unwrap();
+            iter.seek_to_first().await.unwrap();
+            for i in (0..500).filter(|i| i % 2 != 0) {
+                assert_eq!(iter.key(), key(i));
+                iter.next().await.unwrap();
+            }
+            assert!(!iter.valid());
+            db
+        };
+        let db = check(db).await;
+        drop(db);
+
+        // switching style merges everything into the last level
+        let leveled = Options {
+            compaction_style: CompactionStyle::Leveled,
+            ..options.clone()
+        };
+        let db = Db::open_with_options(dir.path(), leveled).await.unwrap();
+        let version = db.inner.current().await.version;
+        assert!((0..NUM_LEVELS - 1).all(|level| version.files(level).is_empty()));
+        assert!(!version.files(NUM_LEVELS - 1).is_empty());
+        drop(version);
+        let db = check(db).await;
+        drop(db);
+
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        assert_eq!(runs(&db).await, 1);
+        check(db).await;
+    }
+
+    #[tokio::test]
+    async fn test_db_compact_range() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            // keep tables in level-0 until compacted manually
+            level0_file_num_compaction_trigger: 1000,
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        for i in 0..1000 {
+            db.set(key(i), Bytes::from(format!("val{}", i)))
+                .await
+                .unwrap();
+        }
+        for i in 0..900 {
+            db.delete(key(i)).await.unwrap();
+        }
+        db.set(key(999), "last".into()).await.unwrap();
+        let overlapping = |db: &Db, level: usize, begin: usize, end: usize| {
+            let db = db.inner.clone();
+            async move {
+                let version = db.current().await.version;
+                version
+                    .files(level)
+                    .iter()
+                    .filter(|file| {
+                        file.smallest.user_key() <= &key(end)[..] &&
+                            file.largest.user_key() >= &key(begin)[..]
+                    })
+                    .count()
+            }
+        };
+
+        // unflushed keys in the range are compacted too
+        let options = CompactRangeOptions {
+            exclusive_manual_compaction: false,
+        };
+        db.compact_range_with_options(&options, Some(&key(950)), Some(&key(2000)))
+            .await
+            .unwrap();
+        assert_eq!(db.inner.current().await.mem.approximate_memory_usage(), 0);
+        for level in 0..NUM_LEVELS - 1 {
+            assert_eq!(overlapping(&db, level, 950, 2000).await, 0);
+        }
+        assert!(overlapping(&db, NUM_LEVELS - 1, 950, 2000).await > 0);
+
+        db.compact_range(None, None).await.unwrap();
+        let version = db.inner.current().await.version;
+        assert!((0..NUM_LEVELS - 1).all(|level| version.files(level).is_empty()));
+        // deleted keys are dropped
+        let size = version.level_size(NUM_LEVELS - 1);
+        assert!(size < 4096, "{}", size);
+        drop(version);
+        assert_eq!(
+            count_files(dir.path(), FileType::Table),
+            db.inner.current().await.version.files(NUM_LEVELS - 1).len()
+        );
+        for i in 0..999 {
+            let expected = (i >= 900).then(|| Bytes::from(format!("val{}", i)));
+            assert_eq!(db.get(key(i)).await.unwrap(), expected);
+        }
+        assert_eq!(db.get(key(999)).await.unwrap(), Some("last".into()));
+    }
+
+    #[tokio::test]
+    async fn test_db_compact_range_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(dir.path()).await.unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        for i in 0..1000 {
+            db.set(key(i), Bytes::from(format!("val{}", i)))
+                .await
+                .unwrap();
+        }
+        for i in 0..1000 {
+            db.delete(key(i)).await.unwrap();
+        }
+        // a single table is rewritten rather than moved down, which drops all its entries
+        db.compact_range(None, None).await.unwrap();
+        let version = db.inner.current().await.version;
+        assert!((0..NUM_LEVELS).all(|level| version.files(level).is_empty()));
+        drop(version);
+        assert_eq!(count_files(dir.path(), FileType::Table), 0);
+        for i in 0..1000 {
+            assert!(db.get(key(i)).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_bloom_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::new(10))),
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        for i in (0..1000).step_by(2) {
+            db.set(key(i), Bytes::from(format!("val{}", i)))
+                .await
+                .unwrap();
+        }
+        wait_for_flush(&db).await;
+        wait_for_compaction(&db).await;
+        db.compact_range(None, None).await.unwrap();
+
+        let statistics = db.statistics();
+        for i in 0..1000 {
+            let expected = (i % 2 == 0).then(|| Bytes::from(format!("val{}", i)));
+            assert_eq!(db.get(key(i)).await.unwrap(), expected);
+        }
+        assert!(statistics.bloom_filter_useful() > 450);
+        assert!(statistics.bloom_filter_false_positive() < 50);
+    }
+
+    #[tokio::test]
+    async fn test_db_shared_block_cache() {
+        let cache = BlockCache::new(1 << 20);
+        let options = Options {
+            write_buffer_size: 4096,
+            block_cache: Some(cache.clone()),
+            ..Options::default()
+        };
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let mut dbs = vec![];
+        // same keys and file numbers in both dbs, but different values
+        for (i, dir) in dirs.iter().enumerate() {
+            let db = Db::open_with_options(dir.path(), options.clone())
+                .await
+                .unwrap();
+            for j in 0..200 {
+                db.set(format!("key{:03}", j).into(), format!("db{}", i).into())
+                    .await
+                    .unwrap();
+            }
+            db.compact_range(None, None).await.unwrap();
+            dbs.push(db);
+        }
+        for _ in 0..2 {
+            for (i, db) in dbs.iter().enumerate() {
+                for j in 0..200 {
+                    assert_eq!(
+                        db.get(format!("key{:03}", j)).await.unwrap(),
+                        Some(format!("db{}", i).into())
+                    );
+                }
+            }
+        }
+        assert!(cache.usage() > 0);
+        for db in &dbs {
+            assert!(db.statistics().block_cache_hit() > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_mmap_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            level0_file_num_compaction_trigger: 1000,
+            block_cache: Some(BlockCache::new(1 << 20)),
+            mmap_reads: true,
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        for i in 0..1000 {
+            db.set(key(i), Bytes::from(format!("val{}", i)))
+                .await
+                .unwrap();
+        }
+        wait_for_flush(&db).await;
+        let tables = db.inner.current().await.version.files(0).to_vec();
+        assert!(tables.len() > 1);
+        let value = db.get(key(0)).await.unwrap().unwrap();
+
+        // compaction removes tables still mapped by values read from them
+        db.compact_range(None, None).await.unwrap();
+        for table in &tables {
+            assert!(!dir.path().join(table_file_name(table.number)).exists());
+        }
+        assert_eq!(value, "val0");
+        for i in 0..1000 {
+            assert_eq!(
+                db.get(key(i)).await.unwrap(),
+                Some(Bytes::from(format!("val{}", i)))
+            );
+        }
+        // blocks of mapped tables skip block cache
+        let statistics = db.statistics();
+        assert_eq!(
+            statistics.block_cache_hit() + statistics.block_cache_miss(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_db_table_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            level0_file_num_compaction_trigger: 1000,
+            // keep only 2 tables open
+            max_open_files: 12,
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        for i in 0..1000 {
+            db.set(key(i), Bytes::from(format!("val{}", i)))
+                .await
+                .unwrap();
+        }
+        wait_for_flush(&db).await;
+        assert!(db.inner.current().await.version.files(0).len() > 2);
+        for _ in 0..2 {
+            for i in (0..1000).rev() {
+                assert_eq!(
+                    db.get(key(i)).await.unwrap(),
+                    Some(Bytes::from(format!("val{}", i)))
+                );
+            }
+        }
+
+        // tables of files removed by compaction are evicted
+        db.compact_range(None, None).await.unwrap();
+        for i in 0..1000 {
+            assert_eq!(
+                db.get(key(i)).await.unwrap(),
+                Some(Bytes::from(format!("val{}", i)))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_memtable_rep() {
+        for ty in [
+            MemTableRepType::BTree,
+            MemTableRepType::SkipList,
+            MemTableRepType::Vector,
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let options = Options {
+                write_buffer_size: 4096,
+                memtable_rep: ty,
+                ..Options::default()
+            };
+            let db = Db::open_with_options(dir.path(), options).await.unwrap();
+            let key = |i: usize| Bytes::from(format!("key{:04}", i));
+            for i in (0..500).rev() {
+                db.set(key(i), Bytes::from(format!("val{}", i)))
+                    .await
+                    .unwrap();
+            }
+            for i in (0..500).step_by(2) {
+                db.delete(key(i)).await.unwrap();
+            }
+            for i in 0..500 {
+                let expected = (i % 2 == 1).then(|| Bytes::from(format!("val{}", i)));
+                assert_eq!(db.get(key(i)).await.unwrap(), expected, "{:?}", ty);
+            }
+            let mut iter = db.iter(ReadOptions::default()).await.unwrap();
+            iter.seek_to_first().await.unwrap();
+            for i in (1..500).step_by(2) {
+                assert_eq!(iter.key(), key(i), "{:?}", ty);
+                iter.next().await.unwrap();
+            }
+            assert!(!iter.valid());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(dir.path()).await.unwrap();
+        assert!(matches!(
+            Db::open(dir.path()).await,
+            Err(DbError::VfsError(VfsError::LockError(_)))
+        ));
+        drop(db);
+        Db::open(dir.path()).await.unwrap();
+    }
+
+    /// a file system counting files created in [`TokioFileSystem`]
+    #[derive(Debug, Default)]
+    struct CountingFileSystem {
+        created: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl FileSystem for CountingFileSystem {
+        async fn open(
+            &self,
+            path: &Path,
+        ) -> std::result::Result<Box<dyn RandomAccessFile>, VfsError> {
+            TokioFileSystem.open(path).await
+        }
+
+        async fn create(
+            &self,
+            path: &Path,
+        ) -> std::result::Result<Box<dyn WritableFile>, VfsError> {
+            self.created.fetch_add(1, Ordering::Relaxed);
+            TokioFileSystem.create(path).await
+        }
+
+        async fn open_appendable(
+            &self,
+            path: &Path,
+        ) -> std::result::Result<Box<dyn WritableFile>, VfsError> {
+            self.created.fetch_add(1, Ordering::Relaxed);
+            TokioFileSystem.open_appendable(path).await
+        }
+
+        async fn rename(&self, from: &Path, to: &Path) -> std::result::Result<(), VfsError> {
+            TokioFileSystem.rename(from, to).await
+        }
+
+        async fn remove(&self, path: &Path) -> std::result::Result<(), VfsError> {
+            TokioFileSystem.remove(path).await
+        }
+
+        async fn list(&self, dir: &Path) -> std::result::Result<Vec<String>, VfsError> {
+            TokioFileSystem.list(dir).await
+        }
+
+        async fn create_dir_all(&self, dir: &Path) -> std::result::Result<(), VfsError> {
+            TokioFileSystem.create_dir_all(dir).await
+        }
+
+        async fn lock(&self, path: &Path) -> std::result::Result<Box<dyn FileLock>, VfsError> {
+            TokioFileSystem.lock(path).await
+        }
+
+        async fn sync_dir(&self, dir: &Path) -> std::result::Result<(), VfsError> {
+            TokioFileSystem.sync_dir(dir).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_custom_file_system() {
+        let dir = tempfile::tempdir().unwrap();
+        let fs = Arc::new(CountingFileSystem::default());
+        let options = Options {
+            file_system: fs.clone(),
+            write_buffer_size: 4096,
+            ..Options::default()
+        };
+        let db = Db::open_with_options(dir.path(), options.clone())
+            .await
+            .unwrap();
+        let created = fs.created.load(Ordering::Relaxed);
+        assert!(created > 0);
+        for i in 0..1000 {
+            db.set(
+                Bytes::from(format!("key{:04}", i)),
+                Bytes::from(format!("val{}", i)),
+            )
+            .await
+            .unwrap();
+        }
+        // flushes create WALs and tables through the file system
+        assert!(fs.created.load(Ordering::Relaxed) > created);
+        drop(db);
+
+        let db = Db::open_with_options(dir.path(), options).await.unwrap();
+        for i in 0..1000 {
+            assert_eq!(
+                db.get(format!("key{:04}", i)).await.unwrap(),
+                Some(Bytes::from(format!("val{}", i)))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_reopen_after_drop() {
+        // I/O on disk lets background work of a dropped db interleave with the reopen
+        let dir = tempfile::tempdir().unwrap();
+        let options = Options {
+            write_buffer_size: 4096,
+            level0_file_num_compaction_trigger: 2,
+            ..Options::default()
+        };
+        // unpadded keys of each round spread over the key space, so compactions take a while
+        let key = |i: usize| Bytes::from(format!("key{}", i));
+        for round in 0..5 {
+            let db = Db::open_with_options(dir.path(), options.clone())
+                .await
+                .unwrap();
+            for i in round * 300..(round + 1) * 300 {
+                db.set(key(i), Bytes::from(format!("val{}", i)))
+                    .await
+                    .unwrap();
+            }
+            // flushes and compactions still running must not touch files of the next db
+            match round % 2 {
+                0 => drop(db),
+                _ => db.close().await,
+            }
+            let db = Db::open_with_options(dir.path(), options.clone())
+                .await
+                .unwrap();
+            for i in 0..(round + 1) * 300 {
+                assert_eq!(
+                    db.get(key(i)).await.unwrap(),
+                    Some(Bytes::from(format!("val{}", i)))
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_in_memory() {
+        let fs = MemFileSystem::new();
+        let options = Options {
+            file_system: Arc::new(fs.clone()),
+            write_buffer_size: 4096,
+            level0_file_num_compaction_trigger: 2,
+            ..Options::default()
+        };
+        let db = Db::open_with_options("db", options.clone()).await.unwrap();
+        let key = |i: usize| Bytes::from(format!("key{:04}", i));
+        for i in 0..2000 {
+            db.set(key(i % 500), Bytes::from(format!("val{}", i)))
+                .await
+                .unwrap();
+        }
+        // flush and compaction still running finish before the db is reopened
+        drop(db);
+
+        // WAL, tables and MANIFEST all live in memory
+        let names = fs.list(Path::new("db")).await.unwrap();
+        for ty in [FileType::Log, FileType::Table, FileType::Manifest] {
+            assert!(names
+                .iter()
+                .any(|name| matches!(parse_file_name(name), Some((_, found)) if found == ty)));
+        }
+        assert!(!Path::new("db").exists());
+
+        let db = Db::open_with_options("db", options).await.unwrap();
+        for i in 0..500 {
+            assert_eq!(
+                db.get(key(i)).await.unwrap(),
+                Some(Bytes::from(format!("val{}", i + 1500)))
+            );
+        }
+    }
+
+    /// options of a db on `fs` which flushes and compacts often
+    fn crash_options(fs: &FaultInjectionFileSystem) -> Options {
+        Options {
+            file_system: Arc::new(fs.clone()),
+            write_buffer_size: 2048,
+            level0_file_num_compaction_trigger: 2,
+            max_file_size: 4096,
+            ..Options::default()
+        }
+    }
+
+    /// values written to every key since the last recovery, a recovered key must hold one of
+    /// them at or after its last synced write
+    #[derive(Default)]
+    struct CrashModel {
+        history: HashMap<Bytes, Vec<Option<Bytes>>>,
+        synced:  HashMap<Bytes, usize>,
+    }
+
+    impl CrashModel {
+        fn write(&mut self, key: &Bytes, value: Option<Bytes>, sync: bool) {
+            let history = self.history.entry(key.clone()).or_default();
+            history.push(value);
+            if sync {
+                self.synced.insert(key.clone(), history.len() - 1);
+            }
+        }
+
+        /// check state of `db` recovered from a crash, which is durable from now on
+        async fn recover(&mut self, db: &Db) {
+            for (key, history) in &mut self.history {
+                let found = db.get(key).await.unwrap();
+                let durable = match self.synced.get(key) {
+                    Some(&synced) => &history[synced..],
+                    // the first write may be lost as well
+                    None => &history[..],
+                };
+                assert!(
+                    durable.contains(&found) || (!self.synced.contains_key(key) && found.is_none()),
+                    "{:?} is recovered as {:?}, which is not in {:?}",
+                    key,
+                    found,
+                    durable
+                );
+                *history = vec![found];
+            }
+            self.synced = self.history.keys().map(|key| (key.clone(), 0)).collect();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_db_crash_recovery() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut rand = move |n: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % n
+        };
+        for _ in 0..10 {
+            let mut fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+            let mut model = CrashModel::default();
+            for _ in 0..4 {
+                let db = Db::open_with_options("db", crash_options(&fs))
+                    .await
+                    .unwrap();
+                model.recover(&db).await;
+                // crash at a random point, maybe in the middle of a flush or compaction
+                for _ in 0..rand(1000) {
+                    let key = Bytes::from(format!("key{:03}", rand(100)));
+                    let value = (rand(5) != 0).then(|| Bytes::from(format!("val{}", rand(1000))));
+                    let sync = rand(10) == 0;
+                    let mut batch = WriteBatch::new();
+                    match &value {
+                        Some(value) => batch.put(key.clone(), value.clone()),
+                        None => batch.delete(key.clone()),
+                    }
+                    db.write_with_options(&WriteOptions { sync }, batch)
+                        .await
+                        .unwrap();
+                    model.write(&key, value, sync);
+                    if rand(20) == 0 {
+                        tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+                    }
+                }
+                fs = fs.crash().await.unwrap();
+                drop(db);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_crash_after_wal_switch() {
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        let db = Db::open_with_options("db", crash_options(&fs))
+            .await
+            .unwrap();
+        let wal = db.inner.wal.lock().await.number();
+        // a full memtable makes the next write switch to a new WAL
+        db.set("key1".into(), "v".repeat(4096).into())
+            .await
+            .unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put("key2".into(), "val2".into());
+        db.write_with_options(&WriteOptions { sync: true }, batch)
+            .await
+            .unwrap();
+        assert_ne!(db.inner.wal.lock().await.number(), wal);
+
+        // crash before the flush of the old memtable gets to run
+        let fs = fs.crash().await.unwrap();
+        drop(db);
+        let db = Db::open_with_options("db", crash_options(&fs))
+            .await
+            .unwrap();
+        assert_eq!(db.get("key2").await.unwrap(), Some("val2".into()));
+    }
+
+    #[tokio::test]
+    async fn test_db_write_error() {
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        let db = Db::open_with_options("db", crash_options(&fs))
+            .await
+            .unwrap();
+        let sync = WriteOptions { sync: true };
+        db.set("key1".into(), "val1".into()).await.unwrap();
+        fs.inject_error(FileOp::Sync);
+        let mut batch = WriteBatch::new();
+        batch.put("key2".into(), "val2".into());
+        assert!(db.write_with_options(&sync, batch).await.is_err());
+        // writes fail after a WAL error, even when the error is gone
+        fs.clear_errors();
+        assert!(db.set("key3".into(), "val3".into()).await.is_err());
+        drop(db);
+
+        let db = Db::open_with_options("db", crash_options(&fs))
+            .await
+            .unwrap();
+        assert_eq!(db.get("key1").await.unwrap(), Some("val1".into()));
+        assert!(db.get("key3").await.unwrap().is_none());
+        db.set("key3".into(), "val3".into()).await.unwrap();
+        assert_eq!(db.get("key3").await.unwrap(), Some("val3".into()));
+    }
+
+    #[tokio::test]
+    async fn test_db_corruption() {
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        let options = crash_options(&fs);
+        let paranoid = Options {
+            paranoid_checks: true,
+            ..options.clone()
+        };
+        let db = Db::open_with_options("db", options.clone()).await.unwrap();
+        let sync = WriteOptions { sync: true };
+        let mut batch = WriteBatch::new();
+        batch.put("key".into(), "val".into());
+        db.write_with_options(&sync, batch.clone()).await.unwrap();
+        let wal = db.inner.wal.lock().await.number();
+        drop(db);
+
+        // a corrupted WAL record is dropped as torn by a crash, unless paranoid checks fail it
+        let wal = Path::new("db").join(log_file_name(wal));
+        fs.corrupt(&wal, 8, 1).await.unwrap();
+        assert!(Db::open_with_options("db", paranoid.clone()).await.is_err());
+        let db = Db::open_with_options("db", options).await.unwrap();
+        assert_eq!(db.get("key").await.unwrap(), None);
+        db.write_with_options(&sync, batch).await.unwrap();
+        drop(db);
+
+        // recovery wrote the WAL to a table, whose corruption is found by reads
+        let db = Db::open_with_options("db", paranoid.clone()).await.unwrap();
+        assert_eq!(db.get("key").await.unwrap(), Some("val".into()));
+        let table = db.inner.current().await.version.files(0)[0].number;
+        drop(db);
+        fs.corrupt(&Path::new("db").join(table_file_name(table)), 0, 1)
+            .await
+            .unwrap();
+        let db = Db::open_with_options("db", paranoid).await.unwrap();
+        assert!(matches!(
+            db.get("key").await,
+            Err(DbError::Corruption { .. })
+        ));
+    }
 }
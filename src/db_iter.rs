@@ -0,0 +1,223 @@
+//! user facing iterator over db
+
+use bytes::Bytes;
+
+use crate::{
+    db::DbError,
+    format::{
+        extract_user_key,
+        InternalKey,
+        SequenceNumber,
+        ValueType,
+    },
+    iterator::InternalIterator,
+    snapshot::Snapshot,
+};
+
+type Result<T> = std::result::Result<T, DbError>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// a seekable iterator over live keys of db in key order
+///
+/// Iterator reads from the snapshot it was created with, so it sees a consistent view of db no
+/// matter what is written while it is used. Shadowed versions and deleted keys are skipped.
+pub struct DbIterator {
+    iter:        Box<dyn InternalIterator>,
+    // keep versions visible to this iterator alive
+    snapshot:    Snapshot,
+    // when moving forward, the underlying iterator is positioned at the current entry, when moving
+    // backward, it is positioned before all entries of current key, which is kept in `saved_key`
+    direction:   Direction,
+    valid:       bool,
+    saved_key:   Bytes,
+    saved_value: Bytes,
+}
+
+impl DbIterator {
+    pub(crate) fn new(iter: Box<dyn InternalIterator>, snapshot: Snapshot) -> Self {
+        DbIterator {
+            iter,
+            snapshot,
+            direction: Direction::Forward,
+            valid: false,
+            saved_key: Bytes::new(),
+            saved_value: Bytes::new(),
+        }
+    }
+
+    /// check if iterator is positioned at an entry
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+
+    /// key of current entry, iterator must be valid
+    pub fn key(&self) -> Bytes {
+        assert!(self.valid, "iterator is not valid");
+        match self.direction {
+            Direction::Forward => user_key_of(&self.iter.key()),
+            Direction::Reverse => self.saved_key.clone(),
+        }
+    }
+
+    /// value of current entry, iterator must be valid
+    pub fn value(&self) -> Bytes {
+        assert!(self.valid, "iterator is not valid");
+        match self.direction {
+            Direction::Forward => self.iter.value(),
+            Direction::Reverse => self.saved_value.clone(),
+        }
+    }
+
+    /// position at the first key
+    pub async fn seek_to_first(&mut self) -> Result<()> {
+        self.direction = Direction::Forward;
+        self.saved_value = Bytes::new();
+        self.iter.seek_to_first().await?;
+        self.find_next_user_entry(None).await
+    }
+
+    /// position at the last key
+    pub async fn seek_to_last(&mut self) -> Result<()> {
+        self.direction = Direction::Reverse;
+        self.saved_value = Bytes::new();
+        self.iter.seek_to_last().await?;
+        self.find_prev_user_entry().await
+    }
+
+    /// position at the first key at or past `target`
+    pub async fn seek(&mut self, target: impl AsRef<[u8]>) -> Result<()> {
+        self.direction = Direction::Forward;
+        self.saved_value = Bytes::new();
+        let target = InternalKey::new(target.as_ref(), self.sequence(), ValueType::FOR_SEEK);
+        self.iter.seek(target.encoded()).await?;
+        self.find_next_user_entry(None).await
+    }
+
+    /// move to the next key, iterator must be valid
+    pub async fn next(&mut self) -> Result<()> {
+        assert!(self.valid, "iterator is not valid");
+        let skip = match self.direction {
+            Direction::Reverse => {
+                // underlying iterator is before entries of current key, move into them, they are
+                // skipped below
+                self.direction = Direction::Forward;
+                if self.iter.valid() {
+                    self.iter.next().await?;
+                } else {
+                    self.iter.seek_to_first().await?;
+                }
+                std::mem::take(&mut self.saved_key)
+            }
+            Direction::Forward => {
+                let skip = user_key_of(&self.iter.key());
+                self.iter.next().await?;
+                skip
+            }
+        };
+        self.find_next_user_entry(Some(skip)).await
+    }
+
+    /// move to the previous key, iterator must be valid
+    pub async fn prev(&mut self) -> Result<()> {
+        assert!(self.valid, "iterator is not valid");
+        if self.direction == Direction::Forward {
+            // underlying iterator is at current entry, move before all entries of current key
+            self.saved_key = user_key_of(&self.iter.key());
+            loop {
+                self.iter.prev().await?;
+                if !self.iter.valid() {
+                    self.valid = false;
+                    self.saved_key = Bytes::new();
+                    self.saved_value = Bytes::new();
+                    return Ok(());
+                }
+                if extract_user_key(&self.iter.key()) < &self.saved_key[..] {
+                    break;
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.find_prev_user_entry().await
+    }
+
+    /// move forward to the first visible value, skipping versions of `skip` and deleted keys
+    async fn find_next_user_entry(&mut self, mut skip: Option<Bytes>) -> Result<()> {
+        while self.iter.valid() {
+            let key = self.iter.key();
+            let ikey = parse_internal_key(&key)?;
+            if ikey.sequence() <= self.sequence() {
+                match ikey.value_type() {
+                    ValueType::Deletion => {
+                        // hide all older versions of this key
+                        skip = Some(Bytes::copy_from_slice(ikey.user_key()));
+                    }
+                    ValueType::Value => {
+                        let hidden = matches!(&skip, Some(skip) if ikey.user_key() <= &skip[..]);
+                        if !hidden {
+                            self.valid = true;
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            self.iter.next().await?;
+        }
+        self.valid = false;
+        Ok(())
+    }
+
+    /// move backward to the newest visible version of the previous key, leaving the underlying
+    /// iterator before all of its entries
+    async fn find_prev_user_entry(&mut self) -> Result<()> {
+        let mut value_type = ValueType::Deletion;
+        while self.iter.valid() {
+            let key = self.iter.key();
+            let ikey = parse_internal_key(&key)?;
+            if ikey.sequence() <= self.sequence() {
+                if value_type != ValueType::Deletion && ikey.user_key() < &self.saved_key[..] {
+                    // found a live value of the key after this one
+                    break;
+                }
+                value_type = ikey.value_type();
+                match value_type {
+                    ValueType::Deletion => {
+                        self.saved_key = Bytes::new();
+                        self.saved_value = Bytes::new();
+                    }
+                    ValueType::Value => {
+                        self.saved_key = Bytes::copy_from_slice(ikey.user_key());
+                        self.saved_value = self.iter.value();
+                    }
+                }
+            }
+            self.iter.prev().await?;
+        }
+
+        if value_type == ValueType::Deletion {
+            self.valid = false;
+            self.saved_key = Bytes::new();
+            self.saved_value = Bytes::new();
+            self.direction = Direction::Forward;
+        } else {
+            self.valid = true;
+        }
+        Ok(())
+    }
+
+    fn sequence(&self) -> SequenceNumber {
+        self.snapshot.sequence()
+    }
+}
+
+fn user_key_of(key: &Bytes) -> Bytes {
+    key.slice(..extract_user_key(key).len())
+}
+
+fn parse_internal_key(key: &Bytes) -> Result<InternalKey> {
+    InternalKey::decode(key.clone()).ok_or(DbError::InvalidInternalKeyError)
+}
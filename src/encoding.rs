@@ -7,6 +7,7 @@ use bytes::{
 pub trait BufMutExt: BufMut {
     fn put_var_u32_le(&mut self, n: u32);
     fn put_var_u64_le(&mut self, n: u64);
+    fn put_length_prefixed_slice(&mut self, data: &[u8]);
 }
 
 const B: u8 = 1 << 7;
@@ -51,6 +52,11 @@ impl<T: BufMut> BufMutExt for T {
         }
         self.put_u8(b.lowest_u8());
     }
+
+    fn put_length_prefixed_slice(&mut self, data: &[u8]) {
+        self.put_var_u32_le(data.len() as u32);
+        self.put_slice(data);
+    }
 }
 
 trait UxExt {
@@ -72,6 +78,7 @@ impl UxExt for u64 {
 pub trait BytesExt {
     fn get_var_u32_le(&mut self) -> Option<u32>;
     fn get_var_u64_le(&mut self) -> Option<u64>;
+    fn get_length_prefixed_slice(&mut self) -> Option<Bytes>;
 }
 
 impl BytesExt for Bytes {
@@ -112,6 +119,14 @@ impl BytesExt for Bytes {
         }
         Some(ret)
     }
+
+    fn get_length_prefixed_slice(&mut self) -> Option<Bytes> {
+        let len = self.get_var_u32_le()? as usize;
+        if self.len() < len {
+            return None;
+        }
+        Some(self.split_to(len))
+    }
 }
 
 #[cfg(test)]
@@ -157,4 +172,26 @@ mod tests {
         }
         assert!(buf.get_var_u64_le().is_none());
     }
+
+    #[test]
+    fn test_length_prefixed_slice() {
+        let mut buf = BytesMut::new();
+        buf.put_length_prefixed_slice(b"");
+        buf.put_length_prefixed_slice(b"foo");
+        buf.put_length_prefixed_slice(&[7u8; 300]);
+        let mut buf = buf.freeze();
+        assert_eq!(buf.get_length_prefixed_slice(), Some(Bytes::new()));
+        assert_eq!(buf.get_length_prefixed_slice(), Some(Bytes::from("foo")));
+        assert_eq!(
+            buf.get_length_prefixed_slice(),
+            Some(Bytes::from(vec![7u8; 300]))
+        );
+        assert!(buf.get_length_prefixed_slice().is_none());
+
+        // length larger than the remaining data
+        let mut buf = BytesMut::new();
+        buf.put_var_u32_le(10);
+        buf.put_slice(b"short");
+        assert!(buf.freeze().get_length_prefixed_slice().is_none());
+    }
 }
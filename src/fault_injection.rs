@@ -0,0 +1,526 @@
+//! file system injecting faults, for crash tests
+//!
+//! [`FaultInjectionFileSystem`] wraps another file system and tracks data of every file written
+//! since its last sync, along with files created, renamed and removed since the last sync of their
+//! directory. A crash drops that data and undoes those operations like a power loss does, I/O
+//! errors and corrupted bytes may be injected as well.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        atomic::{
+            AtomicBool,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::sync::RwLock;
+
+use crate::vfs::{
+    FileLock,
+    FileSystem,
+    RandomAccessFile,
+    VfsError,
+    WritableFile,
+};
+
+type Result<T> = std::result::Result<T, VfsError>;
+
+/// operation of a file system an error may be injected to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileOp {
+    Open,
+    Read,
+    Create,
+    Append,
+    Sync,
+    Rename,
+    Remove,
+    List,
+    Lock,
+    SyncDir,
+}
+
+/// file system wrapping `base`, whose unsynced data is dropped by [`Self::crash`]
+///
+/// Every crash starts a new generation of the file system, all files and handles of the old
+/// generation fail from then on, like those of a dead process.
+#[derive(Clone, Debug)]
+pub struct FaultInjectionFileSystem {
+    state: Arc<FaultState>,
+    // unset once this generation crashes
+    alive: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+struct FaultState {
+    base:    Arc<dyn FileSystem>,
+    // held shared by every operation and exclusively by a crash, so that no operation of the
+    // crashed generation lands after it
+    gate:    RwLock<()>,
+    // synced size of files written since the file system is created, other files are synced
+    synced:  Mutex<HashMap<PathBuf, u64>>,
+    // operations on directories not synced since, in order
+    dir_ops: Mutex<Vec<DirOp>>,
+    errors:  Mutex<HashSet<FileOp>>,
+}
+
+/// operation on entries of a directory, which is lost by a crash until the directory is synced
+#[derive(Debug)]
+enum DirOp {
+    Create(PathBuf),
+    // `replaced` is the synced contents of file `to` before rename
+    Rename {
+        from:     PathBuf,
+        to:       PathBuf,
+        replaced: Option<Bytes>,
+    },
+    // synced contents of the removed file
+    Remove(PathBuf, Bytes),
+}
+
+impl DirOp {
+    /// path whose directory holds the operation, renames are expected to stay in a directory
+    fn path(&self) -> &Path {
+        match self {
+            DirOp::Create(path) | DirOp::Remove(path, _) => path,
+            DirOp::Rename { to, .. } => to,
+        }
+    }
+}
+
+impl FaultInjectionFileSystem {
+    pub fn new(base: Arc<dyn FileSystem>) -> Self {
+        FaultInjectionFileSystem {
+            state: Arc::new(FaultState {
+                base,
+                gate: RwLock::new(()),
+                synced: Default::default(),
+                dir_ops: Default::default(),
+                errors: Default::default(),
+            }),
+            alive: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// make every following `op` fail until [`Self::clear_errors`]
+    pub fn inject_error(&self, op: FileOp) {
+        self.state.errors.lock().unwrap().insert(op);
+    }
+
+    pub fn clear_errors(&self) {
+        self.state.errors.lock().unwrap().clear();
+    }
+
+    /// simulate a power loss, which drops data of every file written since its last sync and
+    /// undoes operations on every directory since its last sync
+    ///
+    /// Return the file system as seen after reboot, while this one and all its files fail from
+    /// now on.
+    pub async fn crash(&self) -> Result<Self> {
+        let _gate = self.state.gate.write().await;
+        self.alive.store(false, Ordering::Release);
+        let state = &self.state;
+        let base = &state.base;
+        let dir_ops = std::mem::take(&mut *state.dir_ops.lock().unwrap());
+        for op in dir_ops.into_iter().rev() {
+            match op {
+                DirOp::Create(path) => {
+                    base.remove(&path).await?;
+                    state.synced.lock().unwrap().remove(&path);
+                }
+                DirOp::Rename { from, to, replaced } => {
+                    base.rename(&to, &from).await?;
+                    let size = state.synced.lock().unwrap().remove(&to);
+                    if let Some(size) = size {
+                        state.synced.lock().unwrap().insert(from, size);
+                    }
+                    if let Some(data) = replaced {
+                        base.create(&to).await?.append(&data).await?;
+                    }
+                }
+                DirOp::Remove(path, data) => base.create(&path).await?.append(&data).await?,
+            }
+        }
+        let paths: Vec<_> = state.synced.lock().unwrap().keys().cloned().collect();
+        for path in paths {
+            if let Some(data) = state.synced_data(&path).await? {
+                base.create(&path).await?.append(&data).await?;
+            }
+        }
+        state.synced.lock().unwrap().clear();
+        Ok(FaultInjectionFileSystem {
+            state: self.state.clone(),
+            alive: Arc::new(AtomicBool::new(true)),
+        })
+    }
+
+    /// flip every bit of `len` bytes at `offset` of file `path`, as if they rot on disk
+    pub async fn corrupt(&self, path: &Path, offset: u64, len: usize) -> Result<()> {
+        let _gate = self.state.gate.read().await;
+        let base = &self.state.base;
+        let file = base.open(path).await?;
+        let mut data = file.read_at(0, file.size().await? as usize).await?.to_vec();
+        for byte in &mut data[offset as usize..][..len] {
+            *byte = !*byte;
+        }
+        base.create(path).await?.append(&data).await?;
+        Ok(())
+    }
+}
+
+impl FaultState {
+    /// fail `op` if `alive` has crashed or an error is injected to it
+    fn check(&self, alive: &AtomicBool, op: FileOp) -> Result<()> {
+        if !alive.load(Ordering::Acquire) {
+            return Err(io::Error::other("file system crashed").into());
+        }
+        if self.errors.lock().unwrap().contains(&op) {
+            return Err(io::Error::other(format!("injected error on {:?}", op)).into());
+        }
+        Ok(())
+    }
+
+    /// contents of file `path` which survive a crash, `None` if it doesn't exist
+    async fn synced_data(&self, path: &Path) -> Result<Option<Bytes>> {
+        let file = match self.base.open(path).await {
+            Ok(file) => file,
+            Err(VfsError::IoError(err)) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+        let size = file.size().await?;
+        let synced = self.synced.lock().unwrap().get(path).copied();
+        let size = synced.map_or(size, |synced| synced.min(size));
+        Ok(Some(file.read_at(0, size as usize).await?))
+    }
+
+    async fn exists(&self, path: &Path) -> Result<bool> {
+        Ok(self.synced_data(path).await?.is_some())
+    }
+
+    fn push_dir_op(&self, op: DirOp) {
+        self.dir_ops.lock().unwrap().push(op);
+    }
+}
+
+#[async_trait]
+impl FileSystem for FaultInjectionFileSystem {
+    async fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Open)?;
+        Ok(Box::new(FaultRandomAccessFile {
+            file:  self.state.base.open(path).await?,
+            state: self.state.clone(),
+            alive: self.alive.clone(),
+        }))
+    }
+
+    async fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Create)?;
+        let existed = self.state.exists(path).await?;
+        let file = self.state.base.create(path).await?;
+        self.state.synced.lock().unwrap().insert(path.to_owned(), 0);
+        if !existed {
+            self.state.push_dir_op(DirOp::Create(path.to_owned()));
+        }
+        Ok(self.writable(path, file))
+    }
+
+    async fn open_appendable(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Create)?;
+        let existed = self.state.exists(path).await?;
+        let file = self.state.base.open_appendable(path).await?;
+        self.state
+            .synced
+            .lock()
+            .unwrap()
+            .entry(path.to_owned())
+            .or_insert(file.size());
+        if !existed {
+            self.state.push_dir_op(DirOp::Create(path.to_owned()));
+        }
+        Ok(self.writable(path, file))
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Rename)?;
+        let replaced = self.state.synced_data(to).await?;
+        self.state.base.rename(from, to).await?;
+        let mut synced = self.state.synced.lock().unwrap();
+        match synced.remove(from) {
+            Some(size) => synced.insert(to.to_owned(), size),
+            None => synced.remove(to),
+        };
+        drop(synced);
+        self.state.push_dir_op(DirOp::Rename {
+            from: from.to_owned(),
+            to: to.to_owned(),
+            replaced,
+        });
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Remove)?;
+        let data = self.state.synced_data(path).await?;
+        self.state.base.remove(path).await?;
+        self.state.synced.lock().unwrap().remove(path);
+        // the file exists, otherwise removing it fails
+        self.state
+            .push_dir_op(DirOp::Remove(path.to_owned(), data.unwrap()));
+        Ok(())
+    }
+
+    async fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::List)?;
+        self.state.base.list(dir).await
+    }
+
+    async fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Create)?;
+        self.state.base.create_dir_all(dir).await
+    }
+
+    async fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Lock)?;
+        self.state.base.lock(path).await
+    }
+
+    async fn sync_dir(&self, dir: &Path) -> Result<()> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::SyncDir)?;
+        self.state.base.sync_dir(dir).await?;
+        self.state
+            .dir_ops
+            .lock()
+            .unwrap()
+            .retain(|op| op.path().parent() != Some(dir));
+        Ok(())
+    }
+}
+
+impl FaultInjectionFileSystem {
+    fn writable(&self, path: &Path, file: Box<dyn WritableFile>) -> Box<dyn WritableFile> {
+        Box::new(FaultWritableFile {
+            file,
+            path: path.to_owned(),
+            state: self.state.clone(),
+            alive: self.alive.clone(),
+        })
+    }
+}
+
+/// a file of [`FaultInjectionFileSystem`] open for reading
+struct FaultRandomAccessFile {
+    file:  Box<dyn RandomAccessFile>,
+    state: Arc<FaultState>,
+    alive: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl RandomAccessFile for FaultRandomAccessFile {
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Read)?;
+        self.file.read_at(offset, len).await
+    }
+
+    async fn size(&self) -> Result<u64> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Read)?;
+        self.file.size().await
+    }
+
+    fn path(&self) -> &Path {
+        self.file.path()
+    }
+}
+
+/// a file of [`FaultInjectionFileSystem`] open for appending
+struct FaultWritableFile {
+    file:  Box<dyn WritableFile>,
+    path:  PathBuf,
+    state: Arc<FaultState>,
+    alive: Arc<AtomicBool>,
+}
+
+#[async_trait]
+impl WritableFile for FaultWritableFile {
+    async fn append(&mut self, data: &[u8]) -> Result<()> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Append)?;
+        self.file.append(data).await
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        let _gate = self.state.gate.read().await;
+        self.state.check(&self.alive, FileOp::Sync)?;
+        self.file.sync().await?;
+        // a file renamed after it's opened is synced by its new name, which isn't tracked
+        if let Some(synced) = self.state.synced.lock().unwrap().get_mut(&self.path) {
+            *synced = self.file.size();
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.file.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_file_system::MemFileSystem;
+
+    async fn read_all(fs: &dyn FileSystem, path: &str) -> Bytes {
+        let file = fs.open(Path::new(path)).await.unwrap();
+        file.read_at(0, file.size().await.unwrap() as usize)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_crash() {
+        let base = MemFileSystem::new();
+        base.create_dir_all(Path::new("db")).await.unwrap();
+        base.create(Path::new("db/old"))
+            .await
+            .unwrap()
+            .append(b"old")
+            .await
+            .unwrap();
+        let fs = FaultInjectionFileSystem::new(Arc::new(base));
+
+        let mut synced = fs.create(Path::new("db/synced")).await.unwrap();
+        synced.append(b"hello").await.unwrap();
+        synced.sync().await.unwrap();
+        synced.append(b" world").await.unwrap();
+        let mut renamed = fs.create(Path::new("db/temp")).await.unwrap();
+        renamed.append(b"renamed").await.unwrap();
+        renamed.sync().await.unwrap();
+        fs.rename(Path::new("db/temp"), Path::new("db/renamed"))
+            .await
+            .unwrap();
+        fs.create(Path::new("db/unsynced"))
+            .await
+            .unwrap()
+            .append(b"lost")
+            .await
+            .unwrap();
+        let mut appended = fs.open_appendable(Path::new("db/old")).await.unwrap();
+        appended.append(b" and new").await.unwrap();
+        fs.sync_dir(Path::new("db")).await.unwrap();
+        assert_eq!(read_all(&fs, "db/synced").await, "hello world");
+
+        let reader = fs.open(Path::new("db/synced")).await.unwrap();
+        let rebooted = fs.crash().await.unwrap();
+        assert_eq!(read_all(&rebooted, "db/synced").await, "hello");
+        assert_eq!(read_all(&rebooted, "db/renamed").await, "renamed");
+        assert_eq!(read_all(&rebooted, "db/unsynced").await, "");
+        assert_eq!(read_all(&rebooted, "db/old").await, "old");
+
+        // the crashed generation is dead
+        assert!(fs.open(Path::new("db/synced")).await.is_err());
+        assert!(reader.read_at(0, 1).await.is_err());
+        assert!(synced.append(b"!").await.is_err());
+        assert_eq!(read_all(&rebooted, "db/synced").await, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_crash_dir() {
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        fs.create_dir_all(Path::new("db")).await.unwrap();
+        for (name, data) in [("db/renamed", "renamed"), ("db/removed", "removed")] {
+            let mut file = fs.create(Path::new(name)).await.unwrap();
+            file.append(data.as_bytes()).await.unwrap();
+            file.sync().await.unwrap();
+        }
+        let mut current = fs.create(Path::new("db/current")).await.unwrap();
+        current.append(b"old").await.unwrap();
+        current.sync().await.unwrap();
+        fs.sync_dir(Path::new("db")).await.unwrap();
+
+        let mut created = fs.create(Path::new("db/created")).await.unwrap();
+        created.append(b"created").await.unwrap();
+        created.sync().await.unwrap();
+        fs.rename(Path::new("db/renamed"), Path::new("db/moved"))
+            .await
+            .unwrap();
+        fs.remove(Path::new("db/removed")).await.unwrap();
+        // replace a file with a synced temp file, like CURRENT
+        let mut temp = fs.create(Path::new("db/temp")).await.unwrap();
+        temp.append(b"new").await.unwrap();
+        temp.sync().await.unwrap();
+        fs.rename(Path::new("db/temp"), Path::new("db/current"))
+            .await
+            .unwrap();
+        assert_eq!(read_all(&fs, "db/current").await, "new");
+
+        let rebooted = fs.crash().await.unwrap();
+        let mut names = rebooted.list(Path::new("db")).await.unwrap();
+        names.sort();
+        assert_eq!(names, ["current", "removed", "renamed"]);
+        assert_eq!(read_all(&rebooted, "db/current").await, "old");
+        assert_eq!(read_all(&rebooted, "db/removed").await, "removed");
+        assert_eq!(read_all(&rebooted, "db/renamed").await, "renamed");
+
+        // operations followed by a sync of their directory survive
+        let fs = rebooted;
+        fs.rename(Path::new("db/renamed"), Path::new("db/moved"))
+            .await
+            .unwrap();
+        fs.remove(Path::new("db/removed")).await.unwrap();
+        fs.sync_dir(Path::new("db")).await.unwrap();
+        fs.create(Path::new("db/unsynced")).await.unwrap();
+        let rebooted = fs.crash().await.unwrap();
+        let mut names = rebooted.list(Path::new("db")).await.unwrap();
+        names.sort();
+        assert_eq!(names, ["current", "moved"]);
+        assert_eq!(read_all(&rebooted, "db/moved").await, "renamed");
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection_errors() {
+        let fs = FaultInjectionFileSystem::new(Arc::new(MemFileSystem::new()));
+        fs.create_dir_all(Path::new("db")).await.unwrap();
+        let mut file = fs.create(Path::new("db/file")).await.unwrap();
+        file.append(b"hello").await.unwrap();
+
+        fs.inject_error(FileOp::Sync);
+        assert!(file.sync().await.is_err());
+        file.append(b" world").await.unwrap();
+        fs.inject_error(FileOp::Read);
+        let reader = fs.open(Path::new("db/file")).await.unwrap();
+        assert!(reader.read_at(0, 5).await.is_err());
+        fs.clear_errors();
+        file.sync().await.unwrap();
+        assert_eq!(read_all(&fs, "db/file").await, "hello world");
+
+        fs.corrupt(Path::new("db/file"), 1, 2).await.unwrap();
+        let data = read_all(&fs, "db/file").await;
+        assert_eq!(data[..5], [b'h', !b'e', !b'l', b'l', b'o']);
+    }
+}
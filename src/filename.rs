@@ -0,0 +1,114 @@
+//! names of files in db directory
+
+/// kind of a file in db directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    /// WAL of a memtable
+    Log,
+    /// sstable
+    Table,
+    /// sstable or CURRENT being written, renamed once it is complete
+    Temp,
+    /// MANIFEST log of version edits
+    Manifest,
+    /// CURRENT file naming the live MANIFEST
+    Current,
+}
+
+/// name of the file naming the live MANIFEST
+pub const CURRENT_FILE_NAME: &str = "CURRENT";
+
+/// name of the file locked by the db process
+pub const LOCK_FILE_NAME: &str = "LOCK";
+
+/// name of the single WAL of the old layout without MANIFEST, which can't be opened anymore
+pub const LEGACY_WAL_FILE_NAME: &str = "wal.log";
+
+/// name of WAL file `number`
+pub fn log_file_name(number: u64) -> String {
+    format!("{:06}.log", number)
+}
+
+/// name of sstable file `number`
+pub fn table_file_name(number: u64) -> String {
+    format!("{:06}.ldb", number)
+}
+
+/// name of temporary file `number`, a file is written to it before renamed to its real name
+pub fn temp_file_name(number: u64) -> String {
+    format!("{:06}.dbtmp", number)
+}
+
+/// name of MANIFEST file `number`
+pub fn descriptor_file_name(number: u64) -> String {
+    format!("MANIFEST-{:06}", number)
+}
+
+/// parse number and type of a file in db directory, `None` if it's not a db file
+pub fn parse_file_name(name: &str) -> Option<(u64, FileType)> {
+    if name == CURRENT_FILE_NAME {
+        return Some((0, FileType::Current));
+    }
+    if let Some(number) = name.strip_prefix("MANIFEST-") {
+        return Some((parse_number(number)?, FileType::Manifest));
+    }
+    let (number, ext) = name.split_once('.')?;
+    let ty = match ext {
+        "log" => FileType::Log,
+        "ldb" => FileType::Table,
+        "dbtmp" => FileType::Temp,
+        _ => return None,
+    };
+    Some((parse_number(number)?, ty))
+}
+
+fn parse_number(number: &str) -> Option<u64> {
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(log_file_name(7), "000007.log");
+        assert_eq!(table_file_name(1234567), "1234567.ldb");
+        assert_eq!(parse_file_name("000007.log"), Some((7, FileType::Log)));
+        assert_eq!(
+            parse_file_name(&table_file_name(100)),
+            Some((100, FileType::Table))
+        );
+        assert_eq!(
+            parse_file_name(&temp_file_name(3)),
+            Some((3, FileType::Temp))
+        );
+        assert_eq!(
+            parse_file_name(&descriptor_file_name(5)),
+            Some((5, FileType::Manifest))
+        );
+        assert_eq!(
+            parse_file_name(CURRENT_FILE_NAME),
+            Some((0, FileType::Current))
+        );
+        for name in &[
+            "",
+            "log",
+            ".log",
+            "x1.log",
+            "+1.log",
+            "1.sst",
+            "1",
+            "wal.log",
+            "MANIFEST",
+            "MANIFEST-",
+            "MANIFEST-x",
+            "CURRENT.log",
+        ] {
+            assert_eq!(parse_file_name(name), None, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! filter block of a table, holding filters of its data blocks
+//!
+//! A filter is created for keys of data blocks starting in every 2KiB range of file offsets. The
+//! block is filters followed by their offsets (4 bytes each), the offset of offsets (4 bytes)
+//! and log2 of range size (1 byte), same as LevelDB.
+
+use std::sync::Arc;
+
+use bytes::{
+    BufMut,
+    Bytes,
+    BytesMut,
+};
+
+use crate::filter_policy::FilterPolicy;
+
+// log2 of size of offset range a filter covers
+const FILTER_BASE_LG: u8 = 11;
+const FILTER_BASE: u64 = 1 << FILTER_BASE_LG;
+
+/// builder of a filter block, data blocks must be started in order of offset
+pub struct FilterBlockBuilder {
+    policy:  Arc<dyn FilterPolicy>,
+    // keys added since the last filter is created
+    keys:    Vec<Bytes>,
+    result:  BytesMut,
+    offsets: Vec<u32>,
+}
+
+impl FilterBlockBuilder {
+    pub fn new(policy: Arc<dyn FilterPolicy>) -> Self {
+        FilterBlockBuilder {
+            policy,
+            keys: vec![],
+            result: BytesMut::new(),
+            offsets: vec![],
+        }
+    }
+
+    /// start a data block at `block_offset`, keys added after go to its filter
+    pub fn start_block(&mut self, block_offset: u64) {
+        let index = block_offset / FILTER_BASE;
+        while (self.offsets.len() as u64) < index {
+            self.generate_filter();
+        }
+    }
+
+    pub fn add_key(&mut self, key: Bytes) {
+        self.keys.push(key);
+    }
+
+    /// build filter block
+    pub fn finish(mut self) -> Bytes {
+        if !self.keys.is_empty() {
+            self.generate_filter();
+        }
+        let array_offset = self.result.len() as u32;
+        for &offset in &self.offsets {
+            self.result.put_u32_le(offset);
+        }
+        self.result.put_u32_le(array_offset);
+        self.result.put_u8(FILTER_BASE_LG);
+        self.result.freeze()
+    }
+
+    fn generate_filter(&mut self) {
+        self.offsets.push(self.result.len() as u32);
+        // a range without keys has an empty filter
+        if !self.keys.is_empty() {
+            self.policy.create_filter(&self.keys, &mut self.result);
+            self.keys.clear();
+        }
+    }
+}
+
+/// reader of a filter block built by [`FilterBlockBuilder`]
+pub struct FilterBlockReader {
+    policy:       Arc<dyn FilterPolicy>,
+    data:         Bytes,
+    // offset of filter offsets in data
+    array_offset: usize,
+    num_filters:  usize,
+    base_lg:      u8,
+}
+
+impl FilterBlockReader {
+    /// parse filter block `data`, `None` if it's malformed
+    pub fn new(policy: Arc<dyn FilterPolicy>, data: Bytes) -> Option<Self> {
+        if data.len() < 5 {
+            return None;
+        }
+        let base_lg = data[data.len() - 1];
+        let array_offset = decode_u32_le(&data[data.len() - 5..]) as usize;
+        if array_offset > data.len() - 5 || base_lg >= 64 {
+            return None;
+        }
+        Some(FilterBlockReader {
+            policy,
+            num_filters: (data.len() - 5 - array_offset) / 4,
+            data,
+            array_offset,
+            base_lg,
+        })
+    }
+
+    /// check if `key` may be in data block at `block_offset`
+    pub fn key_may_match(&self, block_offset: u64, key: &[u8]) -> bool {
+        let index = (block_offset >> self.base_lg) as usize;
+        if index >= self.num_filters {
+            // treat malformed filters as matches
+            return true;
+        }
+        let offset = self.array_offset + index * 4;
+        let start = decode_u32_le(&self.data[offset..]) as usize;
+        let limit = decode_u32_le(&self.data[offset + 4..]) as usize;
+        if start == limit {
+            // no key starts in the range
+            return false;
+        }
+        if start > limit || limit > self.array_offset {
+            return true;
+        }
+        self.policy.key_may_match(key, &self.data[start..limit])
+    }
+}
+
+fn decode_u32_le(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter_policy::BloomFilterPolicy;
+
+    fn policy() -> Arc<dyn FilterPolicy> {
+        Arc::new(BloomFilterPolicy::new(10))
+    }
+
+    #[test]
+    fn test_filter_block_empty() {
+        let block = FilterBlockBuilder::new(policy()).finish();
+        assert_eq!(&block[..], &[0, 0, 0, 0, FILTER_BASE_LG]);
+        let reader = FilterBlockReader::new(policy(), block).unwrap();
+        assert!(reader.key_may_match(0, b"foo"));
+        assert!(reader.key_may_match(100000, b"foo"));
+    }
+
+    #[test]
+    fn test_filter_block_multi_chunk() {
+        let mut builder = FilterBlockBuilder::new(policy());
+        // first filter
+        builder.start_block(0);
+        builder.add_key("foo".into());
+        builder.start_block(2000);
+        builder.add_key("bar".into());
+        // second filter
+        builder.start_block(3100);
+        builder.add_key("box".into());
+        // third filter is empty, last filter
+        builder.start_block(9000);
+        builder.add_key("box".into());
+        builder.add_key("hello".into());
+        let reader = FilterBlockReader::new(policy(), builder.finish()).unwrap();
+
+        for offset in [0, 2000] {
+            assert!(reader.key_may_match(offset, b"foo"));
+            assert!(reader.key_may_match(offset, b"bar"));
+            assert!(!reader.key_may_match(offset, b"box"));
+            assert!(!reader.key_may_match(offset, b"hello"));
+        }
+        assert!(reader.key_may_match(3100, b"box"));
+        assert!(!reader.key_may_match(3100, b"foo"));
+        assert!(!reader.key_may_match(3100, b"hello"));
+        assert!(!reader.key_may_match(4100, b"box"));
+        assert!(!reader.key_may_match(4100, b"foo"));
+        assert!(reader.key_may_match(9000, b"box"));
+        assert!(reader.key_may_match(9000, b"hello"));
+        assert!(!reader.key_may_match(9000, b"foo"));
+    }
+
+    #[test]
+    fn test_filter_block_malformed() {
+        assert!(FilterBlockReader::new(policy(), Bytes::from_static(&[0, 0, 0])).is_none());
+        assert!(FilterBlockReader::new(policy(), Bytes::from_static(&[9, 0, 0, 0, 11])).is_none());
+    }
+}
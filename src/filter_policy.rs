@@ -0,0 +1,222 @@
+//! filters telling keys not in a table apart without reading its data blocks
+
+use std::{
+    fmt::Debug,
+    sync::Arc,
+};
+
+use bytes::{
+    BufMut,
+    Bytes,
+    BytesMut,
+};
+
+use crate::format::extract_user_key;
+
+/// a policy creating small filters from sets of keys
+///
+/// A filter of a data block is checked before reading the block, a key the filter doesn't match
+/// is not in the block.
+pub trait FilterPolicy: Debug + Send + Sync {
+    /// name of policy, a table is only checked with filters created by the policy of the same
+    /// name
+    fn name(&self) -> &str;
+
+    /// append a filter matching all `keys` to `dst`
+    fn create_filter(&self, keys: &[Bytes], dst: &mut BytesMut);
+
+    /// check if `key` may be one of the keys `filter` is created from, which must be true if it
+    /// is, and may be true if it isn't
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool;
+}
+
+/// bloom filter policy, compatible with filters of LevelDB
+#[derive(Clone, Debug)]
+pub struct BloomFilterPolicy {
+    bits_per_key: usize,
+    // number of probes
+    k:            usize,
+}
+
+impl BloomFilterPolicy {
+    /// create a policy spending about `bits_per_key` bits for each key, 10 bits give about 1%
+    /// false positive rate
+    pub fn new(bits_per_key: usize) -> Self {
+        // ln(2) * bits_per_key minimizes false positive rate
+        let k = (bits_per_key as f64 * 0.69) as usize;
+        BloomFilterPolicy {
+            bits_per_key,
+            k: k.clamp(1, 30),
+        }
+    }
+}
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn name(&self) -> &str {
+        "leveldb.BuiltinBloomFilter2"
+    }
+
+    fn create_filter(&self, keys: &[Bytes], dst: &mut BytesMut) {
+        // a small filter has a high false positive rate
+        let bits = (keys.len() * self.bits_per_key).max(64);
+        let bytes = bits.div_ceil(8);
+        let bits = bytes * 8;
+
+        let start = dst.len();
+        dst.resize(start + bytes, 0);
+        let array = &mut dst[start..];
+        for key in keys {
+            // double hashing, every probe is derived from a single hash
+            let mut h = bloom_hash(key);
+            let delta = h.rotate_right(17);
+            for _ in 0..self.k {
+                let bit = h as usize % bits;
+                array[bit / 8] |= 1 << (bit % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        dst.put_u8(self.k as u8);
+    }
+
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+        if filter.len() < 2 {
+            return false;
+        }
+        let (array, k) = filter.split_at(filter.len() - 1);
+        let k = k[0] as usize;
+        if k > 30 {
+            // reserved for new encodings of short bloom filters, consider it a match
+            return true;
+        }
+        let bits = array.len() * 8;
+        let mut h = bloom_hash(key);
+        let delta = h.rotate_right(17);
+        for _ in 0..k {
+            let bit = h as usize % bits;
+            if array[bit / 8] & (1 << (bit % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+}
+
+/// a policy filtering internal keys by their user keys with policy of user
+#[derive(Debug)]
+pub struct InternalFilterPolicy {
+    user_policy: Arc<dyn FilterPolicy>,
+}
+
+impl InternalFilterPolicy {
+    pub fn new(user_policy: Arc<dyn FilterPolicy>) -> Self {
+        InternalFilterPolicy { user_policy }
+    }
+}
+
+impl FilterPolicy for InternalFilterPolicy {
+    fn name(&self) -> &str {
+        self.user_policy.name()
+    }
+
+    fn create_filter(&self, keys: &[Bytes], dst: &mut BytesMut) {
+        let user_keys: Vec<_> = keys
+            .iter()
+            .map(|key| key.slice(..extract_user_key(key).len()))
+            .collect();
+        self.user_policy.create_filter(&user_keys, dst)
+    }
+
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+        self.user_policy
+            .key_may_match(extract_user_key(key), filter)
+    }
+}
+
+/// hash used by bloom filters of LevelDB, which is similar to murmur hash
+fn bloom_hash(data: &[u8]) -> u32 {
+    const SEED: u32 = 0xbc9f1d34;
+    const M: u32 = 0xc6a4a793;
+    let mut h = SEED ^ (data.len() as u32).wrapping_mul(M);
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        h = h.wrapping_add(u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        h = h.wrapping_mul(M);
+        h ^= h >> 16;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, &b) in rest.iter().enumerate() {
+            h = h.wrapping_add((b as u32) << (8 * i));
+        }
+        h = h.wrapping_mul(M);
+        h ^= h >> 24;
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{
+        InternalKey,
+        ValueType,
+    };
+
+    fn key(i: u32) -> Bytes {
+        Bytes::copy_from_slice(&i.to_le_bytes())
+    }
+
+    #[test]
+    fn test_bloom_hash() {
+        // values of LevelDB
+        assert_eq!(bloom_hash(b""), 0xbc9f1d34);
+        assert_eq!(bloom_hash(&[0x62]), 0xef1345c4);
+        assert_eq!(bloom_hash(&[0xc3, 0x97]), 0x5b663814);
+        assert_eq!(bloom_hash(&[0xe2, 0x99, 0xa5]), 0x323c078f);
+        assert_eq!(bloom_hash(&[0xe1, 0x80, 0xb9, 0x32]), 0xed21633a);
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let policy = BloomFilterPolicy::new(10);
+        let mut filter = BytesMut::new();
+        policy.create_filter(&[], &mut filter);
+        assert!(!policy.key_may_match(b"hello", &filter));
+
+        for &n in &[1, 10, 100, 1000, 10000] {
+            let keys: Vec<_> = (0..n).map(key).collect();
+            let mut filter = BytesMut::from(&b"prefix"[..]);
+            policy.create_filter(&keys, &mut filter);
+            let filter = &filter[6..];
+            assert!(filter.len() <= (n as usize * 10 / 8) + 40);
+            for key in &keys {
+                assert!(policy.key_may_match(key, filter));
+            }
+            let false_positives = (0..10000)
+                .filter(|i| policy.key_may_match(&key(i + 1_000_000_000), filter))
+                .count();
+            // about 1% false positive rate
+            assert!(false_positives <= 200, "{} keys: {}", n, false_positives);
+        }
+    }
+
+    #[test]
+    fn test_internal_filter_policy() {
+        let policy = InternalFilterPolicy::new(Arc::new(BloomFilterPolicy::new(10)));
+        let keys = [
+            InternalKey::new(b"foo", 10, ValueType::Value),
+            InternalKey::new(b"bar", 1, ValueType::Deletion),
+        ];
+        let keys: Vec<_> = keys.iter().map(|key| key.encoded().clone()).collect();
+        let mut filter = BytesMut::new();
+        policy.create_filter(&keys, &mut filter);
+        // any version of a user key matches
+        let lookup = InternalKey::new(b"foo", 100, ValueType::FOR_SEEK);
+        assert!(policy.key_may_match(lookup.encoded(), &filter));
+        let lookup = InternalKey::new(b"bar", 0, ValueType::FOR_SEEK);
+        assert!(policy.key_may_match(lookup.encoded(), &filter));
+        let lookup = InternalKey::new(b"baz", 10, ValueType::FOR_SEEK);
+        assert!(!policy.key_may_match(lookup.encoded(), &filter));
+    }
+}
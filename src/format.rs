@@ -0,0 +1,177 @@
+//! formats shared by WAL, memtable and sstables
+
+use std::{
+    cmp::Ordering,
+    convert::TryInto,
+    fmt,
+};
+
+use bytes::{
+    BufMut,
+    Bytes,
+    BytesMut,
+};
+
+/// sequence number of a write, only the lower 56 bits are usable
+pub type SequenceNumber = u64;
+
+/// largest sequence number which can be packed with a [`ValueType`]
+pub const MAX_SEQUENCE_NUMBER: SequenceNumber = (1 << 56) - 1;
+
+/// size of the sequence and type trailer of an internal key
+const TRAILER_SIZE: usize = 8;
+
+/// type of an entry, deletions are kept as tombstones so that they shadow older values
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Deletion = 0,
+    Value    = 1,
+}
+
+impl ValueType {
+    /// type with the largest tag, used to build a key sorting before all entries of a sequence
+    pub const FOR_SEEK: ValueType = ValueType::Value;
+
+    pub fn from_u8(ty: u8) -> Option<Self> {
+        match ty {
+            0 => Some(ValueType::Deletion),
+            1 => Some(ValueType::Value),
+            _ => None,
+        }
+    }
+}
+
+/// key used inside db, which is user key followed by `(sequence << 8) | type` as fixed u64 le
+///
+/// Internal keys are ordered by user key ascending, then by sequence and type descending, so the
+/// newest version of a user key comes first.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct InternalKey {
+    rep: Bytes,
+}
+
+impl InternalKey {
+    /// build internal key from its parts
+    pub fn new(user_key: &[u8], seq: SequenceNumber, ty: ValueType) -> Self {
+        debug_assert!(seq <= MAX_SEQUENCE_NUMBER);
+        let mut rep = BytesMut::with_capacity(user_key.len() + TRAILER_SIZE);
+        rep.put_slice(user_key);
+        rep.put_u64_le(pack_sequence_and_type(seq, ty));
+        InternalKey { rep: rep.freeze() }
+    }
+
+    /// wrap an encoded internal key, return `None` if it is malformed
+    pub fn decode(rep: Bytes) -> Option<Self> {
+        if rep.len() < TRAILER_SIZE || ValueType::from_u8(rep[rep.len() - TRAILER_SIZE]).is_none() {
+            return None;
+        }
+        Some(InternalKey { rep })
+    }
+
+    /// user key part
+    pub fn user_key(&self) -> &[u8] {
+        extract_user_key(&self.rep)
+    }
+
+    /// sequence number part
+    pub fn sequence(&self) -> SequenceNumber {
+        extract_trailer(&self.rep) >> 8
+    }
+
+    /// value type part
+    pub fn value_type(&self) -> ValueType {
+        // checked on construction
+        ValueType::from_u8(extract_trailer(&self.rep) as u8).unwrap()
+    }
+
+    /// encoded representation
+    pub fn encoded(&self) -> &Bytes {
+        &self.rep
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_internal_key(&self.rep, &other.rep)
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for InternalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}@{}:{:?}",
+            Bytes::copy_from_slice(self.user_key()),
+            self.sequence(),
+            self.value_type()
+        )
+    }
+}
+
+fn pack_sequence_and_type(seq: SequenceNumber, ty: ValueType) -> u64 {
+    (seq << 8) | ty as u64
+}
+
+fn extract_trailer(key: &[u8]) -> u64 {
+    u64::from_le_bytes(key[key.len() - TRAILER_SIZE..].try_into().unwrap())
+}
+
+/// user key part of an encoded internal key
+pub fn extract_user_key(key: &[u8]) -> &[u8] {
+    &key[..key.len() - TRAILER_SIZE]
+}
+
+/// value type part of an encoded internal key
+pub fn extract_value_type(key: &[u8]) -> ValueType {
+    ValueType::from_u8(extract_trailer(key) as u8).unwrap()
+}
+
+/// compare two encoded internal keys, see [`InternalKey`] for the order
+pub fn compare_internal_key(a: &[u8], b: &[u8]) -> Ordering {
+    extract_user_key(a)
+        .cmp(extract_user_key(b))
+        .then_with(|| extract_trailer(b).cmp(&extract_trailer(a)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_key_encode_decode() {
+        for &user_key in &[&b""[..], b"k", b"hello"] {
+            for &seq in &[0, 1, 255, 256, MAX_SEQUENCE_NUMBER] {
+                for &ty in &[ValueType::Value, ValueType::Deletion] {
+                    let key = InternalKey::new(user_key, seq, ty);
+                    let key = InternalKey::decode(key.encoded().clone()).unwrap();
+                    assert_eq!(key.user_key(), user_key);
+                    assert_eq!(key.sequence(), seq);
+                    assert_eq!(key.value_type(), ty);
+                }
+            }
+        }
+        assert!(InternalKey::decode(Bytes::from("short")).is_none());
+        assert!(InternalKey::decode(Bytes::from(vec![9, 0, 0, 0, 0, 0, 0, 0])).is_none());
+    }
+
+    #[test]
+    fn test_internal_key_order() {
+        let key =
+            |user_key: &str, seq| InternalKey::new(user_key.as_bytes(), seq, ValueType::Value);
+        assert!(key("a", 1) < key("b", 1));
+        assert!(key("a", 2) < key("a", 1));
+        assert!(key("a", 1) < key("ab", 100));
+        assert!(key("", 100) < key("a", 1));
+        assert!(
+            InternalKey::new(b"a", 1, ValueType::Value) <
+                InternalKey::new(b"a", 1, ValueType::Deletion)
+        );
+    }
+}
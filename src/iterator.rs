@@ -0,0 +1,36 @@
+//! iterators over internal keys
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::db::DbError;
+
+type Result<T> = std::result::Result<T, DbError>;
+
+/// a seekable cursor over encoded internal keys and their values, in internal key order
+#[async_trait]
+pub trait InternalIterator: Send {
+    /// check if iterator is positioned at an entry
+    fn valid(&self) -> bool;
+
+    /// position at the first entry
+    async fn seek_to_first(&mut self) -> Result<()>;
+
+    /// position at the last entry
+    async fn seek_to_last(&mut self) -> Result<()>;
+
+    /// position at the first entry whose key is at or past `target`
+    async fn seek(&mut self, target: &[u8]) -> Result<()>;
+
+    /// move to the next entry, iterator must be valid
+    async fn next(&mut self) -> Result<()>;
+
+    /// move to the previous entry, iterator must be valid
+    async fn prev(&mut self) -> Result<()>;
+
+    /// key of current entry, iterator must be valid
+    fn key(&self) -> Bytes;
+
+    /// value of current entry, iterator must be valid
+    fn value(&self) -> Bytes;
+}
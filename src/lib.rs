@@ -1,13 +1,63 @@
+mod cache;
+mod comparator;
+mod db_iter;
 mod encoding;
+#[cfg(test)] mod fault_injection;
+mod filename;
+mod filter_block;
+mod filter_policy;
+mod format;
+mod iterator;
+mod mem_file_system;
 mod mem_table;
+mod mem_table_rep;
+mod merger;
+mod options;
+mod skiplist;
+mod snapshot;
 mod sorted_stable;
+mod statistics;
+mod table_cache;
+mod version_edit;
+mod version_set;
 mod vfs;
 mod wal;
+mod write_batch;
 
 pub mod db;
 
 pub use bytes::Bytes;
+pub use cache::BlockCache;
+pub use db_iter::DbIterator;
+pub use filter_policy::{
+    BloomFilterPolicy,
+    FilterPolicy,
+};
+pub use mem_file_system::MemFileSystem;
 use mimalloc::MiMalloc;
+pub use options::{
+    CompactRangeOptions,
+    CompactionStyle,
+    MemTableRepType,
+    Options,
+    ReadOptions,
+    UniversalCompactionOptions,
+    WriteOptions,
+};
+pub use snapshot::Snapshot;
+pub use statistics::Statistics;
+pub use vfs::{
+    FileLock,
+    FileSystem,
+    RandomAccessFile,
+    TokioFileSystem,
+    VfsError,
+    WritableFile,
+};
+pub use write_batch::{
+    BatchOp,
+    WriteBatch,
+};
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
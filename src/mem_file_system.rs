@@ -0,0 +1,299 @@
+//! file system in memory
+//!
+//! [`MemFileSystem`] keeps all files of db in memory, which suits tests and throwaway caches.
+//! Everything is lost once the file system is dropped.
+
+use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    fmt,
+    io,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::vfs::{
+    FileLock,
+    FileSystem,
+    RandomAccessFile,
+    VfsError,
+    WritableFile,
+};
+
+type Result<T> = std::result::Result<T, VfsError>;
+
+// contents of a file, shared by all its open handles
+type FileData = Arc<Mutex<Vec<u8>>>;
+
+/// file system keeping files in memory
+///
+/// Clones share the same files, so a db reopened with a clone of the file system sees files
+/// written before.
+#[derive(Clone, Default)]
+pub struct MemFileSystem {
+    inner: Arc<Mutex<MemFileSystemInner>>,
+}
+
+#[derive(Default)]
+struct MemFileSystemInner {
+    files: HashMap<PathBuf, FileData>,
+    dirs:  HashSet<PathBuf>,
+    locks: HashSet<PathBuf>,
+}
+
+impl MemFileSystemInner {
+    fn file(&self, path: &Path) -> Result<FileData> {
+        self.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn check_parent(&self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(dir) if !self.dirs.contains(dir) => Err(not_found(dir)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl MemFileSystem {
+    pub fn new() -> Self {
+        MemFileSystem::default()
+    }
+
+    fn lock_inner(&self) -> std::sync::MutexGuard<'_, MemFileSystemInner> {
+        self.inner.lock().unwrap()
+    }
+}
+
+impl fmt::Debug for MemFileSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock_inner();
+        f.debug_struct("MemFileSystem")
+            .field("files", &inner.files.len())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl FileSystem for MemFileSystem {
+    async fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        let data = self.lock_inner().file(path)?;
+        Ok(Box::new(MemRandomAccessFile {
+            data,
+            path: path.to_owned(),
+        }))
+    }
+
+    async fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut inner = self.lock_inner();
+        inner.check_parent(path)?;
+        // open handles of a replaced file keep its old contents
+        let data = FileData::default();
+        inner.files.insert(path.to_owned(), data.clone());
+        Ok(Box::new(MemWritableFile { data }))
+    }
+
+    async fn open_appendable(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let mut inner = self.lock_inner();
+        inner.check_parent(path)?;
+        let data = inner.files.entry(path.to_owned()).or_default().clone();
+        Ok(Box::new(MemWritableFile { data }))
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut inner = self.lock_inner();
+        inner.check_parent(to)?;
+        let data = inner.files.remove(from).ok_or_else(|| not_found(from))?;
+        inner.files.insert(to.to_owned(), data);
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        let mut inner = self.lock_inner();
+        inner.files.remove(path).ok_or_else(|| not_found(path))?;
+        Ok(())
+    }
+
+    async fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let inner = self.lock_inner();
+        if !inner.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        let names = inner
+            .files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .filter_map(|path| path.file_name()?.to_str().map(str::to_owned))
+            .collect();
+        Ok(names)
+    }
+
+    async fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        let mut inner = self.lock_inner();
+        inner.dirs.extend(dir.ancestors().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    async fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>> {
+        let mut inner = self.lock_inner();
+        inner.check_parent(path)?;
+        if !inner.locks.insert(path.to_owned()) {
+            return Err(VfsError::LockError(path.to_owned()));
+        }
+        inner.files.entry(path.to_owned()).or_default();
+        Ok(Box::new(MemFileLock {
+            fs:   self.clone(),
+            path: path.to_owned(),
+        }))
+    }
+
+    async fn sync_dir(&self, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// a file of [`MemFileSystem`] open for reading
+struct MemRandomAccessFile {
+    data: FileData,
+    path: PathBuf,
+}
+
+#[async_trait]
+impl RandomAccessFile for MemRandomAccessFile {
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes> {
+        let data = self.data.lock().unwrap();
+        let start = offset.min(data.len() as u64) as usize;
+        match data[start..].get(..len) {
+            Some(read) => Ok(Bytes::copy_from_slice(read)),
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    async fn size(&self) -> Result<u64> {
+        Ok(self.data.lock().unwrap().len() as u64)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// a file of [`MemFileSystem`] open for appending
+struct MemWritableFile {
+    data: FileData,
+}
+
+#[async_trait]
+impl WritableFile for MemWritableFile {
+    async fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.data.lock().unwrap().extend_from_slice(data);
+        Ok(())
+    }
+
+    async fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.data.lock().unwrap().len() as u64
+    }
+}
+
+/// a lock of [`MemFileSystem`], which is released when it's dropped
+struct MemFileLock {
+    fs:   MemFileSystem,
+    path: PathBuf,
+}
+
+impl FileLock for MemFileLock {}
+
+impl Drop for MemFileLock {
+    fn drop(&mut self) {
+        self.fs.lock_inner().locks.remove(&self.path);
+    }
+}
+
+fn not_found(path: &Path) -> VfsError {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found", path.display()),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::Vfs;
+
+    #[tokio::test]
+    async fn test_mem_file_system() {
+        let fs = MemFileSystem::new();
+        let vfs = Vfs::new(Arc::new(fs.clone()), "db".into()).await.unwrap();
+        assert!(vfs.open("foo").await.is_err());
+
+        let mut file = vfs.create("foo").await.unwrap();
+        file.append(b"hello").await.unwrap();
+        let reader = vfs.open("foo").await.unwrap();
+        file.append(b" world").await.unwrap();
+        file.sync().await.unwrap();
+        assert_eq!(file.size(), 11);
+        assert_eq!(reader.size().await.unwrap(), 11);
+        assert_eq!(reader.read_at(6, 5).await.unwrap(), &b"world"[..]);
+        assert_eq!(reader.read_at(11, 0).await.unwrap(), &b""[..]);
+        assert!(reader.read_at(6, 6).await.is_err());
+        assert!(reader.read_at(20, 1).await.is_err());
+
+        let mut file = vfs.open_appendable("foo").await.unwrap();
+        assert_eq!(file.size(), 11);
+        file.append(b"!").await.unwrap();
+        assert_eq!(reader.read_at(0, 12).await.unwrap(), &b"hello world!"[..]);
+
+        // a file replaced by create keeps its contents for open handles
+        vfs.create("foo").await.unwrap();
+        assert_eq!(reader.size().await.unwrap(), 12);
+        assert_eq!(vfs.open("foo").await.unwrap().size().await.unwrap(), 0);
+
+        vfs.rename("foo", "bar").await.unwrap();
+        assert!(vfs.open("foo").await.is_err());
+        assert!(vfs.rename("foo", "bar").await.is_err());
+        vfs.create("baz").await.unwrap();
+        let mut names = vfs.list().await.unwrap();
+        names.sort();
+        assert_eq!(names, ["bar", "baz"]);
+        vfs.remove("bar").await.unwrap();
+        assert!(vfs.remove("bar").await.is_err());
+        assert_eq!(vfs.list().await.unwrap(), ["baz"]);
+
+        // files of other directories are apart
+        let other = Vfs::new(Arc::new(fs.clone()), "other".into())
+            .await
+            .unwrap();
+        assert!(other.list().await.unwrap().is_empty());
+        assert!(fs.create(Path::new("missing/foo")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mem_file_system_lock() {
+        let vfs = Vfs::new(Arc::new(MemFileSystem::new()), "db".into())
+            .await
+            .unwrap();
+        let lock = vfs.lock("LOCK").await.unwrap();
+        assert!(matches!(
+            vfs.lock("LOCK").await,
+            Err(VfsError::LockError(_))
+        ));
+        drop(lock);
+        vfs.lock("LOCK").await.unwrap();
+    }
+}
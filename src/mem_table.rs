@@ -1,40 +1,352 @@
 //! memory table
 
-use std::collections::BTreeMap;
+use std::sync::{
+    atomic::{
+        AtomicUsize,
+        Ordering,
+    },
+    Arc,
+};
 
+use async_trait::async_trait;
 use bytes::Bytes;
-use tokio::sync::Mutex;
 
-/// an ordered table in memory
+use crate::{
+    db::DbError,
+    format::{
+        extract_user_key,
+        extract_value_type,
+        InternalKey,
+        SequenceNumber,
+        ValueType,
+    },
+    iterator::InternalIterator,
+    mem_table_rep::{
+        new_mem_table_rep,
+        MemTableRep,
+        MemTableRepIterator,
+    },
+    options::MemTableRepType,
+    write_batch::WriteBatch,
+};
+
+/// result of looking up a key in [`MemTable`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum LookupResult {
+    /// key holds a value
+    Value(Bytes),
+    /// key has been deleted
+    Deleted,
+}
+
+/// an ordered table in memory, holding every version of a key ordered by [`InternalKey`]
 pub struct MemTable {
-    inner: Mutex<BTreeMap<Bytes, Bytes>>,
+    table: Arc<dyn MemTableRep>,
+    // bytes of keys and values inserted
+    usage: AtomicUsize,
 }
 
 impl MemTable {
-    /// create an empty [`MemTable`]
-    pub fn new() -> Self {
+    /// create an empty [`MemTable`] holding entries in a collection of `ty`
+    pub fn new(ty: MemTableRepType) -> Self {
         MemTable {
-            inner: Mutex::default(),
+            table: new_mem_table_rep(ty),
+            usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// approximate bytes of data held by memtable
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.usage.load(Ordering::Relaxed)
+    }
+
+    /// get the newest version of key visible at `seq`, `None` means the key is unknown to this
+    /// memtable
+    pub fn get(&self, key: &[u8], seq: SequenceNumber) -> Option<LookupResult> {
+        let lookup = InternalKey::new(key, seq, ValueType::FOR_SEEK);
+        let (found, value) = self.table.seek(lookup.encoded())?;
+        if extract_user_key(&found) != key {
+            return None;
+        }
+        match extract_value_type(&found) {
+            ValueType::Value => Some(LookupResult::Value(value)),
+            ValueType::Deletion => Some(LookupResult::Deleted),
+        }
+    }
+
+    /// insert all ops in `batch`, numbering them from `seq`
+    ///
+    /// Ops show up to readers one by one, readers must not read at `seq` or later before the
+    /// whole batch is applied. Writes must be serialized by caller.
+    pub fn apply(&self, batch: &WriteBatch, seq: SequenceNumber) {
+        for (i, op) in batch.iter().enumerate() {
+            let key = InternalKey::new(op.key(), seq + i as u64, op.value_type());
+            let value = op.value();
+            self.usage
+                .fetch_add(key.encoded().len() + value.len(), Ordering::Relaxed);
+            self.table.insert(key, value);
+        }
+    }
+
+    /// create an iterator over every version in memtable
+    pub fn iter(&self) -> MemTableIterator {
+        MemTableIterator {
+            iter: self.table.clone().iter(),
+        }
+    }
+}
+
+/// iterator over [`MemTable`]
+///
+/// Entries inserted concurrently may show up, depending on the representation of memtable.
+pub struct MemTableIterator {
+    iter: Box<dyn MemTableRepIterator>,
+}
+
+#[async_trait]
+impl InternalIterator for MemTableIterator {
+    fn valid(&self) -> bool {
+        self.iter.valid()
+    }
+
+    async fn seek_to_first(&mut self) -> Result<(), DbError> {
+        self.iter.seek_to_first();
+        Ok(())
+    }
+
+    async fn seek_to_last(&mut self) -> Result<(), DbError> {
+        self.iter.seek_to_last();
+        Ok(())
+    }
+
+    async fn seek(&mut self, target: &[u8]) -> Result<(), DbError> {
+        self.iter.seek(target);
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<(), DbError> {
+        self.iter.next();
+        Ok(())
+    }
+
+    async fn prev(&mut self) -> Result<(), DbError> {
+        self.iter.prev();
+        Ok(())
+    }
+
+    fn key(&self) -> Bytes {
+        self.iter.key()
+    }
+
+    fn value(&self) -> Bytes {
+        self.iter.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REP_TYPES: [MemTableRepType; 3] = [
+        MemTableRepType::BTree,
+        MemTableRepType::SkipList,
+        MemTableRepType::Vector,
+    ];
+
+    #[test]
+    fn test_mem_table_tombstone() {
+        let mem = MemTable::new(MemTableRepType::default());
+        assert_eq!(mem.get(b"key", 10), None);
+        let mut batch = WriteBatch::new();
+        batch.put("key".into(), "val".into());
+        mem.apply(&batch, 1);
+        assert_eq!(mem.get(b"key", 10), Some(LookupResult::Value("val".into())));
+        batch.clear();
+        batch.delete("key".into());
+        mem.apply(&batch, 2);
+        assert_eq!(mem.get(b"key", 10), Some(LookupResult::Deleted));
+        batch.put("key".into(), "val2".into());
+        mem.apply(&batch, 3);
+        assert_eq!(
+            mem.get(b"key", 10),
+            Some(LookupResult::Value("val2".into()))
+        );
+    }
+
+    #[test]
+    fn test_mem_table_usage() {
+        let mem = MemTable::new(MemTableRepType::default());
+        assert_eq!(mem.approximate_memory_usage(), 0);
+        let mut batch = WriteBatch::new();
+        batch.put("key".into(), "value".into());
+        batch.delete("key".into());
+        mem.apply(&batch, 1);
+        // two internal keys of 3 + 8 bytes and a value of 5 bytes
+        assert_eq!(mem.approximate_memory_usage(), 27);
+    }
+
+    #[test]
+    fn test_mem_table_versions() {
+        for ty in REP_TYPES {
+            check_mem_table_versions(MemTable::new(ty));
         }
     }
 
-    /// get value from memtable
-    pub async fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.inner.lock().await.get(key).cloned()
+    fn check_mem_table_versions(mem: MemTable) {
+        let mut batch = WriteBatch::new();
+        batch.put("a".into(), "a1".into());
+        batch.put("b".into(), "b1".into());
+        mem.apply(&batch, 1);
+        batch.clear();
+        batch.put("a".into(), "a2".into());
+        batch.delete("b".into());
+        mem.apply(&batch, 3);
+
+        assert_eq!(mem.get(b"a", 0), None);
+        assert_eq!(mem.get(b"a", 1), Some(LookupResult::Value("a1".into())));
+        assert_eq!(mem.get(b"a", 2), Some(LookupResult::Value("a1".into())));
+        assert_eq!(mem.get(b"a", 3), Some(LookupResult::Value("a2".into())));
+        assert_eq!(mem.get(b"b", 1), None);
+        assert_eq!(mem.get(b"b", 3), Some(LookupResult::Value("b1".into())));
+        assert_eq!(mem.get(b"b", 4), Some(LookupResult::Deleted));
+        assert_eq!(mem.get(b"c", 4), None);
+        assert_eq!(mem.get(b"", 4), None);
+    }
+
+    #[tokio::test]
+    async fn test_mem_table_iterator() {
+        for ty in REP_TYPES {
+            check_mem_table_iterator(MemTable::new(ty)).await;
+        }
     }
 
-    /// set key value pair, return possible old value
-    pub async fn set(&self, key: Bytes, value: Bytes) -> Option<Bytes> {
-        self.inner.lock().await.insert(key, value)
+    async fn check_mem_table_iterator(mem: MemTable) {
+        let mut batch = WriteBatch::new();
+        batch.put("b".into(), "b1".into());
+        batch.put("a".into(), "a1".into());
+        batch.put("c".into(), "c1".into());
+        mem.apply(&batch, 1);
+        batch.clear();
+        batch.delete("b".into());
+        mem.apply(&batch, 4);
+
+        let expected = vec![
+            InternalKey::new(b"a", 2, ValueType::Value),
+            InternalKey::new(b"b", 4, ValueType::Deletion),
+            InternalKey::new(b"b", 1, ValueType::Value),
+            InternalKey::new(b"c", 3, ValueType::Value),
+        ];
+        let mut iter = mem.iter();
+        assert!(!iter.valid());
+        iter.seek_to_first().await.unwrap();
+        for key in &expected {
+            assert_eq!(&iter.key(), key.encoded());
+            iter.next().await.unwrap();
+        }
+        assert!(!iter.valid());
+
+        iter.seek_to_last().await.unwrap();
+        for key in expected.iter().rev() {
+            assert_eq!(&iter.key(), key.encoded());
+            iter.prev().await.unwrap();
+        }
+        assert!(!iter.valid());
+
+        iter.seek(InternalKey::new(b"b", 3, ValueType::FOR_SEEK).encoded())
+            .await
+            .unwrap();
+        assert_eq!(&iter.key(), expected[2].encoded());
+        assert_eq!(iter.value(), Bytes::from("b1"));
+        iter.seek(InternalKey::new(b"d", 9, ValueType::FOR_SEEK).encoded())
+            .await
+            .unwrap();
+        assert!(!iter.valid());
     }
 
-    /// check if key is in memtable
-    pub async fn contains(&self, key: &Bytes) -> bool {
-        self.inner.lock().await.contains_key(key)
+    #[tokio::test]
+    async fn test_mem_table_vector_snapshot() {
+        let mem = MemTable::new(MemTableRepType::Vector);
+        let mut batch = WriteBatch::new();
+        batch.put("b".into(), "b1".into());
+        batch.put("d".into(), "d1".into());
+        mem.apply(&batch, 1);
+        let mut iter = mem.iter();
+
+        // entries written after an iterator is created are merged apart from it
+        batch.clear();
+        batch.put("c".into(), "c1".into());
+        batch.put("a".into(), "a1".into());
+        mem.apply(&batch, 3);
+        assert_eq!(mem.get(b"a", 4), Some(LookupResult::Value("a1".into())));
+        assert_eq!(mem.get(b"c", 4), Some(LookupResult::Value("c1".into())));
+
+        let mut keys = vec![];
+        iter.seek_to_first().await.unwrap();
+        while iter.valid() {
+            keys.push(iter.key());
+            iter.next().await.unwrap();
+        }
+        assert_eq!(
+            keys,
+            [
+                InternalKey::new(b"b", 1, ValueType::Value)
+                    .encoded()
+                    .clone(),
+                InternalKey::new(b"d", 2, ValueType::Value)
+                    .encoded()
+                    .clone(),
+            ]
+        );
+        let mut iter = mem.iter();
+        iter.seek_to_first().await.unwrap();
+        assert_eq!(
+            &iter.key(),
+            InternalKey::new(b"a", 4, ValueType::Value).encoded()
+        );
     }
 
-    /// remove corresponding value
-    pub async fn remove(&self, key: &Bytes) -> Option<Bytes> {
-        self.inner.lock().await.remove(key)
+    /// mixed load of a writer and several readers on B-tree and skiplist memtables, run with
+    /// `cargo test --release bench_mem_table -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn bench_mem_table_mixed() {
+        const WRITES: u64 = 200_000;
+        const READERS: u64 = 3;
+        const READS: u64 = 200_000;
+        let key = |i: u64| format!("key{:016}", i.wrapping_mul(0x9e3779b97f4a7c15) % WRITES);
+
+        for ty in [MemTableRepType::BTree, MemTableRepType::SkipList] {
+            let mem = Arc::new(MemTable::new(ty));
+            let start = std::time::Instant::now();
+            let writer = {
+                let mem = mem.clone();
+                tokio::spawn(async move {
+                    for i in 0..WRITES {
+                        let mut batch = WriteBatch::new();
+                        batch.put(key(i).into(), "value".into());
+                        mem.apply(&batch, i + 1);
+                    }
+                })
+            };
+            let readers: Vec<_> = (0..READERS)
+                .map(|r| {
+                    let mem = mem.clone();
+                    tokio::spawn(async move {
+                        for i in 0..READS {
+                            mem.get(key(i * READERS + r).as_bytes(), WRITES);
+                        }
+                    })
+                })
+                .collect();
+            writer.await.unwrap();
+            futures::future::join_all(readers).await;
+            println!(
+                "{:?}: {} writes and {} reads in {:?}",
+                ty,
+                WRITES,
+                READS * READERS,
+                start.elapsed()
+            );
+        }
     }
 }
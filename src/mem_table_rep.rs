@@ -0,0 +1,303 @@
+//! representations of memtable entries
+
+use std::{
+    collections::BTreeMap,
+    ops::Bound,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use bytes::Bytes;
+
+use crate::{
+    comparator::InternalKeyComparator,
+    format::InternalKey,
+    options::MemTableRepType,
+    skiplist::{
+        SkipList,
+        SkipListIterator,
+    },
+};
+
+/// an ordered collection of memtable entries keyed by internal keys
+pub trait MemTableRep: Send + Sync {
+    /// insert `key` which is not in collection, inserts are serialized by memtable
+    fn insert(&self, key: InternalKey, value: Bytes);
+
+    /// the first entry at or after encoded internal key `key`
+    fn seek(&self, key: &[u8]) -> Option<(Bytes, Bytes)>;
+
+    /// create an iterator over all entries
+    fn iter(self: Arc<Self>) -> Box<dyn MemTableRepIterator>;
+}
+
+/// iterator over [`MemTableRep`], whose keys are encoded internal keys
+pub trait MemTableRepIterator: Send {
+    fn valid(&self) -> bool;
+
+    fn key(&self) -> Bytes;
+
+    fn value(&self) -> Bytes;
+
+    fn seek_to_first(&mut self);
+
+    fn seek_to_last(&mut self);
+
+    /// move to the first entry at or after `target`
+    fn seek(&mut self, target: &[u8]);
+
+    fn next(&mut self);
+
+    fn prev(&mut self);
+}
+
+/// create an empty collection of `ty`
+pub fn new_mem_table_rep(ty: MemTableRepType) -> Arc<dyn MemTableRep> {
+    match ty {
+        MemTableRepType::BTree => Arc::new(BTreeRep::default()),
+        MemTableRepType::SkipList => Arc::new(SkipList::new(InternalKeyComparator)),
+        MemTableRepType::Vector => Arc::new(VectorRep::default()),
+    }
+}
+
+/// a [`BTreeMap`] locked by every read and write
+#[derive(Default)]
+struct BTreeRep {
+    inner: Mutex<BTreeMap<InternalKey, Bytes>>,
+}
+
+impl MemTableRep for BTreeRep {
+    fn insert(&self, key: InternalKey, value: Bytes) {
+        self.inner.lock().unwrap().insert(key, value);
+    }
+
+    fn seek(&self, key: &[u8]) -> Option<(Bytes, Bytes)> {
+        let key = decode_key(key);
+        let inner = self.inner.lock().unwrap();
+        let (found, value) = inner.range(key..).next()?;
+        Some((found.encoded().clone(), value.clone()))
+    }
+
+    fn iter(self: Arc<Self>) -> Box<dyn MemTableRepIterator> {
+        Box::new(BTreeRepIterator {
+            rep:     self,
+            current: None,
+        })
+    }
+}
+
+/// iterator over [`BTreeRep`]
+///
+/// The iterator doesn't hold the lock between moves, every move looks up the entry next to the
+/// current one, so entries inserted concurrently may show up.
+struct BTreeRepIterator {
+    rep:     Arc<BTreeRep>,
+    current: Option<(InternalKey, Bytes)>,
+}
+
+impl BTreeRepIterator {
+    fn position(&mut self, lower: Bound<&InternalKey>, upper: Bound<&InternalKey>, back: bool) {
+        let inner = self.rep.inner.lock().unwrap();
+        let mut range = inner.range::<InternalKey, _>((lower, upper));
+        let found = if back {
+            range.next_back()
+        } else {
+            range.next()
+        };
+        self.current = found.map(|(key, value)| (key.clone(), value.clone()));
+    }
+
+    fn current(&self) -> &(InternalKey, Bytes) {
+        self.current.as_ref().expect("iterator is not valid")
+    }
+}
+
+impl MemTableRepIterator for BTreeRepIterator {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn key(&self) -> Bytes {
+        self.current().0.encoded().clone()
+    }
+
+    fn value(&self) -> Bytes {
+        self.current().1.clone()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.position(Bound::Unbounded, Bound::Unbounded, false)
+    }
+
+    fn seek_to_last(&mut self) {
+        self.position(Bound::Unbounded, Bound::Unbounded, true)
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        let target = decode_key(target);
+        self.position(Bound::Included(&target), Bound::Unbounded, false)
+    }
+
+    fn next(&mut self) {
+        let (key, _) = self.current.take().expect("iterator is not valid");
+        self.position(Bound::Excluded(&key), Bound::Unbounded, false)
+    }
+
+    fn prev(&mut self) {
+        let (key, _) = self.current.take().expect("iterator is not valid");
+        self.position(Bound::Unbounded, Bound::Excluded(&key), true)
+    }
+}
+
+impl MemTableRep for SkipList<InternalKeyComparator> {
+    fn insert(&self, key: InternalKey, value: Bytes) {
+        SkipList::insert(self, key.encoded(), value)
+    }
+
+    fn seek(&self, key: &[u8]) -> Option<(Bytes, Bytes)> {
+        let (found, value) = self.find(key)?;
+        Some((Bytes::copy_from_slice(found), value.clone()))
+    }
+
+    fn iter(self: Arc<Self>) -> Box<dyn MemTableRepIterator> {
+        Box::new(SkipList::iter(&self))
+    }
+}
+
+impl MemTableRepIterator for SkipListIterator<InternalKeyComparator> {
+    fn valid(&self) -> bool {
+        SkipListIterator::valid(self)
+    }
+
+    fn key(&self) -> Bytes {
+        Bytes::copy_from_slice(SkipListIterator::key(self))
+    }
+
+    fn value(&self) -> Bytes {
+        SkipListIterator::value(self).clone()
+    }
+
+    fn seek_to_first(&mut self) {
+        SkipListIterator::seek_to_first(self)
+    }
+
+    fn seek_to_last(&mut self) {
+        SkipListIterator::seek_to_last(self)
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        SkipListIterator::seek(self, target)
+    }
+
+    fn next(&mut self) {
+        SkipListIterator::next(self)
+    }
+
+    fn prev(&mut self) {
+        SkipListIterator::prev(self)
+    }
+}
+
+/// a vector appended by writes and merged into the sorted entries on the first read after them
+#[derive(Default)]
+struct VectorRep {
+    inner: Mutex<VectorInner>,
+}
+
+#[derive(Default)]
+struct VectorInner {
+    // shared with iterators, which is copied on the first merge after an iterator is created
+    sorted:  Arc<Vec<(InternalKey, Bytes)>>,
+    // inserted since the last read
+    pending: Vec<(InternalKey, Bytes)>,
+}
+
+impl VectorInner {
+    fn sorted_entries(&mut self) -> &Arc<Vec<(InternalKey, Bytes)>> {
+        if !self.pending.is_empty() {
+            let sorted = Arc::make_mut(&mut self.sorted);
+            sorted.append(&mut self.pending);
+            // stable sort finds the sorted run and merges pending entries into it
+            sorted.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        &self.sorted
+    }
+}
+
+impl MemTableRep for VectorRep {
+    fn insert(&self, key: InternalKey, value: Bytes) {
+        self.inner.lock().unwrap().pending.push((key, value));
+    }
+
+    fn seek(&self, key: &[u8]) -> Option<(Bytes, Bytes)> {
+        let key = decode_key(key);
+        let mut inner = self.inner.lock().unwrap();
+        let entries = inner.sorted_entries();
+        let (found, value) = entries.get(entries.partition_point(|(k, _)| k < &key))?;
+        Some((found.encoded().clone(), value.clone()))
+    }
+
+    fn iter(self: Arc<Self>) -> Box<dyn MemTableRepIterator> {
+        let entries = self.inner.lock().unwrap().sorted_entries().clone();
+        Box::new(VectorRepIterator {
+            pos: entries.len(),
+            entries,
+        })
+    }
+}
+
+/// iterator over entries of [`VectorRep`] when it's created
+struct VectorRepIterator {
+    entries: Arc<Vec<(InternalKey, Bytes)>>,
+    // `entries.len()` if iterator is not valid
+    pos:     usize,
+}
+
+impl VectorRepIterator {
+    fn current(&self) -> &(InternalKey, Bytes) {
+        self.entries.get(self.pos).expect("iterator is not valid")
+    }
+}
+
+impl MemTableRepIterator for VectorRepIterator {
+    fn valid(&self) -> bool {
+        self.pos < self.entries.len()
+    }
+
+    fn key(&self) -> Bytes {
+        self.current().0.encoded().clone()
+    }
+
+    fn value(&self) -> Bytes {
+        self.current().1.clone()
+    }
+
+    fn seek_to_first(&mut self) {
+        self.pos = 0;
+    }
+
+    fn seek_to_last(&mut self) {
+        self.pos = self.entries.len().saturating_sub(1);
+    }
+
+    fn seek(&mut self, target: &[u8]) {
+        let target = decode_key(target);
+        self.pos = self.entries.partition_point(|(key, _)| key < &target);
+    }
+
+    fn next(&mut self) {
+        self.current();
+        self.pos += 1;
+    }
+
+    fn prev(&mut self) {
+        self.current();
+        self.pos = self.pos.checked_sub(1).unwrap_or(self.entries.len());
+    }
+}
+
+fn decode_key(key: &[u8]) -> InternalKey {
+    InternalKey::decode(Bytes::copy_from_slice(key)).expect("memtable key must be an internal key")
+}
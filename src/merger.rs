@@ -0,0 +1,256 @@
+//! merge several sorted iterators into one
+
+use std::{
+    cmp::Ordering,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::{
+    comparator::Comparator,
+    db::DbError,
+    iterator::InternalIterator,
+};
+
+type Result<T> = std::result::Result<T, DbError>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// an iterator yielding entries of all children in `cmp` order
+///
+/// Entries with equal keys are all yielded, those of earlier children first when moving forward.
+pub struct MergingIterator {
+    cmp:       Arc<dyn Comparator>,
+    children:  Vec<Box<dyn InternalIterator>>,
+    // index of the child positioned at current entry
+    current:   Option<usize>,
+    // when moving forward, all other children are positioned after current entry, when moving
+    // backward, they are positioned before it
+    direction: Direction,
+}
+
+impl MergingIterator {
+    pub fn new(cmp: Arc<dyn Comparator>, children: Vec<Box<dyn InternalIterator>>) -> Self {
+        MergingIterator {
+            cmp,
+            children,
+            current: None,
+            direction: Direction::Forward,
+        }
+    }
+
+    fn find_smallest(&mut self) {
+        let mut smallest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate() {
+            if !child.valid() {
+                continue;
+            }
+            let smaller = match smallest {
+                Some(j) => {
+                    self.cmp.compare(&child.key(), &self.children[j].key()) == Ordering::Less
+                }
+                None => true,
+            };
+            if smaller {
+                smallest = Some(i);
+            }
+        }
+        self.current = smallest;
+    }
+
+    fn find_largest(&mut self) {
+        let mut largest: Option<usize> = None;
+        for (i, child) in self.children.iter().enumerate().rev() {
+            if !child.valid() {
+                continue;
+            }
+            let larger = match largest {
+                Some(j) => {
+                    self.cmp.compare(&child.key(), &self.children[j].key()) == Ordering::Greater
+                }
+                None => true,
+            };
+            if larger {
+                largest = Some(i);
+            }
+        }
+        self.current = largest;
+    }
+
+    fn current(&self) -> usize {
+        self.current.expect("iterator is not valid")
+    }
+}
+
+#[async_trait]
+impl InternalIterator for MergingIterator {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    async fn seek_to_first(&mut self) -> Result<()> {
+        for child in &mut self.children {
+            child.seek_to_first().await?;
+        }
+        self.find_smallest();
+        self.direction = Direction::Forward;
+        Ok(())
+    }
+
+    async fn seek_to_last(&mut self) -> Result<()> {
+        for child in &mut self.children {
+            child.seek_to_last().await?;
+        }
+        self.find_largest();
+        self.direction = Direction::Reverse;
+        Ok(())
+    }
+
+    async fn seek(&mut self, target: &[u8]) -> Result<()> {
+        for child in &mut self.children {
+            child.seek(target).await?;
+        }
+        self.find_smallest();
+        self.direction = Direction::Forward;
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Result<()> {
+        let current = self.current();
+        if self.direction != Direction::Forward {
+            // move all other children after current key
+            let key = self.children[current].key();
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if i == current {
+                    continue;
+                }
+                child.seek(&key).await?;
+                if child.valid() && self.cmp.compare(&key, &child.key()) == Ordering::Equal {
+                    child.next().await?;
+                }
+            }
+            self.direction = Direction::Forward;
+        }
+        self.children[current].next().await?;
+        self.find_smallest();
+        Ok(())
+    }
+
+    async fn prev(&mut self) -> Result<()> {
+        let current = self.current();
+        if self.direction != Direction::Reverse {
+            // move all other children before current key
+            let key = self.children[current].key();
+            for (i, child) in self.children.iter_mut().enumerate() {
+                if i == current {
+                    continue;
+                }
+                child.seek(&key).await?;
+                if child.valid() {
+                    child.prev().await?;
+                } else {
+                    // all entries of child are before current key
+                    child.seek_to_last().await?;
+                }
+            }
+            self.direction = Direction::Reverse;
+        }
+        self.children[current].prev().await?;
+        self.find_largest();
+        Ok(())
+    }
+
+    fn key(&self) -> Bytes {
+        self.children[self.current()].key()
+    }
+
+    fn value(&self) -> Bytes {
+        self.children[self.current()].value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comparator::InternalKeyComparator,
+        format::{
+            InternalKey,
+            ValueType,
+        },
+        mem_table::MemTable,
+        write_batch::WriteBatch,
+    };
+
+    async fn mem_table(keys: &[&str], seq: u64) -> Arc<MemTable> {
+        let mem = Arc::new(MemTable::new(Default::default()));
+        let mut batch = WriteBatch::new();
+        for key in keys {
+            batch.put(
+                Bytes::copy_from_slice(key.as_bytes()),
+                Bytes::from(seq.to_string()),
+            );
+        }
+        mem.apply(&batch, seq);
+        mem
+    }
+
+    #[tokio::test]
+    async fn test_merging_iterator() {
+        let children = [
+            mem_table(&["b", "e"], 10).await,
+            mem_table(&[], 20).await,
+            mem_table(&["a", "c", "e"], 1).await,
+        ];
+        let expected = vec![
+            InternalKey::new(b"a", 1, ValueType::Value),
+            InternalKey::new(b"b", 10, ValueType::Value),
+            InternalKey::new(b"c", 2, ValueType::Value),
+            InternalKey::new(b"e", 11, ValueType::Value),
+            InternalKey::new(b"e", 3, ValueType::Value),
+        ];
+        let mut iter = MergingIterator::new(
+            Arc::new(InternalKeyComparator),
+            children
+                .iter()
+                .map(|mem| Box::new(mem.iter()) as Box<dyn InternalIterator>)
+                .collect(),
+        );
+        assert!(!iter.valid());
+        iter.seek_to_first().await.unwrap();
+        for key in &expected {
+            assert_eq!(&iter.key(), key.encoded());
+            iter.next().await.unwrap();
+        }
+        assert!(!iter.valid());
+
+        iter.seek_to_last().await.unwrap();
+        for key in expected.iter().rev() {
+            assert_eq!(&iter.key(), key.encoded());
+            iter.prev().await.unwrap();
+        }
+        assert!(!iter.valid());
+
+        // change direction in the middle
+        iter.seek(InternalKey::new(b"c", 5, ValueType::FOR_SEEK).encoded())
+            .await
+            .unwrap();
+        assert_eq!(&iter.key(), expected[2].encoded());
+        iter.prev().await.unwrap();
+        assert_eq!(&iter.key(), expected[1].encoded());
+        iter.next().await.unwrap();
+        assert_eq!(&iter.key(), expected[2].encoded());
+        iter.next().await.unwrap();
+        assert_eq!(&iter.key(), expected[3].encoded());
+        assert_eq!(iter.value(), Bytes::from("10"));
+        iter.prev().await.unwrap();
+        assert_eq!(&iter.key(), expected[2].encoded());
+        assert_eq!(iter.value(), Bytes::from("1"));
+    }
+}
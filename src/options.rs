@@ -0,0 +1,179 @@
+//! options controlling db behaviors
+
+use std::sync::Arc;
+
+use crate::{
+    cache::BlockCache,
+    filter_policy::FilterPolicy,
+    snapshot::Snapshot,
+    statistics::Statistics,
+    vfs::{
+        FileSystem,
+        TokioFileSystem,
+    },
+};
+
+/// options for read operations
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// read from this snapshot instead of the latest state of db
+    pub snapshot:         Option<Snapshot>,
+    /// verify checksums of all data read from sstables
+    pub verify_checksums: bool,
+    /// keep blocks read in block cache, a bulk scan may unset it to keep cached blocks
+    pub fill_cache:       bool,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        ReadOptions {
+            snapshot:         None,
+            verify_checksums: false,
+            fill_cache:       true,
+        }
+    }
+}
+
+/// options for write operations
+#[derive(Clone, Debug, Default)]
+pub struct WriteOptions {
+    /// sync WAL before the write returns, so that the write survives a crash of machine,
+    /// otherwise it only survives a crash of process
+    pub sync: bool,
+}
+
+/// options for [`Db::compact_range_with_options`](crate::db::Db::compact_range_with_options)
+#[derive(Clone, Debug)]
+pub struct CompactRangeOptions {
+    /// keep automatic compactions from running until the manual compaction is done, otherwise
+    /// they take turns with each level the manual compaction compacts
+    pub exclusive_manual_compaction: bool,
+}
+
+impl Default for CompactRangeOptions {
+    fn default() -> Self {
+        CompactRangeOptions {
+            exclusive_manual_compaction: true,
+        }
+    }
+}
+
+/// how table files are organized and compacted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompactionStyle {
+    /// files are merged level by level, every level holds 10x more data than the one above
+    #[default]
+    Leveled,
+    /// sorted runs of similar size are merged, which writes data fewer times at the cost of
+    /// more runs to read and more space taken by shadowed data
+    Universal,
+}
+
+/// how memtable entries are held in memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemTableRepType {
+    /// a B-tree locked by every read and write
+    BTree,
+    /// an arena-backed skiplist reads don't lock, which suits reads concurrent with writes
+    #[default]
+    SkipList,
+    /// a vector appended by writes, which suits loading data in bulk before reading it
+    ///
+    /// The first read after writes sorts them and merges them into all entries, copying all
+    /// entries if an iterator created before holds them.
+    Vector,
+}
+
+/// options of [`CompactionStyle::Universal`]
+///
+/// Each level-0 table and each non-empty level below is a sorted run, newer runs come first.
+#[derive(Clone, Debug)]
+pub struct UniversalCompactionOptions {
+    /// a run is merged with the newer runs picked if it's at most this percent larger than
+    /// their total size
+    pub size_ratio:                     u64,
+    /// min number of runs merged by a compaction picked by size ratio
+    pub min_merge_width:                usize,
+    /// max number of runs merged by a compaction picked by size ratio
+    pub max_merge_width:                usize,
+    /// runs are merged once there are more than this, which is at least 1
+    pub max_sorted_runs:                usize,
+    /// all runs are merged once total size of runs other than the oldest exceeds this percent
+    /// of its size
+    pub max_size_amplification_percent: u64,
+}
+
+impl Default for UniversalCompactionOptions {
+    fn default() -> Self {
+        UniversalCompactionOptions {
+            size_ratio:                     1,
+            min_merge_width:                2,
+            max_merge_width:                usize::MAX,
+            max_sorted_runs:                4,
+            max_size_amplification_percent: 200,
+        }
+    }
+}
+
+/// options for opening db
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// file system db files are stored in
+    pub file_system:                        Arc<dyn FileSystem>,
+    /// bytes of data a memtable takes before it's flushed to a level-0 sstable
+    pub write_buffer_size:                  usize,
+    /// representation of memtables
+    pub memtable_rep:                       MemTableRepType,
+    /// compaction style of db, db written with another style is fully compacted on open
+    pub compaction_style:                   CompactionStyle,
+    /// options used by [`CompactionStyle::Universal`]
+    pub universal_compaction:               UniversalCompactionOptions,
+    /// number of level-0 files which triggers a compaction into level-1
+    pub level0_file_num_compaction_trigger: usize,
+    /// max total bytes of files in level-1, every following level takes 10x more
+    pub max_bytes_for_level_base:           u64,
+    /// size of a table file written by compaction before starting the next one
+    pub max_file_size:                      u64,
+    /// approximate size of user data packed per sstable block
+    pub block_size:                         usize,
+    /// verify checksums of all data read from sstables, as if every read sets
+    /// [`ReadOptions::verify_checksums`], and fail opening db on a corrupted WAL record instead of
+    /// dropping it along with the rest of WAL
+    pub paranoid_checks:                    bool,
+    /// policy creating filters of sstable data blocks, which saves reading data blocks of keys
+    /// not in them
+    pub filter_policy:                      Option<Arc<dyn FilterPolicy>>,
+    /// max number of files db keeps open, most of which are sstables cached with their index and
+    /// filter blocks parsed
+    pub max_open_files:                     usize,
+    /// cache of sstable blocks, which may be shared by several dbs
+    pub block_cache:                        Option<BlockCache>,
+    /// map sstables into memory and read blocks as slices of the mappings, which skips block
+    /// cache and suits data mostly read and fitting in memory, a value read keeps its file
+    /// mapped until it's dropped
+    pub mmap_reads:                         bool,
+    /// counters db records events to, a new one is created if it's not set
+    pub statistics:                         Option<Arc<Statistics>>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            file_system:                        Arc::new(TokioFileSystem),
+            write_buffer_size:                  4 * 1024 * 1024,
+            memtable_rep:                       MemTableRepType::default(),
+            compaction_style:                   CompactionStyle::Leveled,
+            universal_compaction:               UniversalCompactionOptions::default(),
+            level0_file_num_compaction_trigger: 4,
+            max_bytes_for_level_base:           10 * 1024 * 1024,
+            max_file_size:                      2 * 1024 * 1024,
+            block_size:                         4096,
+            paranoid_checks:                    false,
+            filter_policy:                      None,
+            max_open_files:                     1000,
+            block_cache:                        None,
+            mmap_reads:                         false,
+            statistics:                         None,
+        }
+    }
+}
@@ -0,0 +1,513 @@
+//! arena-backed skiplist allowing lock-free reads concurrent with inserts
+//!
+//! Nodes are allocated from an arena and never removed until the whole list is dropped, so a
+//! reader can follow next pointers without locks while inserts link new nodes in with CAS.
+
+use std::{
+    alloc::{
+        self,
+        Layout,
+    },
+    cmp::Ordering as CmpOrdering,
+    mem,
+    ptr,
+    sync::{
+        atomic::{
+            AtomicPtr,
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+        Mutex,
+    },
+};
+
+use bytes::Bytes;
+
+use crate::comparator::Comparator;
+
+const MAX_HEIGHT: usize = 12;
+// a node is promoted to the next level with probability 1/BRANCHING
+const BRANCHING: u64 = 4;
+const ARENA_BLOCK_SIZE: usize = 4096;
+const ALIGN: usize = mem::align_of::<u64>();
+
+/// a bump allocator freeing all its memory at once when dropped
+struct Arena {
+    inner: Mutex<ArenaInner>,
+}
+
+struct ArenaInner {
+    // free space of the current block
+    ptr:       *mut u8,
+    remaining: usize,
+    blocks:    Vec<(*mut u8, Layout)>,
+}
+
+// raw pointers of arena only point to blocks it owns
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    fn new() -> Self {
+        Arena {
+            inner: Mutex::new(ArenaInner {
+                ptr:       ptr::null_mut(),
+                remaining: 0,
+                blocks:    vec![],
+            }),
+        }
+    }
+
+    /// allocate `size` bytes aligned to 8, which live as long as the arena
+    fn allocate(&self, size: usize) -> *mut u8 {
+        let size = (size + ALIGN - 1) & !(ALIGN - 1);
+        let mut inner = self.inner.lock().unwrap();
+        if size > inner.remaining {
+            if size > ARENA_BLOCK_SIZE / 4 {
+                // a large allocation gets its own block, so that the rest of current block is
+                // not wasted
+                return inner.allocate_block(size);
+            }
+            inner.ptr = inner.allocate_block(ARENA_BLOCK_SIZE);
+            inner.remaining = ARENA_BLOCK_SIZE;
+        }
+        let result = inner.ptr;
+        inner.ptr = unsafe { inner.ptr.add(size) };
+        inner.remaining -= size;
+        result
+    }
+}
+
+impl ArenaInner {
+    fn allocate_block(&mut self, size: usize) -> *mut u8 {
+        let layout = Layout::from_size_align(size, ALIGN).unwrap();
+        let block = unsafe { alloc::alloc(layout) };
+        if block.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        self.blocks.push((block, layout));
+        block
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for &(block, layout) in &self.inner.get_mut().unwrap().blocks {
+            unsafe { alloc::dealloc(block, layout) };
+        }
+    }
+}
+
+/// a node of skiplist, followed by its tower of `height` next pointers and its key in arena
+///
+/// Keys are kept next to towers, so that a search doesn't jump to another place of memory for
+/// every key it compares.
+struct Node {
+    value:   Bytes,
+    height:  usize,
+    key_len: usize,
+}
+
+// offset of tower in node allocation
+const TOWER_OFFSET: usize = (mem::size_of::<Node>() + ALIGN - 1) & !(ALIGN - 1);
+
+/// next pointer of `node` at `level`
+///
+/// # Safety
+///
+/// `node` must be allocated by [`SkipList::new_node`] with a height above `level`.
+unsafe fn next_of<'a>(node: *const Node, level: usize) -> &'a AtomicPtr<Node> {
+    &*(node as *const u8)
+        .add(TOWER_OFFSET)
+        .cast::<AtomicPtr<Node>>()
+        .add(level)
+}
+
+/// key of `node`
+///
+/// # Safety
+///
+/// `node` must be allocated by [`SkipList::new_node`].
+unsafe fn key_of<'a>(node: *const Node) -> &'a [u8] {
+    let key =
+        (node as *const u8).add(TOWER_OFFSET + (*node).height * mem::size_of::<AtomicPtr<Node>>());
+    std::slice::from_raw_parts(key, (*node).key_len)
+}
+
+/// a sorted list of unique keys, which may be inserted and read concurrently
+pub struct SkipList<C> {
+    arena:      Arena,
+    cmp:        C,
+    head:       *mut Node,
+    // height of the highest node, which only grows
+    max_height: AtomicUsize,
+    rng:        AtomicU64,
+}
+
+// nodes are immutable once linked, and linked with atomic operations
+unsafe impl<C: Send> Send for SkipList<C> {}
+unsafe impl<C: Sync> Sync for SkipList<C> {}
+
+impl<C: Comparator> SkipList<C> {
+    /// create an empty list ordering keys by `cmp`
+    pub fn new(cmp: C) -> Self {
+        let mut list = SkipList {
+            arena: Arena::new(),
+            cmp,
+            head: ptr::null_mut(),
+            max_height: AtomicUsize::new(1),
+            rng: AtomicU64::new(0xdeadbeef),
+        };
+        list.head = list.new_node(&[], Bytes::new(), MAX_HEIGHT);
+        list
+    }
+
+    /// insert `key` with `value`, `key` must not be in list
+    ///
+    /// Inserts may run concurrently with each other and with reads.
+    pub fn insert(&self, key: &[u8], value: Bytes) {
+        let height = self.random_height();
+        let node = self.new_node(key, value, height);
+        self.max_height.fetch_max(height, Ordering::Relaxed);
+
+        // nodes before and after `key` at every level
+        let mut prev = [self.head; MAX_HEIGHT];
+        let mut next = [ptr::null_mut(); MAX_HEIGHT];
+        let mut x = self.head;
+        for level in (0..MAX_HEIGHT).rev() {
+            let (p, n) = self.find_splice_for_level(key, x, level);
+            prev[level] = p;
+            next[level] = n;
+            x = p;
+        }
+        // link from bottom up, so that a node reachable at a level is reachable at all levels
+        // below it
+        for level in 0..height {
+            loop {
+                unsafe {
+                    next_of(node, level).store(next[level], Ordering::Relaxed);
+                    let linked = next_of(prev[level], level).compare_exchange(
+                        next[level],
+                        node,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    );
+                    if linked.is_ok() {
+                        break;
+                    }
+                }
+                // another node is linked between, search again from the previous node
+                let (p, n) = self.find_splice_for_level(key, prev[level], level);
+                prev[level] = p;
+                next[level] = n;
+            }
+        }
+    }
+
+    /// the first entry at or after `key`
+    pub fn find(&self, key: &[u8]) -> Option<(&[u8], &Bytes)> {
+        let node = self.find_greater_or_equal(key);
+        if node.is_null() {
+            return None;
+        }
+        unsafe { Some((key_of(node), &(*node).value)) }
+    }
+
+    /// create an iterator over list
+    pub fn iter(self: &Arc<Self>) -> SkipListIterator<C> {
+        SkipListIterator {
+            list: self.clone(),
+            node: ptr::null(),
+        }
+    }
+
+    fn new_node(&self, key: &[u8], value: Bytes, height: usize) -> *mut Node {
+        let tower_size = height * mem::size_of::<AtomicPtr<Node>>();
+        let size = TOWER_OFFSET + tower_size + key.len();
+        let node = self.arena.allocate(size).cast::<Node>();
+        unsafe {
+            node.write(Node {
+                value,
+                height,
+                key_len: key.len(),
+            });
+            let dst = (node as *mut u8).add(TOWER_OFFSET + tower_size);
+            ptr::copy_nonoverlapping(key.as_ptr(), dst, key.len());
+            for level in 0..height {
+                let next = (node as *mut u8)
+                    .add(TOWER_OFFSET)
+                    .cast::<AtomicPtr<Node>>()
+                    .add(level);
+                next.write(AtomicPtr::new(ptr::null_mut()));
+            }
+        }
+        node
+    }
+
+    fn random_height(&self) -> usize {
+        let mut height = 1;
+        while height < MAX_HEIGHT && self.next_random().is_multiple_of(BRANCHING) {
+            height += 1;
+        }
+        height
+    }
+
+    /// xorshift, a lost update of a concurrent insert only repeats a number
+    fn next_random(&self) -> u64 {
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.store(x, Ordering::Relaxed);
+        x
+    }
+
+    /// compare key of `node` with `key`, null node is after all keys
+    fn compare_node(&self, node: *const Node, key: &[u8]) -> CmpOrdering {
+        if node.is_null() {
+            return CmpOrdering::Greater;
+        }
+        self.cmp.compare(unsafe { key_of(node) }, key)
+    }
+
+    /// nodes around `key` at `level`, searching from `start` which is before `key`
+    fn find_splice_for_level(
+        &self,
+        key: &[u8],
+        start: *mut Node,
+        level: usize,
+    ) -> (*mut Node, *mut Node) {
+        let mut prev = start;
+        loop {
+            let next = unsafe { next_of(prev, level).load(Ordering::Acquire) };
+            if self.compare_node(next, key) != CmpOrdering::Less {
+                return (prev, next);
+            }
+            prev = next;
+        }
+    }
+
+    /// nodes around `key` at level 0, the last node before `key` (head if there's none) and the
+    /// first node at or after `key` (null if there's none)
+    fn find_splice(&self, key: &[u8]) -> (*const Node, *const Node) {
+        let mut prev = self.head;
+        // a node found at or after `key` at an upper level, which isn't compared again when it's
+        // met at lower levels
+        let mut bound = ptr::null_mut();
+        for level in (0..self.max_height.load(Ordering::Relaxed)).rev() {
+            loop {
+                let next = unsafe { next_of(prev, level).load(Ordering::Acquire) };
+                if next == bound || self.compare_node(next, key) != CmpOrdering::Less {
+                    bound = next;
+                    break;
+                }
+                prev = next;
+            }
+        }
+        (prev, bound)
+    }
+
+    /// the first node at or after `key`, null if there's none
+    fn find_greater_or_equal(&self, key: &[u8]) -> *const Node {
+        self.find_splice(key).1
+    }
+
+    /// the last node before `key`, head if there's none
+    fn find_less_than(&self, key: &[u8]) -> *const Node {
+        self.find_splice(key).0
+    }
+
+    /// the last node, head if list is empty
+    fn find_last(&self) -> *const Node {
+        let mut x = self.head;
+        for level in (0..self.max_height.load(Ordering::Relaxed)).rev() {
+            loop {
+                let next = unsafe { next_of(x, level).load(Ordering::Acquire) };
+                if next.is_null() {
+                    break;
+                }
+                x = next;
+            }
+        }
+        x
+    }
+}
+
+impl<C> Drop for SkipList<C> {
+    fn drop(&mut self) {
+        // arena frees memory of nodes, but not buffers of their values
+        let mut x = self.head;
+        while !x.is_null() {
+            unsafe {
+                let next = next_of(x, 0).load(Ordering::Relaxed);
+                ptr::drop_in_place(x);
+                x = next;
+            }
+        }
+    }
+}
+
+/// iterator over [`SkipList`], which sees entries inserted after it's created
+pub struct SkipListIterator<C> {
+    list: Arc<SkipList<C>>,
+    // null if iterator is not valid
+    node: *const Node,
+}
+
+// the node is kept alive by the list the iterator holds
+unsafe impl<C: Send + Sync> Send for SkipListIterator<C> {}
+unsafe impl<C: Send + Sync> Sync for SkipListIterator<C> {}
+
+impl<C: Comparator> SkipListIterator<C> {
+    pub fn valid(&self) -> bool {
+        !self.node.is_null()
+    }
+
+    pub fn key(&self) -> &[u8] {
+        unsafe { key_of(self.current()) }
+    }
+
+    pub fn value(&self) -> &Bytes {
+        unsafe { &(*self.current()).value }
+    }
+
+    pub fn seek_to_first(&mut self) {
+        self.node = unsafe { next_of(self.list.head, 0).load(Ordering::Acquire) };
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.node = self.or_null(self.list.find_last());
+    }
+
+    /// move to the first entry at or after `target`
+    pub fn seek(&mut self, target: &[u8]) {
+        self.node = self.list.find_greater_or_equal(target);
+    }
+
+    pub fn next(&mut self) {
+        self.current();
+        self.node = unsafe { next_of(self.node, 0).load(Ordering::Acquire) };
+    }
+
+    pub fn prev(&mut self) {
+        self.node = self.or_null(self.list.find_less_than(self.key()));
+    }
+
+    fn current(&self) -> *const Node {
+        assert!(self.valid(), "iterator is not valid");
+        self.node
+    }
+
+    /// `node`, or null if it's head
+    fn or_null(&self, node: *const Node) -> *const Node {
+        if ptr::eq(node, self.list.head) {
+            ptr::null()
+        } else {
+            node
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeSet,
+        thread,
+    };
+
+    use super::*;
+    use crate::comparator::BytewiseComparator;
+
+    fn key(i: u64) -> Vec<u8> {
+        format!("key{:08}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_skiplist() {
+        let list = Arc::new(SkipList::new(BytewiseComparator));
+        let mut iter = list.iter();
+        iter.seek_to_first();
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+
+        let mut keys = BTreeSet::new();
+        let mut x = 1u64;
+        for _ in 0..2000 {
+            x = x * 7919 % 10007;
+            if keys.insert(x) {
+                list.insert(&key(x), Bytes::from(x.to_string()));
+            }
+        }
+
+        iter.seek_to_first();
+        for &k in &keys {
+            assert_eq!(iter.key(), &key(k)[..]);
+            assert_eq!(iter.value(), &Bytes::from(k.to_string()));
+            iter.next();
+        }
+        assert!(!iter.valid());
+        iter.seek_to_last();
+        for &k in keys.iter().rev() {
+            assert_eq!(iter.key(), &key(k)[..]);
+            iter.prev();
+        }
+        assert!(!iter.valid());
+
+        for i in 0..10010 {
+            iter.seek(&key(i));
+            match keys.range(i..).next() {
+                Some(&k) => assert_eq!(iter.key(), &key(k)[..]),
+                None => assert!(!iter.valid()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_skiplist_concurrent() {
+        const WRITERS: u64 = 4;
+        const N: u64 = 5000;
+        let list = Arc::new(SkipList::new(BytewiseComparator));
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|w| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    for i in 0..N {
+                        list.insert(&key(i * WRITERS + w), Bytes::new());
+                    }
+                })
+            })
+            .collect();
+        // readers always see a sorted list while it grows
+        let readers: Vec<_> = (0..2)
+            .map(|_| {
+                let list = list.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        let mut iter = list.iter();
+                        iter.seek_to_first();
+                        let mut last: Option<Vec<u8>> = None;
+                        while iter.valid() {
+                            assert!(last.as_deref().is_none_or(|last| last < iter.key()));
+                            last = Some(iter.key().to_vec());
+                            iter.next();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in writers.into_iter().chain(readers) {
+            handle.join().unwrap();
+        }
+
+        let mut iter = list.iter();
+        iter.seek_to_first();
+        for i in 0..N * WRITERS {
+            assert_eq!(iter.key(), &key(i)[..]);
+            iter.next();
+        }
+        assert!(!iter.valid());
+    }
+}
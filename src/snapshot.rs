@@ -0,0 +1,120 @@
+//! read snapshots
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc,
+        Mutex,
+    },
+};
+
+use crate::format::SequenceNumber;
+
+/// a consistent read-only view of db, pinned to the sequence of the last write when it was taken
+///
+/// Versions visible to a snapshot are kept until every clone of it is dropped.
+#[derive(Clone)]
+pub struct Snapshot {
+    inner: Arc<SnapshotInner>,
+}
+
+struct SnapshotInner {
+    seq:  SequenceNumber,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// sequence this snapshot reads at
+    pub(crate) fn sequence(&self) -> SequenceNumber {
+        self.inner.seq
+    }
+}
+
+impl std::fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("seq", &self.inner.seq)
+            .finish()
+    }
+}
+
+impl Drop for SnapshotInner {
+    fn drop(&mut self) {
+        self.list.release(self.seq);
+    }
+}
+
+/// live snapshots of a db, counted by sequence
+#[derive(Default)]
+pub struct SnapshotList {
+    live: Mutex<BTreeMap<SequenceNumber, usize>>,
+}
+
+impl SnapshotList {
+    /// create an empty [`SnapshotList`]
+    pub fn new() -> Self {
+        SnapshotList::default()
+    }
+
+    /// take a snapshot reading at the sequence `seq` returns
+    ///
+    /// `seq` is called with list locked, so that [`Self::oldest`] never misses a snapshot of a
+    /// sequence it has passed over.
+    pub fn acquire(self: &Arc<Self>, seq: impl FnOnce() -> SequenceNumber) -> Snapshot {
+        let mut live = self.live.lock().unwrap();
+        let seq = seq();
+        *live.entry(seq).or_default() += 1;
+        drop(live);
+        Snapshot {
+            inner: Arc::new(SnapshotInner {
+                seq,
+                list: self.clone(),
+            }),
+        }
+    }
+
+    /// sequence of the oldest live snapshot, or the sequence `latest` returns if there is none,
+    /// versions shadowed before it can't be read anymore
+    ///
+    /// `latest` is called with list locked, so that a snapshot taken later reads at or after it.
+    pub fn oldest(&self, latest: impl FnOnce() -> SequenceNumber) -> SequenceNumber {
+        let live = self.live.lock().unwrap();
+        live.keys().next().copied().unwrap_or_else(latest)
+    }
+
+    fn release(&self, seq: SequenceNumber) {
+        let mut live = self.live.lock().unwrap();
+        if let Some(count) = live.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&seq);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_list() {
+        let list = Arc::new(SnapshotList::new());
+        let latest = || 9;
+        assert_eq!(list.oldest(latest), 9);
+        let s1 = list.acquire(|| 5);
+        let s2 = list.acquire(|| 3);
+        let s3 = list.acquire(|| 3);
+        let s4 = s3.clone();
+        assert_eq!(s4.sequence(), 3);
+        assert_eq!(list.oldest(latest), 3);
+        drop(s2);
+        assert_eq!(list.oldest(latest), 3);
+        drop(s3);
+        assert_eq!(list.oldest(latest), 3);
+        drop(s4);
+        assert_eq!(list.oldest(latest), 5);
+        drop(s1);
+        assert_eq!(list.oldest(latest), 9);
+    }
+}
@@ -1,14 +1,124 @@
+use std::{
+    cmp::Ordering,
+    convert::TryInto,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
 use bytes::{
     BufMut,
     Bytes,
     BytesMut,
 };
+use thiserror::Error;
+
+use crate::{
+    cache::BlockCache,
+    comparator::{
+        BytewiseComparator,
+        Comparator,
+    },
+    db::DbError,
+    encoding::{
+        BufMutExt,
+        BytesExt,
+    },
+    filter_block::{
+        FilterBlockBuilder,
+        FilterBlockReader,
+    },
+    filter_policy::FilterPolicy,
+    iterator::InternalIterator,
+    options::{
+        Options,
+        ReadOptions,
+    },
+    statistics::Statistics,
+    vfs::{
+        RandomAccessFile,
+        VfsError,
+        WritableFile,
+    },
+};
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Error)]
+pub enum TableError {
+    #[error(transparent)]
+    VfsError(#[from] VfsError),
+    #[error("corrupted block: {0}")]
+    CorruptedBlockError(&'static str),
+    #[error("corrupted table: {0}")]
+    CorruptedTableError(&'static str),
+    #[error("corruption in {file} at offset {offset}: {reason}")]
+    Corruption {
+        file:   String,
+        offset: u64,
+        reason: &'static str,
+    },
+}
+
+impl TableError {
+    /// attach location to a corruption found when parsing data at `offset` of `file`
+    fn at(self, file: &dyn RandomAccessFile, offset: u64) -> Self {
+        match self {
+            TableError::CorruptedBlockError(reason) | TableError::CorruptedTableError(reason) => {
+                TableError::Corruption {
+                    file: file.path().display().to_string(),
+                    offset,
+                    reason,
+                }
+            }
+            err => err,
+        }
+    }
+}
 
-use crate::encoding::BufMutExt;
+type Result<T> = std::result::Result<T, TableError>;
 
 const RESTART_THRESHOLD: usize = 16;
 
-struct BlockHandle {}
+// Footer is metaindex and index handles padded to their max size, then magic number (8 bytes).
+const FOOTER_SIZE: usize = 2 * BlockHandle::MAX_ENCODED_SIZE + 8;
+const TABLE_MAGIC_NUMBER: u64 = 0xdb4775248b80fb57;
+// Block trailer is compression type (1 byte), masked crc32c of block and type (4 bytes).
+const BLOCK_TRAILER_SIZE: usize = 1 + 4;
+const NO_COMPRESSION: u8 = 0;
+const CRC_MASK_DELTA: u32 = 0xa282ead8;
+
+/// position of a block in table file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockHandle {
+    pub offset: u64,
+    pub size:   u64,
+}
+
+impl BlockHandle {
+    // offset and size as var u64 each
+    const MAX_ENCODED_SIZE: usize = 10 + 10;
+
+    fn encode_to(&self, buf: &mut impl BufMut) {
+        buf.put_var_u64_le(self.offset);
+        buf.put_var_u64_le(self.size);
+    }
+
+    fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(Self::MAX_ENCODED_SIZE);
+        self.encode_to(&mut buf);
+        buf.freeze()
+    }
+
+    fn decode_from(data: &mut Bytes) -> Result<Self> {
+        let mut get = || {
+            data.get_var_u64_le()
+                .ok_or(TableError::CorruptedTableError("bad block handle"))
+        };
+        Ok(BlockHandle {
+            offset: get()?,
+            size:   get()?,
+        })
+    }
+}
 
 struct BlockBuilder {
     buf:      BytesMut,
@@ -21,7 +131,7 @@ impl BlockBuilder {
     fn new() -> Self {
         BlockBuilder {
             buf:      BytesMut::new(),
-            restarts: vec![],
+            restarts: vec![0],
             count:    0,
             last_key: Bytes::new(),
         }
@@ -45,6 +155,15 @@ impl BlockBuilder {
         self.count += 1;
     }
 
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// size of block if it's built now
+    fn size_estimate(&self) -> usize {
+        self.buf.len() + self.restarts.len() * 4 + 4
+    }
+
     fn build(mut self) -> Bytes {
         let len = self.restarts.len();
         for i in self.restarts {
@@ -61,3 +180,1307 @@ fn shared_prefix_len(key1: &Bytes, key2: &Bytes) -> usize {
         .take_while(|(&a, &b)| a == b)
         .count()
 }
+
+/// a block built by [`BlockBuilder`], entries are followed by restart offsets (u32 each) and
+/// the number of restarts (u32)
+#[derive(Clone)]
+pub struct Block {
+    data:           Bytes,
+    restart_offset: usize,
+    num_restarts:   usize,
+}
+
+impl Block {
+    /// parse the restart array of block
+    pub fn new(data: Bytes) -> Result<Self> {
+        if data.len() < 4 {
+            return Err(TableError::CorruptedBlockError("block too short"));
+        }
+        let num_restarts = decode_u32_le(&data[data.len() - 4..]) as usize;
+        let max_restarts = (data.len() - 4) / 4;
+        if num_restarts == 0 || num_restarts > max_restarts {
+            return Err(TableError::CorruptedBlockError("bad restart count"));
+        }
+        let restart_offset = data.len() - 4 - num_restarts * 4;
+        Ok(Block {
+            data,
+            restart_offset,
+            num_restarts,
+        })
+    }
+
+    /// create an iterator over entries, which must be sorted by `cmp`
+    pub fn iter(&self, cmp: Arc<dyn Comparator>) -> BlockIterator {
+        BlockIterator {
+            block: self.clone(),
+            cmp,
+            current: self.restart_offset,
+            next: self.restart_offset,
+            restart_index: self.num_restarts,
+            key: Vec::new(),
+            value: Bytes::new(),
+        }
+    }
+
+    /// size of block in bytes
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn restart_point(&self, index: usize) -> usize {
+        let offset = self.restart_offset + index * 4;
+        decode_u32_le(&self.data[offset..offset + 4]) as usize
+    }
+}
+
+/// iterator over entries of a [`Block`]
+pub struct BlockIterator {
+    block:         Block,
+    cmp:           Arc<dyn Comparator>,
+    // offset of current entry, `restart_offset` if iterator is not valid
+    current:       usize,
+    // offset of the entry after current one
+    next:          usize,
+    // index of the restart block current entry is in
+    restart_index: usize,
+    key:           Vec<u8>,
+    value:         Bytes,
+}
+
+impl BlockIterator {
+    /// check if iterator is positioned at an entry
+    pub fn valid(&self) -> bool {
+        self.current < self.block.restart_offset
+    }
+
+    /// key of current entry, iterator must be valid
+    pub fn key(&self) -> &[u8] {
+        assert!(self.valid(), "iterator is not valid");
+        &self.key
+    }
+
+    /// value of current entry, iterator must be valid
+    pub fn value(&self) -> &Bytes {
+        assert!(self.valid(), "iterator is not valid");
+        &self.value
+    }
+
+    /// position at the first entry
+    pub fn seek_to_first(&mut self) -> Result<()> {
+        self.seek_to_restart(0);
+        self.parse_next()?;
+        Ok(())
+    }
+
+    /// position at the last entry
+    pub fn seek_to_last(&mut self) -> Result<()> {
+        self.seek_to_restart(self.block.num_restarts - 1);
+        while self.parse_next()? && self.next < self.block.restart_offset {}
+        Ok(())
+    }
+
+    /// position at the first entry whose key is at or past `target`
+    pub fn seek(&mut self, target: &[u8]) -> Result<()> {
+        // find the last restart point whose key is before target
+        let mut left = 0;
+        let mut right = self.block.num_restarts - 1;
+        while left < right {
+            let mid = (left + right).div_ceil(2);
+            let key = self.restart_key(mid)?;
+            if self.cmp.compare(&key, target) == Ordering::Less {
+                left = mid;
+            } else {
+                right = mid - 1;
+            }
+        }
+
+        self.seek_to_restart(left);
+        while self.parse_next()? {
+            if self.cmp.compare(&self.key, target) != Ordering::Less {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// move to the next entry, iterator must be valid
+    pub fn next(&mut self) -> Result<()> {
+        assert!(self.valid(), "iterator is not valid");
+        self.parse_next()?;
+        Ok(())
+    }
+
+    /// move to the previous entry, iterator must be valid
+    pub fn prev(&mut self) -> Result<()> {
+        assert!(self.valid(), "iterator is not valid");
+        // entries can only be decoded forward, scan from the restart point before current entry
+        let original = self.current;
+        while self.block.restart_point(self.restart_index) >= original {
+            if self.restart_index == 0 {
+                // no entry before the first one
+                self.invalidate();
+                return Ok(());
+            }
+            self.restart_index -= 1;
+        }
+        self.seek_to_restart(self.restart_index);
+        while self.parse_next()? && self.next < original {}
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.current = self.block.restart_offset;
+        self.next = self.block.restart_offset;
+        self.restart_index = self.block.num_restarts;
+        self.key.clear();
+        self.value = Bytes::new();
+    }
+
+    fn seek_to_restart(&mut self, index: usize) {
+        self.key.clear();
+        self.restart_index = index;
+        self.next = self.block.restart_point(index);
+    }
+
+    /// full key at restart point `index`, which shares nothing with previous entry
+    fn restart_key(&self, index: usize) -> Result<Bytes> {
+        let offset = self.block.restart_point(index);
+        let (shared, key, ..) = self.decode_entry(offset)?;
+        if shared != 0 {
+            return Err(TableError::CorruptedBlockError("bad restart point"));
+        }
+        Ok(key)
+    }
+
+    /// decode entry at `offset` into shared key length, non-shared key part, value and the offset
+    /// of next entry
+    fn decode_entry(&self, offset: usize) -> Result<(usize, Bytes, Bytes, usize)> {
+        if offset >= self.block.restart_offset {
+            return Err(TableError::CorruptedBlockError("bad entry offset"));
+        }
+        let mut entry = self.block.data.slice(offset..self.block.restart_offset);
+        let mut get_len = || {
+            entry
+                .get_var_u32_le()
+                .map(|len| len as usize)
+                .ok_or(TableError::CorruptedBlockError("bad entry header"))
+        };
+        let shared = get_len()?;
+        let non_shared = get_len()?;
+        let value_len = get_len()?;
+        if entry.len() < non_shared + value_len {
+            return Err(TableError::CorruptedBlockError("entry out of range"));
+        }
+        let key = entry.split_to(non_shared);
+        let value = entry.split_to(value_len);
+        let next = self.block.restart_offset - entry.len();
+        Ok((shared, key, value, next))
+    }
+
+    /// decode entry at `next`, return `false` if there are no more entries
+    fn parse_next(&mut self) -> Result<bool> {
+        self.current = self.next;
+        if self.current >= self.block.restart_offset {
+            self.invalidate();
+            return Ok(false);
+        }
+
+        let (shared, non_shared, value, next) = match self.decode_entry(self.current) {
+            Ok(entry) => entry,
+            Err(err) => {
+                self.invalidate();
+                return Err(err);
+            }
+        };
+        if shared > self.key.len() {
+            self.invalidate();
+            return Err(TableError::CorruptedBlockError("bad shared key length"));
+        }
+        self.key.truncate(shared);
+        self.key.extend_from_slice(&non_shared);
+        self.value = value;
+        self.next = next;
+        while self.restart_index + 1 < self.block.num_restarts &&
+            self.block.restart_point(self.restart_index + 1) <= self.current
+        {
+            self.restart_index += 1;
+        }
+        Ok(true)
+    }
+}
+
+/// write a sorted table into a [`WritableFile`]
+///
+/// Entries are packed into data blocks of about `block_size`, followed by a filter block if there
+/// is a filter policy, a metaindex block mapping the name of filter to its [`BlockHandle`], an
+/// index block mapping a separator key of each data block to its [`BlockHandle`], and a footer.
+pub struct TableBuilder {
+    file:           Box<dyn WritableFile>,
+    cmp:            Arc<dyn Comparator>,
+    block_size:     usize,
+    offset:         u64,
+    data_block:     BlockBuilder,
+    index_block:    BlockBuilder,
+    filter_policy:  Option<Arc<dyn FilterPolicy>>,
+    filter_block:   Option<FilterBlockBuilder>,
+    last_key:       Bytes,
+    num_entries:    u64,
+    // handle of the last data block, whose index entry waits for the first key of next block
+    pending_handle: Option<BlockHandle>,
+}
+
+impl TableBuilder {
+    /// create a builder writing to empty `file`, keys must be added in the order of `cmp`
+    pub fn new(options: &Options, cmp: Arc<dyn Comparator>, file: Box<dyn WritableFile>) -> Self {
+        TableBuilder {
+            file,
+            cmp,
+            block_size: options.block_size,
+            offset: 0,
+            data_block: BlockBuilder::new(),
+            index_block: BlockBuilder::new(),
+            filter_policy: options.filter_policy.clone(),
+            filter_block: options.filter_policy.clone().map(|policy| {
+                let mut builder = FilterBlockBuilder::new(policy);
+                builder.start_block(0);
+                builder
+            }),
+            last_key: Bytes::new(),
+            num_entries: 0,
+            pending_handle: None,
+        }
+    }
+
+    /// add an entry, `key` must be after every key added before
+    pub async fn add(&mut self, key: Bytes, value: Bytes) -> Result<()> {
+        debug_assert!(
+            self.num_entries == 0 || self.cmp.compare(&self.last_key, &key) == Ordering::Less
+        );
+        if let Some(handle) = self.pending_handle.take() {
+            let separator = self.cmp.find_shortest_separator(&self.last_key, &key);
+            self.index_block.add(separator.into(), handle.encode());
+        }
+
+        if let Some(filter_block) = &mut self.filter_block {
+            filter_block.add_key(key.clone());
+        }
+        self.last_key = key.clone();
+        self.num_entries += 1;
+        self.data_block.add(key, value);
+        if self.data_block.size_estimate() >= self.block_size {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// number of entries added
+    pub fn num_entries(&self) -> u64 {
+        self.num_entries
+    }
+
+    /// size of file written so far
+    pub fn file_size(&self) -> u64 {
+        self.offset
+    }
+
+    /// write out all entries and table metadata, return the underlying file and its size
+    pub async fn finish(mut self) -> Result<(Box<dyn WritableFile>, u64)> {
+        self.flush().await?;
+
+        let mut metaindex_block = BlockBuilder::new();
+        if let (Some(policy), Some(filter_block)) =
+            (self.filter_policy.clone(), self.filter_block.take())
+        {
+            let handle = self.write_block(filter_block.finish()).await?;
+            metaindex_block.add(filter_block_key(policy.as_ref()), handle.encode());
+        }
+        let metaindex_handle = self.write_block(metaindex_block.build()).await?;
+
+        if let Some(handle) = self.pending_handle.take() {
+            let successor = self.cmp.find_short_successor(&self.last_key);
+            self.index_block.add(successor.into(), handle.encode());
+        }
+        let index_block = std::mem::replace(&mut self.index_block, BlockBuilder::new());
+        let index_handle = self.write_block(index_block.build()).await?;
+
+        let mut footer = BytesMut::with_capacity(FOOTER_SIZE);
+        metaindex_handle.encode_to(&mut footer);
+        index_handle.encode_to(&mut footer);
+        footer.resize(FOOTER_SIZE - 8, 0);
+        footer.put_u64_le(TABLE_MAGIC_NUMBER);
+        self.file.append(&footer).await?;
+        self.offset += footer.len() as u64;
+
+        Ok((self.file, self.offset))
+    }
+
+    /// write out current data block
+    async fn flush(&mut self) -> Result<()> {
+        if self.data_block.is_empty() {
+            return Ok(());
+        }
+        let block = std::mem::replace(&mut self.data_block, BlockBuilder::new());
+        self.pending_handle = Some(self.write_block(block.build()).await?);
+        if let Some(filter_block) = &mut self.filter_block {
+            filter_block.start_block(self.offset);
+        }
+        Ok(())
+    }
+
+    async fn write_block(&mut self, block: Bytes) -> Result<BlockHandle> {
+        let handle = BlockHandle {
+            offset: self.offset,
+            size:   block.len() as u64,
+        };
+        let mut trailer = BytesMut::with_capacity(BLOCK_TRAILER_SIZE);
+        trailer.put_u8(NO_COMPRESSION);
+        trailer.put_u32_le(mask_crc(block_crc(&block, NO_COMPRESSION)));
+        self.file.append(&block).await?;
+        self.file.append(&trailer).await?;
+        self.offset += (block.len() + BLOCK_TRAILER_SIZE) as u64;
+        Ok(handle)
+    }
+}
+
+/// reader of a table written by [`TableBuilder`]
+pub struct Table {
+    file:        Box<dyn RandomAccessFile>,
+    // file number, which tells blocks of tables apart in block cache
+    number:      u64,
+    size:        u64,
+    cmp:         Arc<dyn Comparator>,
+    // verify checksums of every block read
+    paranoid:    bool,
+    index_block: Block,
+    filter:      Option<FilterBlockReader>,
+    block_cache: Option<BlockCache>,
+    statistics:  Option<Arc<Statistics>>,
+}
+
+impl Table {
+    /// open table in `file` of file `number`, whose keys are ordered by `cmp`
+    pub async fn open(
+        options: &Options,
+        number: u64,
+        file: Box<dyn RandomAccessFile>,
+        cmp: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let size = file.size().await?;
+        if size < FOOTER_SIZE as u64 {
+            return Err(
+                TableError::CorruptedTableError("file too short to be a table")
+                    .at(file.as_ref(), 0),
+            );
+        }
+        let footer_offset = size - FOOTER_SIZE as u64;
+        let footer = file.read_at(footer_offset, FOOTER_SIZE).await?;
+        let (metaindex_handle, index_handle) =
+            decode_footer(footer).map_err(|err| err.at(file.as_ref(), footer_offset))?;
+
+        let paranoid = options.paranoid_checks;
+        let index_block = read_block(file.as_ref(), size, index_handle, paranoid).await?;
+        let filter = match &options.filter_policy {
+            Some(policy) => {
+                read_filter(
+                    file.as_ref(),
+                    size,
+                    metaindex_handle,
+                    policy.clone(),
+                    paranoid,
+                )
+                .await
+            }
+            None => None,
+        };
+        Ok(Table {
+            number,
+            size,
+            cmp,
+            paranoid,
+            index_block,
+            filter,
+            // blocks of a mapped file are slices of the mapping, caching them saves nothing
+            block_cache: options.block_cache.clone().filter(|_| !file.is_mapped()),
+            statistics: options.statistics.clone(),
+            file,
+        })
+    }
+
+    /// find the first entry at or after `key` in the block which may contain `key`
+    ///
+    /// `None` means `key` is not in table, an entry after `key` may be skipped if it's in the next
+    /// block or the filter of block excludes `key`, which doesn't matter for point lookups.
+    pub async fn get(&self, options: &ReadOptions, key: &[u8]) -> Result<Option<(Bytes, Bytes)>> {
+        let mut index_iter = self.index_block.iter(self.cmp.clone());
+        let handle = self.seek_index(&mut index_iter, key)?;
+        let handle = match handle {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+        if let Some(filter) = &self.filter {
+            let may_match = filter.key_may_match(handle.offset, key);
+            if let Some(statistics) = &self.statistics {
+                match may_match {
+                    true => statistics.record_bloom_filter_positive(),
+                    false => statistics.record_bloom_filter_useful(),
+                }
+            }
+            if !may_match {
+                return Ok(None);
+            }
+        }
+        let block = self
+            .read_block(handle, self.verify(options), options.fill_cache)
+            .await?;
+        let mut iter = block.iter(self.cmp.clone());
+        iter.seek(key)
+            .map_err(|err| err.at(self.file.as_ref(), handle.offset))?;
+        if !iter.valid() {
+            return Ok(None);
+        }
+        Ok(Some((
+            Bytes::copy_from_slice(iter.key()),
+            iter.value().clone(),
+        )))
+    }
+
+    /// check if lookups are checked with a filter
+    pub fn has_filter(&self) -> bool {
+        self.filter.is_some()
+    }
+
+    /// create an iterator over all entries
+    pub fn iter(self: &Arc<Self>, options: &ReadOptions) -> TableIterator {
+        TableIterator {
+            table:       self.clone(),
+            verify:      self.verify(options),
+            fill_cache:  options.fill_cache,
+            index_iter:  self.index_block.iter(self.cmp.clone()),
+            data_iter:   None,
+            data_offset: 0,
+        }
+    }
+
+    fn verify(&self, options: &ReadOptions) -> bool {
+        self.paranoid || options.verify_checksums
+    }
+
+    /// seek index block for the handle of data block which may contain `key`
+    fn seek_index(
+        &self,
+        index_iter: &mut BlockIterator,
+        key: &[u8],
+    ) -> Result<Option<BlockHandle>> {
+        index_iter
+            .seek(key)
+            .map_err(|err| err.at(self.file.as_ref(), self.index_offset()))?;
+        self.index_value(index_iter)
+    }
+
+    /// handle at current entry of index block
+    fn index_value(&self, index_iter: &BlockIterator) -> Result<Option<BlockHandle>> {
+        if !index_iter.valid() {
+            return Ok(None);
+        }
+        let handle = BlockHandle::decode_from(&mut index_iter.value().clone())
+            .map_err(|err| err.at(self.file.as_ref(), self.index_offset()))?;
+        Ok(Some(handle))
+    }
+
+    fn index_offset(&self) -> u64 {
+        self.size - FOOTER_SIZE as u64
+    }
+
+    /// read block at `handle` through block cache, keeping it in cache if `fill_cache` is set
+    ///
+    /// Trailer of a block read from file is checked, a cached block is checked when it's read.
+    async fn read_block(
+        &self,
+        handle: BlockHandle,
+        verify: bool,
+        fill_cache: bool,
+    ) -> Result<Block> {
+        let cache = match &self.block_cache {
+            Some(cache) => cache,
+            None => return read_block(self.file.as_ref(), self.size, handle, verify).await,
+        };
+        let cached = cache.get(self.number, handle.offset);
+        if let Some(statistics) = &self.statistics {
+            match cached {
+                Some(_) => statistics.record_block_cache_hit(),
+                None => statistics.record_block_cache_miss(),
+            }
+        }
+        if let Some(block) = cached {
+            return Ok(block);
+        }
+        let block = read_block(self.file.as_ref(), self.size, handle, verify).await?;
+        if fill_cache {
+            cache.insert(self.number, handle.offset, block.clone());
+        }
+        Ok(block)
+    }
+}
+
+/// read block at `handle` from table `file` of `size` bytes, checking its trailer
+async fn read_block(
+    file: &dyn RandomAccessFile,
+    size: u64,
+    handle: BlockHandle,
+    verify: bool,
+) -> Result<Block> {
+    let data = read_block_contents(file, size, handle, verify).await?;
+    Block::new(data).map_err(|err| err.at(file, handle.offset))
+}
+
+/// read contents of block at `handle` from table `file` of `size` bytes, checking its trailer
+async fn read_block_contents(
+    file: &dyn RandomAccessFile,
+    size: u64,
+    handle: BlockHandle,
+    verify: bool,
+) -> Result<Bytes> {
+    let corruption = |reason| TableError::CorruptedBlockError(reason).at(file, handle.offset);
+    // sums of a corrupted handle may overflow
+    let end = handle
+        .size
+        .checked_add(BLOCK_TRAILER_SIZE as u64)
+        .and_then(|len| handle.offset.checked_add(len));
+    if !matches!(end, Some(end) if end <= size) {
+        return Err(corruption("block handle out of range"));
+    }
+    let len = handle.size + BLOCK_TRAILER_SIZE as u64;
+    let mut data = file.read_at(handle.offset, len as usize).await?;
+    let trailer = data.split_off(handle.size as usize);
+    let ty = trailer[0];
+    if verify {
+        let expected = unmask_crc(decode_u32_le(&trailer[1..]));
+        if block_crc(&data, ty) != expected {
+            return Err(corruption("block checksum mismatch"));
+        }
+    }
+    if ty != NO_COMPRESSION {
+        return Err(corruption("unknown compression type"));
+    }
+    Ok(data)
+}
+
+/// key of filter block created by `policy` in metaindex block
+fn filter_block_key(policy: &dyn FilterPolicy) -> Bytes {
+    format!("filter.{}", policy.name()).into()
+}
+
+/// read filter block created by `policy` through metaindex block at `metaindex_handle`
+///
+/// Table is still readable without filter, so `None` is returned if filter is missing or can't
+/// be read.
+async fn read_filter(
+    file: &dyn RandomAccessFile,
+    size: u64,
+    metaindex_handle: BlockHandle,
+    policy: Arc<dyn FilterPolicy>,
+    verify: bool,
+) -> Option<FilterBlockReader> {
+    let metaindex_block = read_block(file, size, metaindex_handle, verify)
+        .await
+        .ok()?;
+    let mut iter = metaindex_block.iter(Arc::new(BytewiseComparator));
+    let key = filter_block_key(policy.as_ref());
+    iter.seek(&key).ok()?;
+    if !iter.valid() || iter.key() != &key[..] {
+        return None;
+    }
+    let handle = BlockHandle::decode_from(&mut iter.value().clone()).ok()?;
+    let data = read_block_contents(file, size, handle, verify).await.ok()?;
+    FilterBlockReader::new(policy, data)
+}
+
+/// decode metaindex and index handles in footer
+fn decode_footer(mut footer: Bytes) -> Result<(BlockHandle, BlockHandle)> {
+    let magic = u64::from_le_bytes(footer[FOOTER_SIZE - 8..].try_into().unwrap());
+    if magic != TABLE_MAGIC_NUMBER {
+        return Err(TableError::CorruptedTableError("bad magic number"));
+    }
+    let metaindex_handle = BlockHandle::decode_from(&mut footer)?;
+    let index_handle = BlockHandle::decode_from(&mut footer)?;
+    Ok((metaindex_handle, index_handle))
+}
+
+/// crc32c of block contents and its compression type
+fn block_crc(block: &[u8], ty: u8) -> u32 {
+    use crc::crc32::Hasher32;
+    let mut digest = crc::crc32::Digest::new(crc::crc32::CASTAGNOLI);
+    digest.write(block);
+    digest.write(&[ty]);
+    digest.sum32()
+}
+
+/// mask crc stored along with data, since computing crc of data with embedded crc is problematic
+fn mask_crc(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(CRC_MASK_DELTA)
+}
+
+fn unmask_crc(masked: u32) -> u32 {
+    let rot = masked.wrapping_sub(CRC_MASK_DELTA);
+    rot.rotate_left(15)
+}
+
+/// iterator over a [`Table`], walking the index block and loading data blocks on demand
+pub struct TableIterator {
+    table:       Arc<Table>,
+    verify:      bool,
+    fill_cache:  bool,
+    index_iter:  BlockIterator,
+    // iterator of the data block pointed by `index_iter`
+    data_iter:   Option<BlockIterator>,
+    data_offset: u64,
+}
+
+impl TableIterator {
+    /// load data block at current index entry
+    async fn init_data_block(&mut self) -> Result<()> {
+        let handle = match self.table.index_value(&self.index_iter)? {
+            Some(handle) => handle,
+            None => {
+                self.data_iter = None;
+                return Ok(());
+            }
+        };
+        let block = self
+            .table
+            .read_block(handle, self.verify, self.fill_cache)
+            .await?;
+        self.data_iter = Some(block.iter(self.table.cmp.clone()));
+        self.data_offset = handle.offset;
+        Ok(())
+    }
+
+    /// attach location to an error found when moving index iterator
+    fn index_error(&self, err: TableError) -> TableError {
+        err.at(self.table.file.as_ref(), self.table.index_offset())
+    }
+
+    /// attach location to an error found when moving data iterator
+    fn data_error(&self, err: TableError) -> TableError {
+        err.at(self.table.file.as_ref(), self.data_offset)
+    }
+
+    /// move to following data blocks until an entry is found
+    async fn skip_empty_blocks_forward(&mut self) -> Result<()> {
+        while !self.data_valid() {
+            if !self.index_iter.valid() {
+                self.data_iter = None;
+                return Ok(());
+            }
+            self.index_iter
+                .next()
+                .map_err(|err| self.index_error(err))?;
+            self.init_data_block().await?;
+            if let Some(iter) = &mut self.data_iter {
+                let result = iter.seek_to_first();
+                result.map_err(|err| self.data_error(err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// move to preceding data blocks until an entry is found
+    async fn skip_empty_blocks_backward(&mut self) -> Result<()> {
+        while !self.data_valid() {
+            if !self.index_iter.valid() {
+                self.data_iter = None;
+                return Ok(());
+            }
+            self.index_iter
+                .prev()
+                .map_err(|err| self.index_error(err))?;
+            self.init_data_block().await?;
+            if let Some(iter) = &mut self.data_iter {
+                let result = iter.seek_to_last();
+                result.map_err(|err| self.data_error(err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// position index iterator with `seek_index`, then data iterator with `seek_data`
+    async fn position(
+        &mut self,
+        seek_index: impl FnOnce(&mut BlockIterator) -> Result<()>,
+        seek_data: impl FnOnce(&mut BlockIterator) -> Result<()>,
+    ) -> Result<()> {
+        seek_index(&mut self.index_iter).map_err(|err| self.index_error(err))?;
+        self.init_data_block().await?;
+        if let Some(iter) = &mut self.data_iter {
+            let result = seek_data(iter);
+            result.map_err(|err| self.data_error(err))?;
+        }
+        Ok(())
+    }
+
+    /// move data iterator with `step`, iterator must be valid
+    fn step(&mut self, step: impl FnOnce(&mut BlockIterator) -> Result<()>) -> Result<()> {
+        let iter = self.data_iter.as_mut().expect("iterator is not valid");
+        let result = step(iter);
+        result.map_err(|err| self.data_error(err))
+    }
+
+    fn data_valid(&self) -> bool {
+        self.data_iter.as_ref().is_some_and(BlockIterator::valid)
+    }
+}
+
+#[async_trait]
+impl InternalIterator for TableIterator {
+    fn valid(&self) -> bool {
+        self.data_valid()
+    }
+
+    async fn seek_to_first(&mut self) -> std::result::Result<(), DbError> {
+        self.position(BlockIterator::seek_to_first, BlockIterator::seek_to_first)
+            .await?;
+        self.skip_empty_blocks_forward().await?;
+        Ok(())
+    }
+
+    async fn seek_to_last(&mut self) -> std::result::Result<(), DbError> {
+        self.position(BlockIterator::seek_to_last, BlockIterator::seek_to_last)
+            .await?;
+        self.skip_empty_blocks_backward().await?;
+        Ok(())
+    }
+
+    async fn seek(&mut self, target: &[u8]) -> std::result::Result<(), DbError> {
+        self.position(|iter| iter.seek(target), |iter| iter.seek(target))
+            .await?;
+        self.skip_empty_blocks_forward().await?;
+        Ok(())
+    }
+
+    async fn next(&mut self) -> std::result::Result<(), DbError> {
+        self.step(BlockIterator::next)?;
+        self.skip_empty_blocks_forward().await?;
+        Ok(())
+    }
+
+    async fn prev(&mut self) -> std::result::Result<(), DbError> {
+        self.step(BlockIterator::prev)?;
+        self.skip_empty_blocks_backward().await?;
+        Ok(())
+    }
+
+    fn key(&self) -> Bytes {
+        let iter = self.data_iter.as_ref().expect("iterator is not valid");
+        Bytes::copy_from_slice(iter.key())
+    }
+
+    fn value(&self) -> Bytes {
+        let iter = self.data_iter.as_ref().expect("iterator is not valid");
+        iter.value().clone()
+    }
+}
+
+fn decode_u32_le(data: &[u8]) -> u32 {
+    u32::from_le_bytes(data[..4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        comparator::{
+            BytewiseComparator,
+            InternalKeyComparator,
+        },
+        filter_policy::BloomFilterPolicy,
+        format::{
+            InternalKey,
+            ValueType,
+        },
+        vfs::{
+            TokioFileSystem,
+            Vfs,
+        },
+    };
+
+    fn build_block(entries: &[(Bytes, Bytes)]) -> Block {
+        let mut builder = BlockBuilder::new();
+        for (key, value) in entries {
+            builder.add(key.clone(), value.clone());
+        }
+        Block::new(builder.build()).unwrap()
+    }
+
+    fn gen_entries(n: usize) -> Vec<(Bytes, Bytes)> {
+        (0..n)
+            .map(|i| {
+                let key = format!("key{:05}", i * 2);
+                let value = "v".repeat(i % 7);
+                (key.into(), value.into())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_empty() {
+        let block = build_block(&[]);
+        let mut iter = block.iter(Arc::new(BytewiseComparator));
+        iter.seek_to_first().unwrap();
+        assert!(!iter.valid());
+        iter.seek_to_last().unwrap();
+        assert!(!iter.valid());
+        iter.seek(b"key").unwrap();
+        assert!(!iter.valid());
+    }
+
+    #[test]
+    fn test_block_iterate() {
+        for &n in &[1, 15, 16, 17, 100] {
+            let entries = gen_entries(n);
+            let block = build_block(&entries);
+            let mut iter = block.iter(Arc::new(BytewiseComparator));
+
+            iter.seek_to_first().unwrap();
+            for (key, value) in &entries {
+                assert_eq!(iter.key(), &key[..]);
+                assert_eq!(iter.value(), value);
+                iter.next().unwrap();
+            }
+            assert!(!iter.valid());
+
+            iter.seek_to_last().unwrap();
+            for (key, value) in entries.iter().rev() {
+                assert_eq!(iter.key(), &key[..]);
+                assert_eq!(iter.value(), value);
+                iter.prev().unwrap();
+            }
+            assert!(!iter.valid());
+        }
+    }
+
+    #[test]
+    fn test_block_seek() {
+        let entries = gen_entries(100);
+        let block = build_block(&entries);
+        let mut iter = block.iter(Arc::new(BytewiseComparator));
+        for (i, (key, value)) in entries.iter().enumerate() {
+            iter.seek(key).unwrap();
+            assert_eq!(iter.key(), &key[..]);
+            assert_eq!(iter.value(), value);
+
+            // a key between two entries
+            let target = format!("key{:05}", i * 2 + 1);
+            iter.seek(target.as_bytes()).unwrap();
+            match entries.get(i + 1) {
+                Some((next, _)) => assert_eq!(iter.key(), &next[..]),
+                None => assert!(!iter.valid()),
+            }
+        }
+        iter.seek(b"").unwrap();
+        assert_eq!(iter.key(), &entries[0].0[..]);
+        iter.seek(b"z").unwrap();
+        assert!(!iter.valid());
+
+        // change direction after seek
+        iter.seek(b"key00033").unwrap();
+        assert_eq!(iter.key(), b"key00034");
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"key00032");
+        iter.prev().unwrap();
+        assert_eq!(iter.key(), b"key00030");
+        iter.next().unwrap();
+        assert_eq!(iter.key(), b"key00032");
+    }
+
+    #[test]
+    fn test_block_corrupted() {
+        assert!(Block::new(Bytes::from_static(&[1, 0])).is_err());
+        assert!(Block::new(Bytes::from_static(&[0, 0, 0, 0])).is_err());
+        assert!(Block::new(Bytes::from_static(&[0, 0, 0, 0, 9, 0, 0, 0])).is_err());
+
+        // an entry claiming more bytes than the block has
+        let mut data = BytesMut::new();
+        data.put_var_u32_le(0);
+        data.put_var_u32_le(100);
+        data.put_var_u32_le(0);
+        data.put_u32_le(0);
+        data.put_u32_le(1);
+        let block = Block::new(data.freeze()).unwrap();
+        let mut iter = block.iter(Arc::new(BytewiseComparator));
+        assert!(iter.seek_to_first().is_err());
+        assert!(!iter.valid());
+    }
+
+    async fn build_table(
+        options: &Options,
+        cmp: Arc<dyn Comparator>,
+        entries: &[(Bytes, Bytes)],
+    ) -> Arc<Table> {
+        let dir = tempfile::tempdir().unwrap().into_path();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir).await.unwrap();
+        let file = vfs.create("000001.ldb").await.unwrap();
+        let mut builder = TableBuilder::new(options, cmp.clone(), file);
+        for (key, value) in entries {
+            builder.add(key.clone(), value.clone()).await.unwrap();
+        }
+        assert_eq!(builder.num_entries(), entries.len() as u64);
+        let (_, size) = builder.finish().await.unwrap();
+        let file = vfs.open("000001.ldb").await.unwrap();
+        assert_eq!(file.size().await.unwrap(), size);
+        Arc::new(Table::open(options, 1, file, cmp).await.unwrap())
+    }
+
+    async fn check_table(table: &Arc<Table>, entries: &[(Bytes, Bytes)]) {
+        let mut iter = table.iter(&ReadOptions::default());
+        iter.seek_to_first().await.unwrap();
+        for (key, value) in entries {
+            assert_eq!(&iter.key(), key);
+            assert_eq!(&iter.value(), value);
+            iter.next().await.unwrap();
+        }
+        assert!(!iter.valid());
+
+        iter.seek_to_last().await.unwrap();
+        for (key, value) in entries.iter().rev() {
+            assert_eq!(&iter.key(), key);
+            assert_eq!(&iter.value(), value);
+            iter.prev().await.unwrap();
+        }
+        assert!(!iter.valid());
+
+        for (i, (key, value)) in entries.iter().enumerate() {
+            assert_eq!(
+                table.get(&ReadOptions::default(), key).await.unwrap(),
+                Some((key.clone(), value.clone()))
+            );
+            iter.seek(key).await.unwrap();
+            assert_eq!(&iter.key(), key);
+            if i > 0 {
+                iter.prev().await.unwrap();
+                assert_eq!(iter.key(), entries[i - 1].0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_table_round_trip() {
+        for &block_size in &[64, 1024, 4096] {
+            let options = Options {
+                block_size,
+                ..Options::default()
+            };
+            let entries = gen_entries(1000);
+            let table = build_table(&options, Arc::new(BytewiseComparator), &entries).await;
+            check_table(&table, &entries).await;
+
+            let mut iter = table.iter(&ReadOptions::default());
+            iter.seek(b"key00101").await.unwrap();
+            assert_eq!(iter.key(), Bytes::from("key00102"));
+            iter.seek(b"z").await.unwrap();
+            assert!(!iter.valid());
+            assert_eq!(
+                table.get(&ReadOptions::default(), b"z").await.unwrap(),
+                None
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_table_internal_keys() {
+        let options = Options {
+            block_size: 128,
+            ..Options::default()
+        };
+        let mut entries = vec![];
+        for i in 0..200u64 {
+            let user_key = format!("user{:03}", i / 3);
+            let ty = if i % 5 == 0 {
+                ValueType::Deletion
+            } else {
+                ValueType::Value
+            };
+            let key = InternalKey::new(user_key.as_bytes(), 1000 - i, ty);
+            entries.push((key.encoded().clone(), Bytes::from(format!("value{}", i))));
+        }
+        let table = build_table(&options, Arc::new(InternalKeyComparator), &entries).await;
+        check_table(&table, &entries).await;
+
+        // lookup the newest version visible at a sequence
+        let lookup = InternalKey::new(b"user010", 975, ValueType::FOR_SEEK);
+        let (key, _) = table
+            .get(&ReadOptions::default(), lookup.encoded())
+            .await
+            .unwrap()
+            .unwrap();
+        let key = InternalKey::decode(key).unwrap();
+        assert_eq!(key.user_key(), b"user010");
+        assert_eq!(key.sequence(), 970);
+    }
+
+    #[tokio::test]
+    async fn test_table_filter() {
+        let statistics = Arc::new(Statistics::new());
+        let options = Options {
+            block_size: 128,
+            filter_policy: Some(Arc::new(BloomFilterPolicy::new(10))),
+            statistics: Some(statistics.clone()),
+            ..Options::default()
+        };
+        let entries = gen_entries(1000);
+        let table = build_table(&options, Arc::new(BytewiseComparator), &entries).await;
+        assert!(table.has_filter());
+        check_table(&table, &entries).await;
+        for (key, value) in &entries {
+            let found = table.get(&ReadOptions::default(), key).await.unwrap();
+            assert_eq!(found, Some((key.clone(), value.clone())));
+        }
+        assert_eq!(statistics.bloom_filter_useful(), 0);
+
+        // missing keys between existing ones are mostly excluded without reading data blocks
+        for (key, _) in &entries {
+            let mut missing = key.to_vec();
+            missing.push(0);
+            let found = table.get(&ReadOptions::default(), &missing).await.unwrap();
+            assert_ne!(found.map(|(found, _)| found), Some(Bytes::from(missing)));
+        }
+        assert!(statistics.bloom_filter_useful() > 950);
+
+        // table is readable without filter
+        let options = Options {
+            block_size: 128,
+            ..Options::default()
+        };
+        let path = table.file.path().to_owned();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), path.parent().unwrap().to_owned())
+            .await
+            .unwrap();
+        let file = vfs.open(path.file_name().unwrap()).await.unwrap();
+        let table = Table::open(&options, 1, file, Arc::new(BytewiseComparator))
+            .await
+            .unwrap();
+        assert!(!table.has_filter());
+        check_table(&Arc::new(table), &entries).await;
+    }
+
+    #[tokio::test]
+    async fn test_table_block_cache() {
+        let statistics = Arc::new(Statistics::new());
+        let cache = BlockCache::new(1 << 20);
+        let options = Options {
+            block_size: 128,
+            block_cache: Some(cache.clone()),
+            statistics: Some(statistics.clone()),
+            ..Options::default()
+        };
+        let entries = gen_entries(200);
+        let table = build_table(&options, Arc::new(BytewiseComparator), &entries).await;
+        let no_fill = ReadOptions {
+            fill_cache: false,
+            ..ReadOptions::default()
+        };
+        let key = &entries[100].0;
+        table.get(&no_fill, key).await.unwrap().unwrap();
+        assert_eq!(cache.usage(), 0);
+        assert_eq!(statistics.block_cache_miss(), 1);
+
+        table
+            .get(&ReadOptions::default(), key)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(cache.usage() > 0);
+        let found = table.get(&no_fill, key).await.unwrap();
+        assert_eq!(found, Some(entries[100].clone()));
+        assert_eq!(statistics.block_cache_hit(), 1);
+        assert_eq!(statistics.block_cache_miss(), 2);
+
+        // iterators read through cache too
+        check_table(&table, &entries).await;
+        let misses = statistics.block_cache_miss();
+        check_table(&table, &entries).await;
+        assert_eq!(statistics.block_cache_miss(), misses);
+        assert!(cache.usage() < 1 << 20);
+    }
+
+    #[tokio::test]
+    async fn test_table_empty() {
+        let table = build_table(&Options::default(), Arc::new(BytewiseComparator), &[]).await;
+        check_table(&table, &[]).await;
+        assert_eq!(
+            table.get(&ReadOptions::default(), b"key").await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_table_checksum() {
+        let dir = tempfile::tempdir().unwrap().into_path();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.clone())
+            .await
+            .unwrap();
+        let options = Options {
+            block_size: 64,
+            ..Options::default()
+        };
+        let cmp: Arc<dyn Comparator> = Arc::new(BytewiseComparator);
+        let mut builder = TableBuilder::new(
+            &options,
+            cmp.clone(),
+            vfs.create("000001.ldb").await.unwrap(),
+        );
+        for (key, value) in gen_entries(100) {
+            builder.add(key, value).await.unwrap();
+        }
+        builder.finish().await.unwrap();
+
+        // flip value of "key00002" in the first data block
+        let path = dir.join("000001.ldb");
+        let mut data = std::fs::read(&path).unwrap();
+        assert_eq!(data[15], b'v');
+        data[15] = b'w';
+        std::fs::write(&path, data).unwrap();
+
+        let table = Arc::new(
+            Table::open(
+                &options,
+                1,
+                vfs.open("000001.ldb").await.unwrap(),
+                cmp.clone(),
+            )
+            .await
+            .unwrap(),
+        );
+        let (_, value) = table
+            .get(&ReadOptions::default(), b"key00002")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, Bytes::from("w"));
+
+        let verify = ReadOptions {
+            verify_checksums: true,
+            ..ReadOptions::default()
+        };
+        let err = table.get(&verify, b"key00002").await.unwrap_err();
+        assert!(matches!(err, TableError::Corruption { offset: 0, .. }));
+        match DbError::from(err) {
+            DbError::Corruption { file, offset } => {
+                assert!(file.ends_with("000001.ldb"));
+                assert_eq!(offset, 0);
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+        // blocks after the corrupted one are fine
+        assert!(table.get(&verify, b"key00198").await.unwrap().is_some());
+
+        let mut iter = table.iter(&verify);
+        assert!(iter.seek_to_first().await.is_err());
+        let mut iter = table.iter(&ReadOptions::default());
+        iter.seek_to_first().await.unwrap();
+
+        let paranoid = Options {
+            paranoid_checks: true,
+            ..options
+        };
+        let table = Table::open(&paranoid, 1, vfs.open("000001.ldb").await.unwrap(), cmp)
+            .await
+            .unwrap();
+        assert!(table
+            .get(&ReadOptions::default(), b"key00002")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_mask_crc() {
+        let crc = block_crc(b"foo", NO_COMPRESSION);
+        assert_ne!(mask_crc(crc), crc);
+        assert_ne!(mask_crc(mask_crc(crc)), crc);
+        assert_eq!(unmask_crc(mask_crc(crc)), crc);
+        assert_eq!(unmask_crc(unmask_crc(mask_crc(mask_crc(crc)))), crc);
+    }
+
+    #[tokio::test]
+    async fn test_table_bad_index_handle() {
+        let dir = tempfile::tempdir().unwrap().into_path();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.clone())
+            .await
+            .unwrap();
+        let options = Options::default();
+        let mut builder = TableBuilder::new(
+            &options,
+            Arc::new(BytewiseComparator),
+            vfs.create("000001.ldb").await.unwrap(),
+        );
+        for (key, value) in gen_entries(10) {
+            builder.add(key, value).await.unwrap();
+        }
+        builder.finish().await.unwrap();
+        let path = dir.join("000001.ldb");
+        let data = std::fs::read(&path).unwrap();
+        let footer_offset = data.len() - FOOTER_SIZE;
+        let (metaindex_handle, _) =
+            decode_footer(Bytes::copy_from_slice(&data[footer_offset..])).unwrap();
+
+        let bad_handles = [
+            BlockHandle {
+                offset: u64::MAX - 2,
+                size:   0,
+            },
+            BlockHandle {
+                offset: 0,
+                size:   u64::MAX - 2,
+            },
+            BlockHandle {
+                offset: footer_offset as u64,
+                size:   FOOTER_SIZE as u64,
+            },
+        ];
+        for index_handle in bad_handles {
+            let mut footer = BytesMut::with_capacity(FOOTER_SIZE);
+            metaindex_handle.encode_to(&mut footer);
+            index_handle.encode_to(&mut footer);
+            footer.resize(FOOTER_SIZE - 8, 0);
+            footer.put_u64_le(TABLE_MAGIC_NUMBER);
+            let mut data = data.clone();
+            data[footer_offset..].copy_from_slice(&footer);
+            std::fs::write(&path, data).unwrap();
+
+            let file = vfs.open("000001.ldb").await.unwrap();
+            let err = Table::open(&options, 1, file, Arc::new(BytewiseComparator))
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(
+                err,
+                TableError::Corruption {
+                    reason: "block handle out of range",
+                    ..
+                }
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_table_bad_footer() {
+        let dir = tempfile::tempdir().unwrap().into_path();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir).await.unwrap();
+        let mut file = vfs.create("short.ldb").await.unwrap();
+        file.append(b"not a table").await.unwrap();
+        let file = vfs.open("short.ldb").await.unwrap();
+        assert!(
+            Table::open(&Options::default(), 1, file, Arc::new(BytewiseComparator))
+                .await
+                .is_err()
+        );
+
+        let mut file = vfs.create("magic.ldb").await.unwrap();
+        file.append(&[0u8; 100]).await.unwrap();
+        let file = vfs.open("magic.ldb").await.unwrap();
+        assert!(
+            Table::open(&Options::default(), 1, file, Arc::new(BytewiseComparator))
+                .await
+                .is_err()
+        );
+    }
+}
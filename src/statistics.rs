@@ -0,0 +1,70 @@
+//! counters of db events
+
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
+
+/// counters of db events, which may be shared by several dbs through
+/// [`Options::statistics`](crate::Options::statistics)
+#[derive(Debug, Default)]
+pub struct Statistics {
+    bloom_filter_useful:        AtomicU64,
+    bloom_filter_positive:      AtomicU64,
+    bloom_filter_true_positive: AtomicU64,
+    block_cache_hit:            AtomicU64,
+    block_cache_miss:           AtomicU64,
+}
+
+impl Statistics {
+    pub fn new() -> Self {
+        Statistics::default()
+    }
+
+    /// number of table lookups which skip reading a data block since its filter excludes the
+    /// key
+    pub fn bloom_filter_useful(&self) -> u64 {
+        self.bloom_filter_useful.load(Ordering::Relaxed)
+    }
+
+    /// number of table lookups which find no entry of the key the filter of data block doesn't
+    /// exclude
+    pub fn bloom_filter_false_positive(&self) -> u64 {
+        let positive = self.bloom_filter_positive.load(Ordering::Relaxed);
+        let true_positive = self.bloom_filter_true_positive.load(Ordering::Relaxed);
+        positive.saturating_sub(true_positive)
+    }
+
+    /// number of blocks read from block cache
+    pub fn block_cache_hit(&self) -> u64 {
+        self.block_cache_hit.load(Ordering::Relaxed)
+    }
+
+    /// number of blocks read from table files since they're not in block cache
+    pub fn block_cache_miss(&self) -> u64 {
+        self.block_cache_miss.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_bloom_filter_useful(&self) {
+        self.bloom_filter_useful.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// record a lookup not excluded by filter
+    pub(crate) fn record_bloom_filter_positive(&self) {
+        self.bloom_filter_positive.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// record a lookup not excluded by filter which finds the key
+    pub(crate) fn record_bloom_filter_true_positive(&self) {
+        self.bloom_filter_true_positive
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_block_cache_hit(&self) {
+        self.block_cache_hit.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_block_cache_miss(&self) {
+        self.block_cache_miss.fetch_add(1, Ordering::Relaxed);
+    }
+}
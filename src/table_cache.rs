@@ -0,0 +1,132 @@
+//! cache of open table readers
+
+use std::sync::Arc;
+
+use crate::{
+    cache::ShardedLruCache,
+    comparator::InternalKeyComparator,
+    options::Options,
+    sorted_stable::{
+        Table,
+        TableError,
+    },
+    vfs::Vfs,
+};
+
+type Result<T> = std::result::Result<T, TableError>;
+
+// files db keeps open besides tables, which are WAL, MANIFEST, LOCK and so on
+const NUM_NON_TABLE_CACHE_FILES: usize = 10;
+
+/// a cache of open tables with their index and filter blocks parsed, bounded by
+/// [`Options::max_open_files`](crate::Options::max_open_files)
+pub struct TableCache {
+    vfs:     Vfs,
+    options: Options,
+    cache:   ShardedLruCache<u64, Arc<Table>>,
+}
+
+impl TableCache {
+    pub fn new(vfs: Vfs, options: Options) -> Self {
+        let capacity = options
+            .max_open_files
+            .saturating_sub(NUM_NON_TABLE_CACHE_FILES)
+            .max(1);
+        TableCache {
+            vfs,
+            options,
+            cache: ShardedLruCache::new(capacity),
+        }
+    }
+
+    /// table of file `number`, which is opened if it's not in cache
+    ///
+    /// A table evicted from cache stays open until all its users drop it.
+    pub async fn find_table(&self, number: u64) -> Result<Arc<Table>> {
+        if let Some(table) = self.cache.get(&number) {
+            return Ok(table);
+        }
+        let file = self
+            .vfs
+            .open_sstable(number, self.options.mmap_reads)
+            .await?;
+        let table =
+            Table::open(&self.options, number, file, Arc::new(InternalKeyComparator)).await?;
+        let table = Arc::new(table);
+        self.cache.insert(number, table.clone(), 1);
+        Ok(table)
+    }
+
+    /// drop table of file `number` from cache, which is called when the file is deleted
+    pub fn evict(&self, number: u64) {
+        self.cache.erase(&number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        filename::table_file_name,
+        format::{
+            InternalKey,
+            ValueType,
+        },
+        options::ReadOptions,
+        sorted_stable::TableBuilder,
+        vfs::TokioFileSystem,
+    };
+
+    async fn build_table(vfs: &Vfs, options: &Options, number: u64) {
+        let file = vfs.create(table_file_name(number)).await.unwrap();
+        let mut builder = TableBuilder::new(options, Arc::new(InternalKeyComparator), file);
+        let key = InternalKey::new(format!("key{}", number).as_bytes(), 1, ValueType::Value);
+        builder
+            .add(key.encoded().clone(), Bytes::from("value"))
+            .await
+            .unwrap();
+        builder.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_table_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.path().to_owned())
+            .await
+            .unwrap();
+        let options = Options {
+            max_open_files: NUM_NON_TABLE_CACHE_FILES + 2,
+            ..Options::default()
+        };
+        for number in 1..=3 {
+            build_table(&vfs, &options, number).await;
+        }
+        let cache = TableCache::new(vfs.clone(), options);
+
+        let table = cache.find_table(1).await.unwrap();
+        assert!(Arc::ptr_eq(&table, &cache.find_table(1).await.unwrap()));
+        let key = InternalKey::new(b"key1", 1, ValueType::FOR_SEEK);
+        let (_, value) = table
+            .get(&ReadOptions::default(), key.encoded())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(value, "value");
+
+        // at most 2 tables are kept
+        for number in 1..=3 {
+            cache.find_table(number).await.unwrap();
+        }
+        assert!(cache.cache.total_charge() <= 2);
+
+        // an evicted table is opened again, and a removed file can't be opened
+        let table = cache.find_table(3).await.unwrap();
+        cache.evict(3);
+        assert!(!Arc::ptr_eq(&table, &cache.find_table(3).await.unwrap()));
+        cache.evict(3);
+        vfs.remove(table_file_name(3)).await.unwrap();
+        assert!(cache.find_table(3).await.is_err());
+    }
+}
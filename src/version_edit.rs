@@ -0,0 +1,188 @@
+//! changes between two versions of db file layout
+
+use std::collections::BTreeSet;
+
+use bytes::{
+    Buf,
+    Bytes,
+    BytesMut,
+};
+
+use crate::{
+    encoding::{
+        BufMutExt,
+        BytesExt,
+    },
+    format::{
+        InternalKey,
+        SequenceNumber,
+    },
+    options::CompactionStyle,
+};
+
+// Tags of fields in an encoded edit, same as LevelDB.
+const TAG_LOG_NUMBER: u32 = 2;
+const TAG_NEXT_FILE_NUMBER: u32 = 3;
+const TAG_LAST_SEQUENCE: u32 = 4;
+const TAG_DELETED_FILE: u32 = 6;
+const TAG_NEW_FILE: u32 = 7;
+// Not in LevelDB.
+const TAG_COMPACTION_STYLE: u32 = 100;
+
+/// metadata of a live table file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number:    u64,
+    pub file_size: u64,
+    /// smallest internal key in table
+    pub smallest:  InternalKey,
+    /// largest internal key in table
+    pub largest:   InternalKey,
+}
+
+/// a change to db file layout, recorded in MANIFEST
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    /// WALs older than this are no longer needed
+    pub log_number:       Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub last_sequence:    Option<SequenceNumber>,
+    /// files removed, by level and file number
+    pub deleted_files:    BTreeSet<(usize, u64)>,
+    /// files added, with their levels
+    pub new_files:        Vec<(usize, FileMetaData)>,
+    /// compaction style files are organized by
+    pub compaction_style: Option<CompactionStyle>,
+}
+
+impl VersionEdit {
+    /// create an empty [`VersionEdit`]
+    pub fn new() -> Self {
+        VersionEdit::default()
+    }
+
+    /// record adding table `file` to `level`
+    pub fn add_file(&mut self, level: usize, file: FileMetaData) {
+        self.new_files.push((level, file));
+    }
+
+    /// record removing table `number` from `level`
+    pub fn delete_file(&mut self, level: usize, number: u64) {
+        self.deleted_files.insert((level, number));
+    }
+
+    /// encode edit as a sequence of tagged fields
+    pub fn encode(&self) -> Bytes {
+        let mut data = BytesMut::new();
+        if let Some(log_number) = self.log_number {
+            data.put_var_u32_le(TAG_LOG_NUMBER);
+            data.put_var_u64_le(log_number);
+        }
+        if let Some(next_file_number) = self.next_file_number {
+            data.put_var_u32_le(TAG_NEXT_FILE_NUMBER);
+            data.put_var_u64_le(next_file_number);
+        }
+        if let Some(last_sequence) = self.last_sequence {
+            data.put_var_u32_le(TAG_LAST_SEQUENCE);
+            data.put_var_u64_le(last_sequence);
+        }
+        if let Some(style) = self.compaction_style {
+            data.put_var_u32_le(TAG_COMPACTION_STYLE);
+            data.put_var_u32_le(match style {
+                CompactionStyle::Leveled => 0,
+                CompactionStyle::Universal => 1,
+            });
+        }
+        for &(level, number) in &self.deleted_files {
+            data.put_var_u32_le(TAG_DELETED_FILE);
+            data.put_var_u32_le(level as u32);
+            data.put_var_u64_le(number);
+        }
+        for (level, file) in &self.new_files {
+            data.put_var_u32_le(TAG_NEW_FILE);
+            data.put_var_u32_le(*level as u32);
+            data.put_var_u64_le(file.number);
+            data.put_var_u64_le(file.file_size);
+            data.put_length_prefixed_slice(file.smallest.encoded());
+            data.put_length_prefixed_slice(file.largest.encoded());
+        }
+        data.freeze()
+    }
+
+    /// decode an edit produced by [`VersionEdit::encode`], return `None` if data is malformed
+    pub fn decode(mut data: Bytes) -> Option<Self> {
+        let mut edit = VersionEdit::new();
+        while data.has_remaining() {
+            match data.get_var_u32_le()? {
+                TAG_LOG_NUMBER => edit.log_number = Some(data.get_var_u64_le()?),
+                TAG_NEXT_FILE_NUMBER => edit.next_file_number = Some(data.get_var_u64_le()?),
+                TAG_LAST_SEQUENCE => edit.last_sequence = Some(data.get_var_u64_le()?),
+                TAG_DELETED_FILE => {
+                    let level = data.get_var_u32_le()? as usize;
+                    let number = data.get_var_u64_le()?;
+                    edit.delete_file(level, number);
+                }
+                TAG_NEW_FILE => {
+                    let level = data.get_var_u32_le()? as usize;
+                    let file = FileMetaData {
+                        number:    data.get_var_u64_le()?,
+                        file_size: data.get_var_u64_le()?,
+                        smallest:  InternalKey::decode(data.get_length_prefixed_slice()?)?,
+                        largest:   InternalKey::decode(data.get_length_prefixed_slice()?)?,
+                    };
+                    edit.add_file(level, file);
+                }
+                TAG_COMPACTION_STYLE => {
+                    edit.compaction_style = match data.get_var_u32_le()? {
+                        0 => Some(CompactionStyle::Leveled),
+                        1 => Some(CompactionStyle::Universal),
+                        _ => return None,
+                    }
+                }
+                _ => return None,
+            }
+        }
+        Some(edit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::ValueType;
+
+    #[test]
+    fn test_version_edit_encode_decode() {
+        let edit = VersionEdit::new();
+        assert!(edit.encode().is_empty());
+        assert_eq!(VersionEdit::decode(edit.encode()), Some(edit));
+
+        let mut edit = VersionEdit::new();
+        edit.log_number = Some(7);
+        edit.next_file_number = Some(1 << 40);
+        edit.last_sequence = Some(12345);
+        edit.delete_file(3, 100);
+        edit.delete_file(0, 9);
+        edit.compaction_style = Some(CompactionStyle::Universal);
+        for i in 0..4 {
+            edit.add_file(
+                i as usize,
+                FileMetaData {
+                    number:    20 + i,
+                    file_size: 4096 * i,
+                    smallest:  InternalKey::new(b"a", i, ValueType::Value),
+                    largest:   InternalKey::new(b"zz", 1 << 50, ValueType::Deletion),
+                },
+            );
+        }
+        let data = edit.encode();
+        assert_eq!(VersionEdit::decode(data.clone()), Some(edit));
+        for len in 1..data.len() {
+            // every prefix either decodes into a shorter edit or is malformed
+            if let Some(decoded) = VersionEdit::decode(data.slice(..len)) {
+                assert!(decoded.new_files.len() < 4);
+            }
+        }
+        assert!(VersionEdit::decode(Bytes::from_static(&[99, 1])).is_none());
+    }
+}
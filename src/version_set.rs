@@ -0,0 +1,920 @@
+//! versions of db file layout, persisted in MANIFEST
+
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        Weak,
+    },
+};
+
+use futures::TryStreamExt;
+use thiserror::Error;
+
+use crate::{
+    filename::{
+        descriptor_file_name,
+        parse_file_name,
+        temp_file_name,
+        FileType,
+        CURRENT_FILE_NAME,
+        LEGACY_WAL_FILE_NAME,
+    },
+    format::{
+        InternalKey,
+        SequenceNumber,
+    },
+    options::{
+        CompactionStyle,
+        Options,
+        UniversalCompactionOptions,
+    },
+    version_edit::{
+        FileMetaData,
+        VersionEdit,
+    },
+    vfs::{
+        Vfs,
+        VfsError,
+    },
+    wal::{
+        WalError,
+        WalFileReader,
+        WalFileWriter,
+    },
+};
+
+/// number of levels of table files
+pub const NUM_LEVELS: usize = 7;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Error)]
+pub enum VersionError {
+    #[error(transparent)]
+    VfsError(#[from] VfsError),
+    #[error(transparent)]
+    WalError(#[from] WalError),
+    #[error("corrupted manifest: {0}")]
+    CorruptedManifestError(&'static str),
+}
+
+type Result<T> = std::result::Result<T, VersionError>;
+
+/// table files of each level at some point
+///
+/// Files in level-0 may overlap and are ordered by file number, files in other levels are
+/// ordered by key range and don't overlap.
+#[derive(Clone, Debug, Default)]
+pub struct Version {
+    files: [Vec<Arc<FileMetaData>>; NUM_LEVELS],
+}
+
+impl Version {
+    /// files in `level`
+    pub fn files(&self, level: usize) -> &[Arc<FileMetaData>] {
+        &self.files[level]
+    }
+
+    /// total size of files in `level`
+    pub fn level_size(&self, level: usize) -> u64 {
+        self.files[level].iter().map(|file| file.file_size).sum()
+    }
+
+    /// files in `level` whose user key range overlaps `[smallest, largest]`
+    ///
+    /// Level-0 files overlapping those found are included too, since files in level-0 may
+    /// overlap each other.
+    pub fn overlapping_files(
+        &self,
+        level: usize,
+        smallest: &[u8],
+        largest: &[u8],
+    ) -> Vec<Arc<FileMetaData>> {
+        let mut smallest = smallest.to_vec();
+        let mut largest = largest.to_vec();
+        loop {
+            let found: Vec<_> = self.files[level]
+                .iter()
+                .filter(|file| {
+                    file.largest.user_key() >= &smallest[..] &&
+                        file.smallest.user_key() <= &largest[..]
+                })
+                .cloned()
+                .collect();
+            if level > 0 {
+                return found;
+            }
+            let (found_smallest, found_largest) = match user_key_range(&found) {
+                Some(range) => range,
+                None => return found,
+            };
+            if found_smallest >= &smallest[..] && found_largest <= &largest[..] {
+                return found;
+            }
+            // restart with the wider range
+            smallest = smallest.min(found_smallest.to_vec());
+            largest = largest.max(found_largest.to_vec());
+        }
+    }
+
+    /// files which may contain `user_key`, files holding newer versions come first
+    pub fn files_for_key(&self, user_key: &[u8]) -> Vec<Arc<FileMetaData>> {
+        let contains = |file: &FileMetaData| {
+            file.smallest.user_key() <= user_key && user_key <= file.largest.user_key()
+        };
+        let mut found: Vec<_> = self.files[0]
+            .iter()
+            .rev()
+            .filter(|file| contains(file))
+            .cloned()
+            .collect();
+        for files in &self.files[1..] {
+            let i = files.partition_point(|file| file.largest.user_key() < user_key);
+            if let Some(file) = files.get(i).filter(|file| contains(file)) {
+                found.push(file.clone());
+            }
+        }
+        found
+    }
+
+    /// new version with `edit` applied
+    fn apply(&self, edit: &VersionEdit) -> Version {
+        let mut version = self.clone();
+        for (level, files) in version.files.iter_mut().enumerate() {
+            files.retain(|file| !edit.deleted_files.contains(&(level, file.number)));
+        }
+        for (level, file) in &edit.new_files {
+            version.files[*level].push(Arc::new(file.clone()));
+        }
+        version.files[0].sort_by_key(|file| file.number);
+        for files in &mut version.files[1..] {
+            files.sort_by(|a, b| a.smallest.cmp(&b.smallest));
+        }
+        version
+    }
+}
+
+/// all runs if size of runs other than the oldest is over the limit
+fn pick_by_size_amplification(
+    runs: &[SortedRun],
+    universal: &UniversalCompactionOptions,
+) -> Option<(usize, usize)> {
+    let (oldest, newer) = runs.split_last()?;
+    let newer_size: u64 = newer.iter().map(|run| run.size).sum();
+    if newer.is_empty() ||
+        newer_size * 100 <= oldest.size * universal.max_size_amplification_percent
+    {
+        return None;
+    }
+    Some((0, runs.len()))
+}
+
+/// the first window of runs `[start, end)`, in which each run is at most `size_ratio` percent
+/// larger than the runs before it, of at least `min_merge_width` runs
+fn pick_by_size_ratio(
+    runs: &[SortedRun],
+    universal: &UniversalCompactionOptions,
+) -> Option<(usize, usize)> {
+    for start in 0..runs.len() {
+        let mut size = runs[start].size;
+        let mut end = start + 1;
+        while end < runs.len() &&
+            end - start < universal.max_merge_width &&
+            runs[end].size * 100 <= size * (100 + universal.size_ratio)
+        {
+            size += runs[end].size;
+            end += 1;
+        }
+        // a shorter window may be placed where a longer one can't
+        while end - start >= universal.min_merge_width.max(2) {
+            if universal_output_level(runs, end).is_some() {
+                return Some((start, end));
+            }
+            end -= 1;
+        }
+    }
+    None
+}
+
+/// smallest and largest user keys in `files`
+fn user_key_range(files: &[Arc<FileMetaData>]) -> Option<(&[u8], &[u8])> {
+    let smallest = files.iter().map(|file| file.smallest.user_key()).min()?;
+    let largest = files.iter().map(|file| file.largest.user_key()).max()?;
+    Some((smallest, largest))
+}
+
+/// max bytes of files in the level below the output level a file moved by a compaction may
+/// overlap
+fn max_grandparent_overlap_bytes(options: &Options) -> u64 {
+    10 * options.max_file_size
+}
+
+/// max total bytes of files in `level`, which is 10x the budget of the level above
+fn max_bytes_for_level(options: &Options, level: usize) -> f64 {
+    let mut result = options.max_bytes_for_level_base as f64;
+    for _ in 1..level {
+        result *= 10.0;
+    }
+    result
+}
+
+/// a compaction merging input files into `output_level`
+pub struct Compaction {
+    /// level new files go to
+    pub output_level: usize,
+    /// input files with their levels
+    pub inputs:       Vec<(usize, Arc<FileMetaData>)>,
+    // version inputs are picked from
+    version:          Arc<Version>,
+    // picked by a manual compaction, whose files are always rewritten to drop obsolete data
+    manual:           bool,
+}
+
+impl Compaction {
+    /// check if the compaction can be done by moving its only input file to the output level
+    ///
+    /// A file overlapping too much data of the level below the output level isn't moved, since
+    /// compacting it there later would be too expensive.
+    pub fn is_trivial_move(&self, options: &Options) -> bool {
+        if self.manual || self.inputs.len() != 1 {
+            return false;
+        }
+        let grandparent_level = self.output_level + 1;
+        if grandparent_level >= NUM_LEVELS {
+            return true;
+        }
+        let file = &self.inputs[0].1;
+        let overlap: u64 = self
+            .version
+            .overlapping_files(
+                grandparent_level,
+                file.smallest.user_key(),
+                file.largest.user_key(),
+            )
+            .iter()
+            .map(|file| file.file_size)
+            .sum();
+        overlap <= max_grandparent_overlap_bytes(options)
+    }
+
+    /// check if no level below the output level may contain `user_key`, so that a tombstone of
+    /// it shadows nothing once compacted
+    pub fn is_base_level_for_key(&self, user_key: &[u8]) -> bool {
+        self.version.files[self.output_level + 1..]
+            .iter()
+            .all(|files| {
+                files.iter().all(|file| {
+                    user_key < file.smallest.user_key() || user_key > file.largest.user_key()
+                })
+            })
+    }
+
+    /// record removing all input files in `edit`
+    pub fn add_input_deletions(&self, edit: &mut VersionEdit) {
+        for (level, file) in &self.inputs {
+            edit.delete_file(*level, file.number);
+        }
+    }
+}
+
+/// compaction merging `inputs` of `level` and the files of the next level they overlap
+fn compaction_into_next_level(
+    version: Arc<Version>,
+    level: usize,
+    inputs: Vec<Arc<FileMetaData>>,
+) -> Compaction {
+    let (smallest, largest) = user_key_range(&inputs).unwrap();
+    let next_inputs = version.overlapping_files(level + 1, smallest, largest);
+    let inputs = inputs.into_iter().map(|file| (level, file));
+    let next_inputs = next_inputs.into_iter().map(|file| (level + 1, file));
+    Compaction {
+        output_level: level + 1,
+        inputs: inputs.chain(next_inputs).collect(),
+        version,
+        manual: false,
+    }
+}
+
+/// a level-0 file or a non-empty level below, for [`CompactionStyle::Universal`]
+struct SortedRun {
+    level: usize,
+    files: Vec<Arc<FileMetaData>>,
+    size:  u64,
+}
+
+/// sorted runs of `version`, newer runs first
+fn sorted_runs(version: &Version) -> Vec<SortedRun> {
+    let mut runs: Vec<_> = version.files[0]
+        .iter()
+        .rev()
+        .map(|file| SortedRun {
+            level: 0,
+            files: vec![file.clone()],
+            size:  file.file_size,
+        })
+        .collect();
+    for level in 1..NUM_LEVELS {
+        if !version.files[level].is_empty() {
+            runs.push(SortedRun {
+                level,
+                files: version.files[level].clone(),
+                size: version.level_size(level),
+            });
+        }
+    }
+    runs
+}
+
+/// level the merge of `runs[start..end]` goes to, `None` if the merge can't be placed
+///
+/// Levels below 0 are older than every level-0 file, so the merge goes to the level of its
+/// oldest run. A merge of level-0 files only is placed in the lowest empty level above older
+/// runs, which requires no older level-0 file is left out.
+fn universal_output_level(runs: &[SortedRun], end: usize) -> Option<usize> {
+    if runs[end - 1].level > 0 {
+        return Some(runs[end - 1].level);
+    }
+    let next_level = runs.get(end).map_or(NUM_LEVELS, |run| run.level);
+    if next_level <= 1 {
+        return None;
+    }
+    Some(next_level - 1)
+}
+
+/// current [`Version`] of db and counters shared by all versions
+pub struct VersionSet {
+    vfs:              Vfs,
+    current:          Arc<Version>,
+    // versions handed out, files in them are kept until they are dropped
+    versions:         Vec<Weak<Version>>,
+    // largest key of the last compaction of each level, the next one starts after it
+    compact_pointer:  [Option<InternalKey>; NUM_LEVELS],
+    // style files are organized by
+    compaction_style: CompactionStyle,
+    // writer of live MANIFEST, a new one is started by the next edit if it's `None`
+    manifest:         Option<WalFileWriter>,
+    manifest_number:  u64,
+    next_file_number: u64,
+    log_number:       u64,
+    last_sequence:    SequenceNumber,
+}
+
+impl VersionSet {
+    /// load file layout from the MANIFEST named by CURRENT, or start an empty one if db is new
+    ///
+    /// Edits are appended to a new MANIFEST, which is started by the first call to
+    /// [`VersionSet::log_and_apply`].
+    pub async fn recover(vfs: Vfs) -> Result<Self> {
+        let mut set = VersionSet {
+            vfs,
+            current: Arc::new(Version::default()),
+            versions: vec![],
+            compact_pointer: Default::default(),
+            compaction_style: CompactionStyle::Leveled,
+            manifest: None,
+            manifest_number: 0,
+            next_file_number: 1,
+            log_number: 0,
+            last_sequence: 0,
+        };
+        let names = set.vfs.list().await?;
+        if !names.iter().any(|name| name == CURRENT_FILE_NAME) {
+            // opening a db of the old layout as a new one would hide its data
+            if names.iter().any(|name| name == LEGACY_WAL_FILE_NAME) {
+                return Err(VersionError::CorruptedManifestError(
+                    "missing CURRENT of a db in unsupported legacy format",
+                ));
+            }
+            return Ok(set);
+        }
+
+        let current = set.vfs.open(CURRENT_FILE_NAME).await?;
+        let current = current.read_at(0, current.size().await? as usize).await?;
+        let name = std::str::from_utf8(&current)
+            .ok()
+            .and_then(|name| name.strip_suffix('\n'))
+            .ok_or(VersionError::CorruptedManifestError("malformed CURRENT"))?;
+        let number = match parse_file_name(name) {
+            Some((number, FileType::Manifest)) if names.iter().any(|found| found == name) => number,
+            _ => {
+                return Err(VersionError::CorruptedManifestError(
+                    "CURRENT names a missing MANIFEST",
+                ))
+            }
+        };
+
+        // dropping edits of a corrupted MANIFEST would lose track of tables, always fail instead
+        let reader = WalFileReader::new(set.vfs.open(name).await?, true);
+        let records = reader.into_data_stream();
+        futures::pin_mut!(records);
+        let mut version = Version::default();
+        while let Some(data) = records.try_next().await? {
+            let edit = VersionEdit::decode(data)
+                .filter(|edit| {
+                    let levels = edit.deleted_files.iter().map(|(level, _)| level);
+                    let levels = levels.chain(edit.new_files.iter().map(|(level, _)| level));
+                    levels.copied().all(|level| level < NUM_LEVELS)
+                })
+                .ok_or(VersionError::CorruptedManifestError(
+                    "malformed version edit",
+                ))?;
+            version = version.apply(&edit);
+            set.log_number = edit.log_number.unwrap_or(set.log_number);
+            set.next_file_number = edit.next_file_number.unwrap_or(set.next_file_number);
+            set.last_sequence = edit.last_sequence.unwrap_or(set.last_sequence);
+            set.compaction_style = edit.compaction_style.unwrap_or(set.compaction_style);
+        }
+        set.current = Arc::new(version);
+        set.versions.push(Arc::downgrade(&set.current));
+        set.manifest_number = number;
+        set.mark_file_number_used(number);
+        Ok(set)
+    }
+
+    /// current version
+    pub fn current(&self) -> Arc<Version> {
+        self.current.clone()
+    }
+
+    /// WALs older than this are no longer needed
+    pub fn log_number(&self) -> u64 {
+        self.log_number
+    }
+
+    /// number of live MANIFEST
+    pub fn manifest_number(&self) -> u64 {
+        self.manifest_number
+    }
+
+    /// last sequence recorded by an edit
+    pub fn last_sequence(&self) -> SequenceNumber {
+        self.last_sequence
+    }
+
+    /// compaction style files are organized by, which is leveled for a db written before
+    /// styles are recorded
+    pub fn compaction_style(&self) -> CompactionStyle {
+        self.compaction_style
+    }
+
+    /// allocate a file number
+    pub fn new_file_number(&mut self) -> u64 {
+        let number = self.next_file_number;
+        self.next_file_number += 1;
+        number
+    }
+
+    /// make sure file `number` is never allocated again
+    pub fn mark_file_number_used(&mut self, number: u64) {
+        self.next_file_number = self.next_file_number.max(number + 1);
+    }
+
+    /// numbers of tables in versions which are still in use
+    pub fn live_files(&mut self) -> HashSet<u64> {
+        let live: Vec<_> = self.versions.iter().filter_map(Weak::upgrade).collect();
+        self.versions = live.iter().map(Arc::downgrade).collect();
+        live.iter()
+            .flat_map(|version| version.files.iter().flatten())
+            .map(|file| file.number)
+            .collect()
+    }
+
+    /// check if some level is over its budget, or there are too many sorted runs for
+    /// [`CompactionStyle::Universal`]
+    pub fn needs_compaction(&self, options: &Options) -> bool {
+        match options.compaction_style {
+            CompactionStyle::Leveled => self.compaction_level(options).is_some(),
+            CompactionStyle::Universal => {
+                let max_sorted_runs = options.universal_compaction.max_sorted_runs.max(1);
+                sorted_runs(&self.current).len() > max_sorted_runs
+            }
+        }
+    }
+
+    /// pick files to compact, `None` if no compaction is needed
+    pub fn pick_compaction(&mut self, options: &Options) -> Option<Compaction> {
+        match options.compaction_style {
+            CompactionStyle::Leveled => self.pick_leveled_compaction(options),
+            CompactionStyle::Universal => self.pick_universal_compaction(options),
+        }
+    }
+
+    /// merge all files into the last level, `None` if there is no file
+    pub fn pick_full_compaction(&self) -> Option<Compaction> {
+        let version = self.current.clone();
+        let inputs: Vec<_> = (0..NUM_LEVELS)
+            .flat_map(|level| {
+                version.files[level]
+                    .iter()
+                    .map(move |file| (level, file.clone()))
+            })
+            .collect();
+        if inputs.is_empty() {
+            return None;
+        }
+        Some(Compaction {
+            output_level: NUM_LEVELS - 1,
+            inputs,
+            version,
+            manual: false,
+        })
+    }
+
+    /// pick the level most over its budget and the files to compact it into the next level
+    fn pick_leveled_compaction(&mut self, options: &Options) -> Option<Compaction> {
+        let level = self.compaction_level(options)?;
+        let version = self.current.clone();
+        let files = version.files(level);
+        // files of a level are compacted in turn, starting after the last compacted range
+        let first = match &self.compact_pointer[level] {
+            Some(pointer) if level > 0 => files.iter().find(|file| &file.largest > pointer),
+            _ => None,
+        };
+        let first = first.unwrap_or(&files[0]);
+        let mut inputs = vec![first.clone()];
+        if level == 0 {
+            let (smallest, largest) = user_key_range(&inputs).unwrap();
+            inputs = version.overlapping_files(0, smallest, largest);
+        }
+        self.compact_pointer[level] = inputs.iter().map(|file| file.largest.clone()).max();
+        Some(compaction_into_next_level(version, level, inputs))
+    }
+
+    /// pick files of `level` overlapping user keys `[begin, end]` to compact into the next
+    /// level, a missing bound is unbounded, `None` if no file overlaps
+    pub fn pick_range_compaction(
+        &self,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Option<Compaction> {
+        let version = self.current.clone();
+        let (first, last) = user_key_range(&version.files[level])?;
+        let inputs = version.overlapping_files(level, begin.unwrap_or(first), end.unwrap_or(last));
+        if inputs.is_empty() {
+            return None;
+        }
+        let mut compaction = compaction_into_next_level(version, level, inputs);
+        compaction.manual = true;
+        Some(compaction)
+    }
+
+    /// pick consecutive sorted runs to merge
+    ///
+    /// All runs are merged if newer runs take too much space compared with the oldest one,
+    /// otherwise runs of similar size are merged, and if there are none, the newest runs are
+    /// merged to bring down the number of runs.
+    fn pick_universal_compaction(&self, options: &Options) -> Option<Compaction> {
+        let universal = &options.universal_compaction;
+        let runs = sorted_runs(&self.current);
+        let max_sorted_runs = universal.max_sorted_runs.max(1);
+        if runs.len() <= max_sorted_runs {
+            return None;
+        }
+        let (start, end) = pick_by_size_amplification(&runs, universal)
+            .or_else(|| pick_by_size_ratio(&runs, universal))
+            .unwrap_or_else(|| {
+                // merge just enough newest runs, extending the merge until it can be placed
+                let mut end = runs.len() + 1 - max_sorted_runs;
+                while universal_output_level(&runs, end).is_none() {
+                    end += 1;
+                }
+                (0, end)
+            });
+        let output_level = universal_output_level(&runs, end).unwrap();
+        let inputs = runs[start..end]
+            .iter()
+            .flat_map(|run| run.files.iter().map(move |file| (run.level, file.clone())))
+            .collect();
+        Some(Compaction {
+            output_level,
+            inputs,
+            version: self.current.clone(),
+            manual: false,
+        })
+    }
+
+    /// level with the highest ratio of size to its budget, if the ratio reaches 1
+    fn compaction_level(&self, options: &Options) -> Option<usize> {
+        let mut best = None;
+        let mut best_score = 0.0;
+        // the last level has no level to compact into
+        for level in 0..NUM_LEVELS - 1 {
+            let score = if level == 0 {
+                // level-0 files are counted, since every read may check all of them
+                self.current.files[0].len() as f64 /
+                    options.level0_file_num_compaction_trigger as f64
+            } else {
+                self.current.level_size(level) as f64 / max_bytes_for_level(options, level)
+            };
+            if score >= 1.0 && score > best_score {
+                best = Some(level);
+                best_score = score;
+            }
+        }
+        best
+    }
+
+    /// persist `edit` to MANIFEST, then make the version with it applied current
+    ///
+    /// Log number and last sequence are kept if `edit` doesn't set them.
+    pub async fn log_and_apply(&mut self, edit: &mut VersionEdit) -> Result<()> {
+        let new_manifest = match self.manifest {
+            Some(_) => None,
+            None => Some(self.new_file_number()),
+        };
+        edit.log_number.get_or_insert(self.log_number);
+        edit.last_sequence.get_or_insert(self.last_sequence);
+        edit.next_file_number = Some(self.next_file_number);
+        let version = self.current.apply(edit);
+
+        let result = self.write_edit(new_manifest, edit).await;
+        if result.is_err() {
+            // the MANIFEST may end with a partial record, start a new one next time
+            self.manifest = None;
+            if let Some(number) = new_manifest {
+                let _ = self.vfs.remove(descriptor_file_name(number)).await;
+            }
+            return result;
+        }
+
+        self.current = Arc::new(version);
+        self.versions.push(Arc::downgrade(&self.current));
+        self.log_number = edit.log_number.unwrap();
+        self.last_sequence = edit.last_sequence.unwrap();
+        self.compaction_style = edit.compaction_style.unwrap_or(self.compaction_style);
+        if let Some(number) = new_manifest {
+            self.manifest_number = number;
+        }
+        Ok(())
+    }
+
+    /// append `edit` to MANIFEST, starting MANIFEST `new_manifest` if it's set
+    async fn write_edit(&mut self, new_manifest: Option<u64>, edit: &VersionEdit) -> Result<()> {
+        if let Some(number) = new_manifest {
+            let file = self.vfs.create(descriptor_file_name(number)).await?;
+            let mut writer = WalFileWriter::new(file);
+            // a new MANIFEST starts with the whole current layout
+            writer.write_data(self.snapshot().encode()).await?;
+            self.manifest = Some(writer);
+        }
+        let writer = self.manifest.as_mut().unwrap();
+        writer.write_data(edit.encode()).await?;
+        writer.sync().await?;
+        if let Some(number) = new_manifest {
+            self.set_current_file(number).await?;
+        }
+        Ok(())
+    }
+
+    /// edit building current version from scratch
+    fn snapshot(&self) -> VersionEdit {
+        let mut edit = VersionEdit::new();
+        edit.compaction_style = Some(self.compaction_style);
+        for (level, files) in self.current.files.iter().enumerate() {
+            for file in files {
+                edit.add_file(level, file.as_ref().clone());
+            }
+        }
+        edit
+    }
+
+    /// point CURRENT at MANIFEST `number`
+    async fn set_current_file(&mut self, number: u64) -> Result<()> {
+        let temp = temp_file_name(number);
+        let mut file = self.vfs.create(&temp).await?;
+        file.append(format!("{}\n", descriptor_file_name(number)).as_bytes())
+            .await?;
+        file.sync().await?;
+        self.vfs.rename(&temp, CURRENT_FILE_NAME).await?;
+        // the new MANIFEST and CURRENT survive a crash
+        self.vfs.sync_dir().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        format::{
+            InternalKey,
+            ValueType,
+        },
+        vfs::TokioFileSystem,
+    };
+
+    fn file(number: u64, smallest: &str, largest: &str) -> FileMetaData {
+        FileMetaData {
+            number,
+            file_size: 100,
+            smallest: InternalKey::new(smallest.as_bytes(), 1, ValueType::Value),
+            largest: InternalKey::new(largest.as_bytes(), 1, ValueType::Value),
+        }
+    }
+
+    fn numbers(files: &[Arc<FileMetaData>]) -> Vec<u64> {
+        files.iter().map(|file| file.number).collect()
+    }
+
+    #[test]
+    fn test_version_files_for_key() {
+        let mut edit = VersionEdit::new();
+        edit.add_file(0, file(1, "a", "m"));
+        edit.add_file(0, file(3, "k", "z"));
+        edit.add_file(0, file(2, "c", "d"));
+        edit.add_file(1, file(5, "n", "q"));
+        edit.add_file(1, file(4, "a", "f"));
+        edit.add_file(2, file(6, "a", "z"));
+        let version = Version::default().apply(&edit);
+        assert_eq!(numbers(version.files(0)), vec![1, 2, 3]);
+        assert_eq!(numbers(version.files(1)), vec![4, 5]);
+        assert_eq!(numbers(&version.files_for_key(b"c")), vec![2, 1, 4, 6]);
+        assert_eq!(numbers(&version.files_for_key(b"l")), vec![3, 1, 6]);
+        assert_eq!(numbers(&version.files_for_key(b"p")), vec![3, 5, 6]);
+        assert!(version.files_for_key(b"zz").is_empty());
+
+        let mut edit = VersionEdit::new();
+        edit.delete_file(0, 1);
+        edit.delete_file(1, 5);
+        let version = version.apply(&edit);
+        assert_eq!(numbers(version.files(0)), vec![2, 3]);
+        assert_eq!(numbers(version.files(1)), vec![4]);
+    }
+
+    #[test]
+    fn test_compaction_trivial_move() {
+        let options = Options {
+            max_file_size: 20,
+            ..Options::default()
+        };
+        let mut edit = VersionEdit::new();
+        edit.add_file(1, file(1, "a", "c"));
+        edit.add_file(1, file(2, "m", "o"));
+        edit.add_file(1, file(3, "x", "z"));
+        edit.add_file(2, file(4, "b", "b"));
+        // grandparents of the files of level-1
+        edit.add_file(3, file(5, "a", "d"));
+        edit.add_file(3, file(6, "m", "n"));
+        edit.add_file(3, file(7, "n", "o"));
+        edit.add_file(3, file(8, "o", "p"));
+        let version = Arc::new(Version::default().apply(&edit));
+        let compaction = |number: u64| {
+            let file = version.files(1)[number as usize - 1].clone();
+            compaction_into_next_level(version.clone(), 1, vec![file])
+        };
+
+        // overlapping level-2 files are compacted
+        assert!(!compaction(1).is_trivial_move(&options));
+        // overlapping 300 bytes of grandparents is over the bound of 200
+        assert!(!compaction(2).is_trivial_move(&options));
+        assert!(compaction(3).is_trivial_move(&options));
+        let mut manual = compaction(3);
+        manual.manual = true;
+        assert!(!manual.is_trivial_move(&options));
+    }
+
+    #[tokio::test]
+    async fn test_version_set_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.path().to_owned())
+            .await
+            .unwrap();
+        let mut set = VersionSet::recover(vfs.clone()).await.unwrap();
+        assert!(numbers(set.current().files(0)).is_empty());
+        let log_number = set.new_file_number();
+
+        let mut edit = VersionEdit::new();
+        edit.log_number = Some(log_number);
+        edit.last_sequence = Some(10);
+        edit.add_file(0, file(set.new_file_number(), "a", "c"));
+        set.log_and_apply(&mut edit).await.unwrap();
+        let manifest_number = set.manifest_number();
+        let mut edit = VersionEdit::new();
+        edit.add_file(0, file(set.new_file_number(), "b", "d"));
+        edit.add_file(1, file(set.new_file_number(), "e", "f"));
+        set.log_and_apply(&mut edit).await.unwrap();
+        let mut edit = VersionEdit::new();
+        edit.delete_file(0, 2);
+        edit.last_sequence = Some(20);
+        set.log_and_apply(&mut edit).await.unwrap();
+        assert_eq!(set.manifest_number(), manifest_number);
+        assert_eq!(set.live_files(), [4, 5].iter().copied().collect());
+        let next_file_number = set.new_file_number();
+        drop(set);
+
+        let current = std::fs::read_to_string(dir.path().join(CURRENT_FILE_NAME)).unwrap();
+        assert_eq!(
+            current,
+            format!("{}\n", descriptor_file_name(manifest_number))
+        );
+
+        let mut set = VersionSet::recover(vfs.clone()).await.unwrap();
+        assert_eq!(set.log_number(), log_number);
+        assert_eq!(set.last_sequence(), 20);
+        assert_eq!(set.manifest_number(), manifest_number);
+        assert_eq!(numbers(set.current().files(0)), vec![4]);
+        assert_eq!(numbers(set.current().files(1)), vec![5]);
+        assert_eq!(set.new_file_number(), next_file_number);
+
+        // a reopened set starts a new MANIFEST with the whole layout
+        set.log_and_apply(&mut VersionEdit::new()).await.unwrap();
+        assert!(set.manifest_number() > manifest_number);
+        let mut set = VersionSet::recover(vfs).await.unwrap();
+        assert_eq!(set.live_files(), [4, 5].iter().copied().collect());
+        assert_eq!(set.last_sequence(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_version_set_bad_current() {
+        let dir = tempfile::tempdir().unwrap();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.path().to_owned())
+            .await
+            .unwrap();
+        std::fs::write(dir.path().join(CURRENT_FILE_NAME), "MANIFEST-000009\n").unwrap();
+        assert!(matches!(
+            VersionSet::recover(vfs.clone()).await,
+            Err(VersionError::CorruptedManifestError(_))
+        ));
+        std::fs::write(dir.path().join(CURRENT_FILE_NAME), "garbage").unwrap();
+        assert!(matches!(
+            VersionSet::recover(vfs.clone()).await,
+            Err(VersionError::CorruptedManifestError(_))
+        ));
+
+        // a WAL of the old layout is not taken for a new db
+        std::fs::remove_file(dir.path().join(CURRENT_FILE_NAME)).unwrap();
+        std::fs::write(dir.path().join(LEGACY_WAL_FILE_NAME), "data").unwrap();
+        assert!(matches!(
+            VersionSet::recover(vfs).await,
+            Err(VersionError::CorruptedManifestError(_))
+        ));
+        assert!(!dir.path().join(CURRENT_FILE_NAME).exists());
+    }
+
+    #[tokio::test]
+    async fn test_version_set_pick_universal_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.path().to_owned())
+            .await
+            .unwrap();
+        let mut set = VersionSet::recover(vfs).await.unwrap();
+        let mut options = Options {
+            compaction_style: CompactionStyle::Universal,
+            ..Options::default()
+        };
+        // files of given levels and sizes, file numbers increase with their index
+        let mut pick = |files: &[(usize, u64)], options: &Options| {
+            let mut edit = VersionEdit::new();
+            for (i, &(level, size)) in files.iter().enumerate() {
+                let mut file = file(i as u64 + 1, "a", "z");
+                file.file_size = size;
+                edit.add_file(level, file);
+            }
+            set.current = Arc::new(Version::default().apply(&edit));
+            assert_eq!(
+                set.needs_compaction(options),
+                set.pick_compaction(options).is_some()
+            );
+            set.pick_compaction(options).map(|compaction| {
+                let mut inputs: Vec<_> = compaction
+                    .inputs
+                    .iter()
+                    .map(|(level, file)| (*level, file.number))
+                    .collect();
+                inputs.sort_unstable();
+                (inputs, compaction.output_level)
+            })
+        };
+
+        // few enough runs
+        assert!(pick(&[(6, 100), (0, 1), (0, 1), (0, 1)], &options).is_none());
+        // runs of similar size are merged into the lowest empty level above older runs
+        assert_eq!(
+            pick(&[(6, 100), (0, 1), (0, 1), (0, 1), (0, 1)], &options),
+            Some((vec![(0, 2), (0, 3), (0, 4), (0, 5)], 5))
+        );
+        // newer runs take too much space
+        assert_eq!(
+            pick(&[(6, 10), (0, 10), (0, 10), (0, 10), (0, 10)], &options),
+            Some((vec![(0, 2), (0, 3), (0, 4), (0, 5), (6, 1)], 6))
+        );
+        // no runs of similar size, newest runs are merged until the merge can be placed
+        assert_eq!(
+            pick(
+                &[(6, 10000), (0, 1000), (0, 100), (0, 10), (0, 1)],
+                &options
+            ),
+            Some((vec![(0, 2), (0, 3), (0, 4), (0, 5)], 5))
+        );
+        // a merge reaching a level goes to that level
+        options.universal_compaction.max_sorted_runs = 2;
+        assert_eq!(
+            pick(&[(6, 1000), (1, 1), (0, 1), (0, 1)], &options),
+            Some((vec![(0, 3), (0, 4), (1, 2)], 1))
+        );
+        // a leveled db is compacted level by level
+        options.compaction_style = CompactionStyle::Leveled;
+        assert!(pick(&[(6, 1000), (1, 1), (0, 1), (0, 1)], &options).is_none());
+    }
+}
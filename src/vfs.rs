@@ -1,7 +1,12 @@
 //! virtual file system
+//!
+//! All files of db go through a [`FileSystem`], which is [`TokioFileSystem`] on local disk by
+//! default and may be replaced through [`Options::file_system`](crate::Options::file_system),
+//! like with [`MemFileSystem`](crate::MemFileSystem) keeping files in memory.
 
 use std::{
-    io::SeekFrom,
+    fmt::Debug,
+    io,
     path::{
         Path,
         PathBuf,
@@ -9,142 +14,464 @@ use std::{
     sync::Arc,
 };
 
+use async_trait::async_trait;
+use bytes::Bytes;
+use memmap2::Mmap;
 use thiserror::Error;
 use tokio::{
     fs::{
         File,
         OpenOptions,
     },
-    io::{
-        AsyncReadExt,
-        AsyncSeekExt,
-        AsyncWriteExt,
-    },
-    sync::Mutex,
+    io::AsyncWriteExt,
 };
 
+use crate::filename::table_file_name;
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum VfsError {
     #[error(transparent)]
-    IoError(#[from] std::io::Error),
+    IoError(#[from] io::Error),
+    #[error("lock {0} is held by another db")]
+    LockError(PathBuf),
 }
 
 type Result<T> = std::result::Result<T, VfsError>;
 
-/// virtual file system object, which encapsulate all states
+/// a file read at random offsets, like a table
+#[async_trait]
+pub trait RandomAccessFile: Send + Sync {
+    /// read `len` bytes at `offset`, which fails with [`io::ErrorKind::UnexpectedEof`] if the
+    /// file ends before
+    ///
+    /// Reads of the same file may run concurrently.
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes>;
+
+    /// file size in bytes
+    async fn size(&self) -> Result<u64>;
+
+    /// path of file, which shows up in errors
+    fn path(&self) -> &Path;
+
+    /// whether reads are slices of a memory mapping of file, which are cheap enough to skip
+    /// block cache
+    fn is_mapped(&self) -> bool {
+        false
+    }
+}
+
+/// a file written by appending, like a WAL
+#[async_trait]
+pub trait WritableFile: Send + Sync {
+    /// append `data`, which is visible to readers of file once it returns
+    async fn append(&mut self, data: &[u8]) -> Result<()>;
+
+    /// persist data appended so far
+    async fn sync(&mut self) -> Result<()>;
+
+    /// file size in bytes
+    fn size(&self) -> u64;
+}
+
+/// a lock of a file held until it's dropped
+pub trait FileLock: Send + Sync {}
+
+/// file system db files are stored in
+#[async_trait]
+pub trait FileSystem: Debug + Send + Sync {
+    /// open an existing file for reading
+    async fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>>;
+
+    /// open an existing file which is never modified for reading through a memory mapping, a
+    /// file system without mappings opens it by [`Self::open`]
+    async fn open_mapped(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        self.open(path).await
+    }
+
+    /// create a file for writing, truncating it if it exists
+    async fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// open a file for appending, creating it if it doesn't exist
+    async fn open_appendable(&self, path: &Path) -> Result<Box<dyn WritableFile>>;
+
+    /// rename file `from` to `to`, replacing `to` if it exists
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    async fn remove(&self, path: &Path) -> Result<()>;
+
+    /// names of all files in directory `dir`
+    async fn list(&self, dir: &Path) -> Result<Vec<String>>;
+
+    /// create directory `dir` and its missing parents
+    async fn create_dir_all(&self, dir: &Path) -> Result<()>;
+
+    /// lock file `path`, creating it if it doesn't exist, which fails with
+    /// [`VfsError::LockError`] if it's already locked
+    async fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>>;
+
+    /// persist entries of directory `dir`, so that files created, renamed or removed in it
+    /// survive a crash
+    async fn sync_dir(&self, dir: &Path) -> Result<()>;
+}
+
+/// virtual file system object, which opens files under the db directory
 #[derive(Clone)]
 pub struct Vfs {
     inner: Arc<VfsInner>,
 }
 
 struct VfsInner {
+    fs:   Arc<dyn FileSystem>,
     base: PathBuf,
 }
 
 impl Vfs {
-    /// create new VFS object
-    pub async fn new(base: PathBuf) -> Result<Self> {
-        let _ = tokio::fs::create_dir_all(&base).await?;
+    /// create new VFS object of directory `base` in `fs`, creating it if it does not exist
+    pub async fn new(fs: Arc<dyn FileSystem>, base: PathBuf) -> Result<Self> {
+        fs.create_dir_all(&base).await?;
         Ok(Vfs {
-            inner: Arc::new(VfsInner { base }),
+            inner: Arc::new(VfsInner { fs, base }),
         })
     }
 
-    pub async fn open(&self, path: impl AsRef<Path>) -> Result<VFile> {
-        let path = self.base().join(path);
-        let file = VFile::open(&path).await?;
-        Ok(file)
+    pub async fn open(&self, path: impl AsRef<Path>) -> Result<Box<dyn RandomAccessFile>> {
+        self.inner.fs.open(&self.base().join(path)).await
     }
 
-    /// open sstable file by level
-    pub async fn open_sstable(&self, level: usize) -> Result<VFile> {
-        let path = self.base().join(level.to_string());
-        let inner = VFileInner::open(&path).await?;
-        Ok(VFile {
-            inner: Mutex::new(inner),
-        })
+    /// open sstable file by number, through a memory mapping if `mmap` is set
+    pub async fn open_sstable(&self, number: u64, mmap: bool) -> Result<Box<dyn RandomAccessFile>> {
+        let path = self.base().join(table_file_name(number));
+        if mmap {
+            self.inner.fs.open_mapped(&path).await
+        } else {
+            self.inner.fs.open(&path).await
+        }
     }
 
-    fn base(&self) -> PathBuf {
-        self.inner.base.clone()
+    /// create a file for writing, truncating it if it exists
+    pub async fn create(&self, path: impl AsRef<Path>) -> Result<Box<dyn WritableFile>> {
+        self.inner.fs.create(&self.base().join(path)).await
     }
-}
 
-/// virtual file representation
-pub struct VFile {
-    inner: Mutex<VFileInner>,
-}
+    /// open a file for appending, creating it if it doesn't exist
+    pub async fn open_appendable(&self, path: impl AsRef<Path>) -> Result<Box<dyn WritableFile>> {
+        self.inner.fs.open_appendable(&self.base().join(path)).await
+    }
 
-struct VFileInner {
-    writer: File,
-    reader: File,
-}
+    /// names of all files under base directory
+    pub async fn list(&self) -> Result<Vec<String>> {
+        self.inner.fs.list(self.base()).await
+    }
 
-impl VFile {
-    /// Append data to [`VFile`] and return appended size.
-    pub async fn append(&self, data: &[u8]) -> Result<()> {
-        self.inner.lock().await.append(data).await?;
-        Ok(())
+    /// remove file
+    pub async fn remove(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.inner.fs.remove(&self.base().join(path)).await
     }
 
-    /// Read a block of `len` size starting from `offset`.
-    pub async fn read_at(&self, offset: u64, len: usize) -> Result<Vec<u8>> {
-        self.inner.lock().await.read_at(offset, len).await
+    /// rename file `from` to `to`, replacing `to` if it exists
+    pub async fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+        let base = self.base();
+        self.inner.fs.rename(&base.join(from), &base.join(to)).await
     }
 
-    /// Read underline file
-    pub async fn read_exact(&self, buf: &mut [u8]) -> Result<usize> {
-        Ok(self.inner.lock().await.reader.read_exact(buf).await?)
+    /// lock file, which is held until the returned lock is dropped
+    pub async fn lock(&self, path: impl AsRef<Path>) -> Result<Box<dyn FileLock>> {
+        self.inner.fs.lock(&self.base().join(path)).await
     }
 
-    /// Synchronize file
-    pub async fn sync(&self) -> Result<()> {
-        self.inner.lock().await.sync().await?;
-        Ok(())
+    /// persist entries of base directory
+    pub async fn sync_dir(&self) -> Result<()> {
+        self.inner.fs.sync_dir(self.base()).await
+    }
+
+    fn base(&self) -> &Path {
+        &self.inner.base
     }
+}
+
+/// file system on local disk through `tokio::fs`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioFileSystem;
 
-    /// file length in bytes
-    pub async fn len(&self) -> Result<usize> {
-        self.inner.lock().await.len().await
+#[async_trait]
+impl FileSystem for TokioFileSystem {
+    async fn open(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        let file = File::open(path).await?.into_std().await;
+        Ok(Box::new(TokioRandomAccessFile {
+            file: Arc::new(file),
+            path: path.to_owned(),
+        }))
     }
 
-    async fn open(path: &Path) -> Result<Self> {
-        let inner = VFileInner::open(path).await?;
-        Ok(VFile {
-            inner: Mutex::new(inner),
+    async fn open_mapped(&self, path: &Path) -> Result<Box<dyn RandomAccessFile>> {
+        let file = File::open(path).await?.into_std().await;
+        let data = tokio::task::spawn_blocking(move || {
+            if file.metadata()?.len() == 0 {
+                // an empty file can't be mapped
+                return Ok(Bytes::new());
+            }
+            // SAFETY: the file is never modified once it's opened for reading. The mapping is
+            // owned by the returned bytes and all slices of it, so it's unmapped only after the
+            // last block read from it is dropped, even if the file is removed before.
+            let mmap = unsafe { Mmap::map(&file)? };
+            Ok::<_, io::Error>(Bytes::from_owner(mmap))
         })
+        .await
+        .map_err(io::Error::other)??;
+        Ok(Box::new(TokioMappedFile {
+            data,
+            path: path.to_owned(),
+        }))
     }
-}
 
-impl VFileInner {
-    async fn open(path: &Path) -> Result<Self> {
-        let writer = OpenOptions::new()
+    async fn create(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = File::create(path).await?;
+        Ok(Box::new(TokioWritableFile { file, len: 0 }))
+    }
+
+    async fn open_appendable(&self, path: &Path) -> Result<Box<dyn WritableFile>> {
+        let file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(path)
             .await?;
-        let reader = OpenOptions::new().read(true).open(path).await?;
-        Ok(VFileInner { reader, writer })
+        let len = file.metadata().await?.len();
+        Ok(Box::new(TokioWritableFile { file, len }))
     }
 
-    async fn append(&mut self, data: &[u8]) -> Result<()> {
-        self.writer.write_all(data).await?;
+    async fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        tokio::fs::rename(from, to).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> Result<()> {
+        tokio::fs::remove_file(path).await?;
+        Ok(())
+    }
+
+    async fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let mut names = vec![];
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    async fn create_dir_all(&self, dir: &Path) -> Result<()> {
+        tokio::fs::create_dir_all(dir).await?;
+        Ok(())
+    }
+
+    async fn lock(&self, path: &Path) -> Result<Box<dyn FileLock>> {
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+            match file.try_lock() {
+                Ok(()) => Ok(Box::new(TokioFileLock { _file: file }) as Box<dyn FileLock>),
+                Err(std::fs::TryLockError::WouldBlock) => Err(VfsError::LockError(path)),
+                Err(std::fs::TryLockError::Error(err)) => Err(err.into()),
+            }
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    async fn sync_dir(&self, dir: &Path) -> Result<()> {
+        // directories can't be opened as files on some platforms, whose entries are persisted
+        // with files anyway
+        if cfg!(unix) {
+            File::open(dir).await?.sync_all().await?;
+        }
         Ok(())
     }
+}
+
+/// a file of [`TokioFileSystem`] open for reading
+struct TokioRandomAccessFile {
+    // positional reads leave the file cursor alone, so the handle is shared without a lock
+    file: Arc<std::fs::File>,
+    path: PathBuf,
+}
+
+#[async_trait]
+impl RandomAccessFile for TokioRandomAccessFile {
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes> {
+        let file = self.file.clone();
+        let data = tokio::task::spawn_blocking(move || {
+            let mut data = vec![0u8; len];
+            read_exact_at(&file, &mut data, offset)?;
+            Ok::<_, io::Error>(data)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        Ok(data.into())
+    }
+
+    async fn size(&self) -> Result<u64> {
+        let file = self.file.clone();
+        let metadata = tokio::task::spawn_blocking(move || file.metadata())
+            .await
+            .map_err(io::Error::other)??;
+        Ok(metadata.len())
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// a file of [`TokioFileSystem`] mapped into memory
+struct TokioMappedFile {
+    data: Bytes,
+    path: PathBuf,
+}
+
+#[async_trait]
+impl RandomAccessFile for TokioMappedFile {
+    async fn read_at(&self, offset: u64, len: usize) -> Result<Bytes> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.data.len() as u64 => {
+                Ok(self.data.slice(offset as usize..end as usize))
+            }
+            _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    async fn size(&self) -> Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn is_mapped(&self) -> bool {
+        true
+    }
+}
+
+/// a file of [`TokioFileSystem`] open for appending
+struct TokioWritableFile {
+    file: File,
+    len:  u64,
+}
 
-    async fn read_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
-        self.reader.seek(SeekFrom::Start(offset)).await?;
-        let mut ret = vec![0u8; len];
-        self.reader.read_exact(&mut ret).await?;
-        Ok(ret)
+#[async_trait]
+impl WritableFile for TokioWritableFile {
+    async fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.file.write_all(data).await?;
+        // make the data visible to readers before returning
+        self.file.flush().await?;
+        self.len += data.len() as u64;
+        Ok(())
     }
 
-    async fn sync(&self) -> Result<()> {
-        self.writer.sync_all().await?;
+    async fn sync(&mut self) -> Result<()> {
+        self.file.sync_all().await?;
         Ok(())
     }
 
-    async fn len(&self) -> Result<usize> {
-        Ok(self.reader.metadata().await?.len() as usize)
+    fn size(&self) -> u64 {
+        self.len
+    }
+}
+
+/// a lock of [`TokioFileSystem`], which is released when the file is closed
+struct TokioFileLock {
+    _file: std::fs::File,
+}
+
+impl FileLock for TokioFileLock {}
+
+#[cfg(unix)]
+fn read_exact_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &std::fs::File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                buf = &mut std::mem::take(&mut buf)[n..];
+                offset += n as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_tokio_file_system_concurrent_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.path().to_owned())
+            .await
+            .unwrap();
+        let data: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut file = vfs.create("file").await.unwrap();
+        file.append(&data).await.unwrap();
+        file.sync().await.unwrap();
+
+        let file: Arc<dyn RandomAccessFile> = vfs.open("file").await.unwrap().into();
+        assert_eq!(file.size().await.unwrap(), data.len() as u64);
+        let reads = (0..64u64).map(|i| {
+            let file = file.clone();
+            tokio::spawn(async move {
+                let offset = i * 1000;
+                (offset, file.read_at(offset, 1024).await.unwrap())
+            })
+        });
+        for read in futures::future::join_all(reads).await {
+            let (offset, read) = read.unwrap();
+            assert_eq!(read, data[offset as usize..][..1024]);
+        }
+        assert!(file.read_at(data.len() as u64 - 1, 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tokio_file_system_mapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.path().to_owned())
+            .await
+            .unwrap();
+        let mut file = vfs.create(table_file_name(1)).await.unwrap();
+        file.append(b"hello world").await.unwrap();
+        file.sync().await.unwrap();
+
+        let file = vfs.open_sstable(1, true).await.unwrap();
+        assert!(file.is_mapped());
+        assert_eq!(file.size().await.unwrap(), 11);
+        let read = file.read_at(6, 5).await.unwrap();
+        assert_eq!(read, "world");
+        assert!(file.read_at(6, 6).await.is_err());
+        assert!(file.read_at(u64::MAX, 1).await.is_err());
+        assert!(!vfs.open_sstable(1, false).await.unwrap().is_mapped());
+
+        // a read keeps the mapping alive after the file is closed and removed
+        drop(file);
+        vfs.remove(table_file_name(1)).await.unwrap();
+        assert_eq!(read, "world");
+
+        vfs.create(table_file_name(2)).await.unwrap();
+        let file = vfs.open_sstable(2, true).await.unwrap();
+        assert_eq!(file.size().await.unwrap(), 0);
+        assert!(file.read_at(0, 1).await.is_err());
     }
 }
@@ -5,18 +5,21 @@ use bytes::{
 };
 use futures::prelude::*;
 use thiserror::Error;
-use tokio::sync::Mutex;
 use vfs::VfsError;
 
 use crate::{
-    encoding::BufMutExt,
+    filename::log_file_name,
+    format::SequenceNumber,
     vfs::{
         self,
-        VFile,
+        RandomAccessFile,
         Vfs,
+        WritableFile,
     },
+    write_batch::WriteBatch,
 };
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum WalError {
     #[error(transparent)]
@@ -25,6 +28,10 @@ pub enum WalError {
     InvalidWalFileError,
     #[error("invalid record type")]
     InvalidRecordTypeError,
+    #[error("record checksum mismatch")]
+    ChecksumMismatchError,
+    #[error("malformed record payload")]
+    MalformedRecordError,
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
@@ -35,81 +42,74 @@ const BLOCK_SIZE: usize = 32768;
 // Header is checksum (4 bytes), length (2 bytes), type (1 byte).
 const HEADER_SIZE: usize = 4 + 2 + 1;
 
+/// WAL of a memtable, stored in log file `number`
 pub struct Wal {
-    writer: Mutex<WalFileWriter>,
+    writer: WalFileWriter,
+    number: u64,
 }
 
 impl Wal {
-    pub async fn open(vfs: Vfs) -> Result<Self> {
-        Ok(Wal {
-            writer: Mutex::new(WalFileWriter::open(vfs).await?),
-        })
+    pub async fn open(vfs: Vfs, number: u64) -> Result<Self> {
+        let writer = WalFileWriter::open(vfs.clone(), number).await?;
+        // the new file survives a crash along with records synced to it
+        vfs.sync_dir().await?;
+        Ok(Wal { writer, number })
     }
 
-    pub async fn set(&self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
-        let key = key.as_ref();
-        let value = value.as_ref();
-        self.set_impl(key, value).await
+    /// number of log file
+    pub fn number(&self) -> u64 {
+        self.number
     }
 
-    async fn set_impl(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        let mut data = BytesMut::new();
-        data.put_var_u32_le(key.len() as u32);
-        data.put(key);
-        data.put_var_u32_le(value.len() as u32);
-        data.put(value);
-        self.writer.lock().await.write_data(data.freeze()).await?;
+    /// write the whole batch starting at `seq` as a single record
+    pub async fn write(&mut self, batch: &WriteBatch, seq: SequenceNumber) -> Result<()> {
+        self.writer.write_data(batch.encode(seq)).await?;
         Ok(())
     }
+
+    /// sync records written so far
+    pub async fn sync(&mut self) -> Result<()> {
+        self.writer.sync().await
+    }
 }
 
 /// represent WAL writer
 pub struct WalFileWriter {
-    file:         VFile,
+    file:         Box<dyn WritableFile>,
     block_offset: usize,
 }
 
 impl WalFileWriter {
-    /// into inner vfile
-    pub fn into_inner(self) -> VFile {
-        self.file
-    }
-
-    /// open wal file
-    pub async fn open(vfs: Vfs) -> Result<Self> {
-        let vfile = vfs.open("wal.log").await?;
-        let wal = WalFileWriter::new(vfile).await?;
-        Ok(wal)
+    /// open wal file `number` for appending, creating it if it doesn't exist
+    pub async fn open(vfs: Vfs, number: u64) -> Result<Self> {
+        let file = vfs.open_appendable(log_file_name(number)).await?;
+        Ok(WalFileWriter::new(file))
     }
 
     /// write a record
     pub async fn write_data(&mut self, data: Bytes) -> Result<()> {
-        let mut rest_data = Some(data.as_ref());
+        let mut rest_data = data.as_ref();
         let mut is_begin = true;
-        let mut is_end = false;
-        while let Some(data) = rest_data {
+        loop {
             let left_over = BLOCK_SIZE - self.block_offset;
             if left_over < HEADER_SIZE {
-                // move to next block
+                // move to next block, the trailer is too small to hold a header
                 const ZEROES: [u8; HEADER_SIZE] = [0; HEADER_SIZE];
                 self.file.append(&ZEROES[..left_over]).await?;
                 self.block_offset = 0;
             }
 
-            let cur_len = if left_over >= data.len() {
-                is_end = true;
-                data.len()
-            } else {
-                left_over
-            };
+            let avail = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let cur_len = avail.min(rest_data.len());
+            let is_end = cur_len == rest_data.len();
 
-            self.emit_physical_record(RecordType::calc(is_begin, is_end), &data[..cur_len])
+            self.emit_physical_record(RecordType::calc(is_begin, is_end), &rest_data[..cur_len])
                 .await?;
-            self.block_offset += cur_len;
+            self.block_offset += HEADER_SIZE + cur_len;
             is_begin = false;
 
-            rest_data = data.get(cur_len..);
-            if data.len() == cur_len {
+            rest_data = &rest_data[cur_len..];
+            if is_end {
                 break;
             }
         }
@@ -117,22 +117,20 @@ impl WalFileWriter {
         Ok(())
     }
 
-    pub async fn new(vfile: VFile) -> Result<Self> {
-        let block_offset = vfile.len().await? % BLOCK_SIZE;
-        Ok(WalFileWriter {
-            file: vfile,
-            block_offset,
-        })
+    pub fn new(file: Box<dyn WritableFile>) -> Self {
+        let block_offset = (file.size() % BLOCK_SIZE as u64) as usize;
+        WalFileWriter { file, block_offset }
+    }
+
+    /// sync written records to disk
+    pub async fn sync(&mut self) -> Result<()> {
+        self.file.sync().await?;
+        Ok(())
     }
 
     async fn emit_physical_record(&mut self, ty: RecordType, rec: &[u8]) -> Result<()> {
-        use crc::crc32::Hasher32;
-        let mut digest = crc::crc32::Digest::new(crc::crc32::CASTAGNOLI);
-        digest.write(&[ty as u8]);
-        digest.write(rec);
-        let checksum = digest.sum32();
         let mut data = BytesMut::new();
-        data.put_u32_le(checksum);
+        data.put_u32_le(record_crc(ty as u8, rec));
         data.put_u16_le(rec.len() as u16);
         data.put_u8(ty as u8);
         data.put_slice(rec);
@@ -144,28 +142,47 @@ impl WalFileWriter {
 
 /// represent WAL reader
 pub struct WalFileReader {
-    file: VFile,
+    file:         Box<dyn RandomAccessFile>,
+    // offset of the next read in file
+    offset:       u64,
+    block_offset: usize,
+    // fail on a corrupted record instead of dropping it along with the rest of file
+    paranoid:     bool,
+    // set once a corrupted record is dropped
+    stopped:      bool,
 }
 
 impl WalFileReader {
-    pub async fn new(file: VFile) -> Result<Self> {
-        Ok(WalFileReader { file })
+    pub fn new(file: Box<dyn RandomAccessFile>, paranoid: bool) -> Self {
+        WalFileReader {
+            file,
+            offset: 0,
+            block_offset: 0,
+            paranoid,
+            stopped: false,
+        }
     }
 
-    pub async fn open(vfs: Vfs) -> Result<Self> {
-        let vfile = vfs.open("wal.log").await?;
-        let wal = WalFileReader::new(vfile).await?;
-        Ok(wal)
+    pub async fn open(vfs: Vfs, number: u64, paranoid: bool) -> Result<Self> {
+        let file = vfs.open(log_file_name(number)).await?;
+        Ok(WalFileReader::new(file, paranoid))
     }
 
     async fn read_record(&mut self) -> Result<Option<Record>> {
-        let mut buf = vec![0u8; 4 + 2 + 1]; // chucksum(u32) + length(u16) + type(u8), little endian
-        let _read = match self.file.read_exact(&mut buf).await {
-            Err(VfsError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
-                return Ok(None)
+        let left_over = BLOCK_SIZE - self.block_offset;
+        if left_over < HEADER_SIZE {
+            // skip the zeroed block trailer
+            let mut trailer = vec![0u8; left_over];
+            if !self.read_or_eof(&mut trailer).await? {
+                return Ok(None);
             }
-            result @ _ => result?,
-        };
+            self.block_offset = 0;
+        }
+
+        let mut buf = vec![0u8; HEADER_SIZE]; // chucksum(u32) + length(u16) + type(u8), little endian
+        if !self.read_or_eof(&mut buf).await? {
+            return Ok(None);
+        }
         let mut crc = [0u8; 4];
         crc.clone_from_slice(&buf[0..4]);
         let crc = u32::from_le_bytes(crc);
@@ -176,28 +193,57 @@ impl WalFileReader {
 
         let ty = buf[6];
         let mut data = vec![0u8; len as usize];
-        self.file.read_exact(&mut data).await?;
+        if !self.read_or_eof(&mut data).await? {
+            // a partially written record at the tail of the file
+            return Ok(None);
+        }
+        self.block_offset += HEADER_SIZE + len as usize;
+        if record_crc(ty, &data) != crc {
+            return Err(WalError::ChecksumMismatchError);
+        }
         let record = Record {
-            crc,
-            len,
-            ty: RecordType::from_u8(ty)?,
+            ty:   RecordType::from_u8(ty)?,
             data: data.into(),
         };
-        if !record.is_valid() {
-            return Err(WalError::InvalidRecordTypeError);
-        }
 
         Ok(Some(record))
     }
 
-    fn into_record_stream(self) -> impl Stream<Item = Result<Record>> {
-        futures::stream::unfold(self, |mut reader| async move {
-            let record = reader.read_record().await.transpose()?;
-            Some((record, reader))
-        })
+    /// fill `buf` from the file, return `false` if the file ends before that
+    async fn read_or_eof(&mut self, buf: &mut [u8]) -> Result<bool> {
+        match self.file.read_at(self.offset, buf.len()).await {
+            Ok(data) => {
+                buf.copy_from_slice(&data);
+                self.offset += buf.len() as u64;
+                Ok(true)
+            }
+            Err(VfsError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
     }
 
+    /// read the next logical record, `None` means the end of file
+    ///
+    /// Unless paranoid, a corrupted record ends the file, since it's most likely torn by a crash
+    /// in the middle of writing the tail.
     pub async fn read_data(&mut self) -> Result<Option<Bytes>> {
+        if self.stopped {
+            return Ok(None);
+        }
+        match self.read_fragments().await {
+            Err(WalError::ChecksumMismatchError | WalError::InvalidRecordTypeError)
+                if !self.paranoid =>
+            {
+                self.stopped = true;
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    async fn read_fragments(&mut self) -> Result<Option<Bytes>> {
         let first = self.read_record().await?;
         let first = match first {
             Some(first) => first,
@@ -209,7 +255,11 @@ impl WalFileReader {
             _ => return Err(WalError::InvalidRecordTypeError),
         }
         let mut result = vec![first];
-        result.extend(read_rest_records(self).await?);
+        match read_rest_records(self).await? {
+            Some(rest) => result.extend(rest),
+            // the writer crashed in the middle of this record, it was never acknowledged
+            None => return Ok(None),
+        }
         Ok(Some(
             result
                 .into_iter()
@@ -229,13 +279,15 @@ impl WalFileReader {
     }
 }
 
-async fn read_rest_records(reader: &mut WalFileReader) -> Result<Vec<Record>> {
+/// read fragments following a [`RecordType::First`] record, return `None` if file ends before
+/// [`RecordType::Last`]
+async fn read_rest_records(reader: &mut WalFileReader) -> Result<Option<Vec<Record>>> {
     let mut result = vec![];
     loop {
         let record = reader.read_record().await?;
         let record = match record {
             Some(record) => record,
-            None => return Err(WalError::InvalidWalFileError),
+            None => return Ok(None),
         };
         match record.ty {
             RecordType::Middle => result.push(record),
@@ -246,7 +298,7 @@ async fn read_rest_records(reader: &mut WalFileReader) -> Result<Vec<Record>> {
             _ => return Err(WalError::InvalidRecordTypeError),
         }
     }
-    Ok(result)
+    Ok(Some(result))
 }
 
 #[repr(u8)]
@@ -281,26 +333,25 @@ impl RecordType {
 }
 
 pub struct Record {
-    pub crc:  u32,
-    pub len:  u16,
     pub ty:   RecordType,
     pub data: Bytes,
 }
 
-impl Record {
-    fn is_valid(&self) -> bool {
-        use crc::crc32::Hasher32;
-        let mut digest = crc::crc32::Digest::new(crc::crc32::CASTAGNOLI);
-        digest.write(&[self.ty as u8]);
-        digest.write(&self.data);
-        let crc = digest.sum32();
-        crc == self.crc
-    }
+/// crc32c of record type and payload
+fn record_crc(ty: u8, data: &[u8]) -> u32 {
+    use crc::crc32::Hasher32;
+    let mut digest = crc::crc32::Digest::new(crc::crc32::CASTAGNOLI);
+    digest.write(&[ty]);
+    digest.write(data);
+    digest.sum32()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use super::*;
+    use crate::vfs::TokioFileSystem;
 
     fn gen_data(bytes: usize) -> Bytes {
         let mut data = BytesMut::new();
@@ -316,9 +367,9 @@ mod tests {
 
     async fn setup_reader_writer() -> Result<(WalFileReader, WalFileWriter)> {
         let dir = tempfile::tempdir().unwrap().into_path();
-        let vfs = Vfs::new(dir).await?;
-        let writer = WalFileWriter::open(vfs.clone()).await.unwrap();
-        let reader = WalFileReader::open(vfs).await.unwrap();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir).await?;
+        let writer = WalFileWriter::open(vfs.clone(), 1).await.unwrap();
+        let reader = WalFileReader::open(vfs, 1, false).await.unwrap();
         Ok((reader, writer))
     }
 
@@ -333,7 +384,7 @@ mod tests {
             .read_data()
             .await?
             .as_ref()
-            .map(|data| String::from_utf8_lossy(&*data).to_string())
+            .map(|data| String::from_utf8_lossy(data).to_string())
             .unwrap_or_else(|| "EOF".to_string());
         Ok(data)
     }
@@ -386,4 +437,77 @@ mod tests {
         }
         assert!(reader.read_data().await.unwrap().is_none());
     }
+
+    #[tokio::test]
+    async fn test_wal_block_trailer() {
+        let (mut reader, mut writer) = setup_reader_writer().await.unwrap();
+        // leave a trailer shorter than a header at the end of the first block
+        let first_len = BLOCK_SIZE - HEADER_SIZE - 3;
+        writer.write_data(gen_data(first_len)).await.unwrap();
+        writer.write_data(gen_data(100)).await.unwrap();
+        write(&mut writer, "tail").await.unwrap();
+
+        let data = reader.read_data().await.unwrap().unwrap();
+        assert_eq!(data.len(), first_len);
+        assert!(validate_data(&data));
+        let data = reader.read_data().await.unwrap().unwrap();
+        assert_eq!(data.len(), 100);
+        assert!(validate_data(&data));
+        assert_eq!(read(&mut reader).await.unwrap(), "tail");
+        assert_eq!(read(&mut reader).await.unwrap(), "EOF");
+    }
+
+    #[tokio::test]
+    async fn test_wal_reopen_writer() {
+        let dir = tempfile::tempdir().unwrap().into_path();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir).await.unwrap();
+        let mut writer = WalFileWriter::open(vfs.clone(), 1).await.unwrap();
+        writer
+            .write_data(gen_data(BLOCK_SIZE - 2 * HEADER_SIZE - 1))
+            .await
+            .unwrap();
+        drop(writer);
+
+        let mut writer = WalFileWriter::open(vfs.clone(), 1).await.unwrap();
+        write(&mut writer, "after reopen").await.unwrap();
+
+        let mut reader = WalFileReader::open(vfs, 1, false).await.unwrap();
+        let data = reader.read_data().await.unwrap().unwrap();
+        assert!(validate_data(&data));
+        assert_eq!(read(&mut reader).await.unwrap(), "after reopen");
+        assert_eq!(read(&mut reader).await.unwrap(), "EOF");
+    }
+
+    #[tokio::test]
+    async fn test_wal_corrupted_tail() {
+        let dir = tempfile::tempdir().unwrap().into_path();
+        let vfs = Vfs::new(Arc::new(TokioFileSystem), dir.clone())
+            .await
+            .unwrap();
+        let mut writer = WalFileWriter::open(vfs.clone(), 1).await.unwrap();
+        write(&mut writer, "foo").await.unwrap();
+        write(&mut writer, "bar").await.unwrap();
+        drop(writer);
+
+        // flip a byte of payload, then of type of the last record
+        let path = dir.join(log_file_name(1));
+        let data = std::fs::read(&path).unwrap();
+        for offset in [data.len() - 1, data.len() - 4] {
+            let mut data = data.clone();
+            data[offset] ^= 1;
+            std::fs::write(&path, data).unwrap();
+
+            let mut reader = WalFileReader::open(vfs.clone(), 1, false).await.unwrap();
+            assert_eq!(read(&mut reader).await.unwrap(), "foo");
+            assert_eq!(read(&mut reader).await.unwrap(), "EOF");
+            assert_eq!(read(&mut reader).await.unwrap(), "EOF");
+
+            let mut reader = WalFileReader::open(vfs.clone(), 1, true).await.unwrap();
+            assert_eq!(read(&mut reader).await.unwrap(), "foo");
+            assert!(matches!(
+                reader.read_data().await,
+                Err(WalError::ChecksumMismatchError)
+            ));
+        }
+    }
 }
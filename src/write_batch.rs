@@ -0,0 +1,207 @@
+//! atomic batch of updates
+
+use bytes::{
+    Buf,
+    BufMut,
+    Bytes,
+    BytesMut,
+};
+
+use crate::{
+    encoding::{
+        BufMutExt,
+        BytesExt,
+    },
+    format::{
+        SequenceNumber,
+        ValueType,
+    },
+};
+
+// Header is starting sequence (8 bytes), count (4 bytes).
+const HEADER_SIZE: usize = 8 + 4;
+
+/// a single update in [`WriteBatch`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BatchOp {
+    Put { key: Bytes, value: Bytes },
+    Delete { key: Bytes },
+}
+
+impl BatchOp {
+    /// key updated by this op
+    pub fn key(&self) -> &Bytes {
+        match self {
+            BatchOp::Put { key, .. } | BatchOp::Delete { key } => key,
+        }
+    }
+
+    /// type of entry this op produces
+    pub(crate) fn value_type(&self) -> ValueType {
+        match self {
+            BatchOp::Put { .. } => ValueType::Value,
+            BatchOp::Delete { .. } => ValueType::Deletion,
+        }
+    }
+
+    /// value of this op, empty for deletions
+    pub(crate) fn value(&self) -> Bytes {
+        match self {
+            BatchOp::Put { value, .. } => value.clone(),
+            BatchOp::Delete { .. } => Bytes::new(),
+        }
+    }
+}
+
+/// a group of updates applied to db atomically
+///
+/// Ops are applied in the order they were added, so a later op on the same key wins.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteBatch {
+    ops: Vec<BatchOp>,
+}
+
+impl WriteBatch {
+    /// create an empty [`WriteBatch`]
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    /// store key value pair
+    pub fn put(&mut self, key: Bytes, value: Bytes) {
+        self.ops.push(BatchOp::Put { key, value });
+    }
+
+    /// delete key
+    pub fn delete(&mut self, key: Bytes) {
+        self.ops.push(BatchOp::Delete { key });
+    }
+
+    /// drop all ops in batch
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    /// number of ops in batch
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// check if batch has no op
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// iterate over ops in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = &BatchOp> {
+        self.ops.iter()
+    }
+
+    /// encode batch as starting sequence (u64) and count (u32) followed by ops of type (u8), key
+    /// and optional value
+    pub(crate) fn encode(&self, seq: SequenceNumber) -> Bytes {
+        let mut data = BytesMut::new();
+        data.put_u64_le(seq);
+        data.put_u32_le(self.ops.len() as u32);
+        for op in &self.ops {
+            data.put_u8(op.value_type() as u8);
+            data.put_length_prefixed_slice(op.key());
+            if let BatchOp::Put { value, .. } = op {
+                data.put_length_prefixed_slice(value);
+            }
+        }
+        data.freeze()
+    }
+
+    /// decode a batch and its starting sequence produced by [`WriteBatch::encode`], return `None`
+    /// if data is malformed
+    pub(crate) fn decode(mut data: Bytes) -> Option<(SequenceNumber, Self)> {
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+        let seq = data.get_u64_le();
+        let count = data.get_u32_le() as usize;
+        let mut ops = Vec::with_capacity(count.min(data.len()));
+        for _ in 0..count {
+            if data.is_empty() {
+                return None;
+            }
+            let ty = ValueType::from_u8(data.get_u8())?;
+            let key = data.get_length_prefixed_slice()?;
+            let op = match ty {
+                ValueType::Value => BatchOp::Put {
+                    key,
+                    value: data.get_length_prefixed_slice()?,
+                },
+                ValueType::Deletion => BatchOp::Delete { key },
+            };
+            ops.push(op);
+        }
+        if !data.is_empty() {
+            return None;
+        }
+        Some((seq, WriteBatch { ops }))
+    }
+}
+
+impl<'a> IntoIterator for &'a WriteBatch {
+    type IntoIter = std::slice::Iter<'a, BatchOp>;
+    type Item = &'a BatchOp;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ops.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_batch_encode_decode() {
+        let mut batch = WriteBatch::new();
+        assert_eq!(
+            WriteBatch::decode(batch.encode(1)),
+            Some((1, batch.clone()))
+        );
+
+        batch.put("key1".into(), "val1".into());
+        batch.delete("key2".into());
+        batch.put("key3".into(), Bytes::new());
+        assert_eq!(batch.len(), 3);
+        let (seq, decoded) = WriteBatch::decode(batch.encode(1 << 40)).unwrap();
+        assert_eq!(seq, 1 << 40);
+        assert_eq!(decoded, batch);
+        assert_eq!(
+            decoded.iter().collect::<Vec<_>>(),
+            vec![
+                &BatchOp::Put {
+                    key:   "key1".into(),
+                    value: "val1".into(),
+                },
+                &BatchOp::Delete { key: "key2".into() },
+                &BatchOp::Put {
+                    key:   "key3".into(),
+                    value: Bytes::new(),
+                },
+            ]
+        );
+
+        batch.clear();
+        assert!(batch.is_empty());
+    }
+
+    #[test]
+    fn test_write_batch_decode_malformed() {
+        let mut batch = WriteBatch::new();
+        batch.put("key1".into(), "val1".into());
+        batch.delete("key2".into());
+        let data = batch.encode(7);
+        for len in 0..data.len() {
+            assert!(WriteBatch::decode(data.slice(..len)).is_none());
+        }
+        let mut data = BytesMut::from(&data[..]);
+        data.put_u8(0);
+        assert!(WriteBatch::decode(data.freeze()).is_none());
+    }
+}